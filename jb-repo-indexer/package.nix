@@ -18,6 +18,7 @@
 
   src = ./.;
 
-  useFetchCargoVendor = true;
-  cargoHash = "sha256-q15Itz2lc3c55J441GGGCtpxmgK222HTFRPiAbf0v3M=";
+  # The lock file pins the checksum of every crate, so there is no vendor
+  # hash to keep in sync whenever a dependency changes
+  cargoLock.lockFile = ./Cargo.lock;
 }
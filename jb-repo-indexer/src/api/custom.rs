@@ -0,0 +1,254 @@
+use crate::api::{RepoPluginDetails, RepoPluginUrls, RepoUpdateMetadata, RepoUpdateVersion, RepoVendor};
+use crate::error::IndexerError;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use sha2::Digest as _;
+use std::collections::{HashMap, HashSet};
+
+/// Ids are kept below 2^53, so they survive a round trip through JSON
+/// numbers and fit into SQLite integers.
+const SYNTHETIC_ID_MASK: u64 = (1 << 53) - 1;
+
+/// A custom plugin repository described by a classic `updatePlugins.xml`.
+///
+/// Custom repositories have no numeric ids, so stable ones are derived from
+/// the plugin id and version.
+#[derive(Debug)]
+pub(super) struct CustomRepository {
+    plugins: HashMap<String, Vec<CustomPluginVersion>>,
+}
+
+#[derive(Debug, Default)]
+struct CustomPluginVersion {
+    update_id: u64,
+    version: String,
+    url: String,
+    name: Option<String>,
+    description: Option<String>,
+    change_notes: Option<String>,
+    vendor: Option<RepoVendor>,
+    since: Option<String>,
+    until: Option<String>,
+    dependencies: Vec<String>,
+    optional_dependencies: Vec<String>,
+}
+
+/// The element whose text is currently being read.
+enum Field {
+    Name,
+    Description,
+    ChangeNotes,
+    Vendor,
+    Depends { optional: bool },
+}
+
+impl CustomRepository {
+    pub fn parse(data: &[u8]) -> Result<Self, IndexerError> {
+        let mut reader = Reader::from_reader(data);
+        let mut buffer = Vec::new();
+
+        let mut plugins = HashMap::<String, Vec<CustomPluginVersion>>::new();
+        let mut current: Option<(String, CustomPluginVersion)> = None;
+        let mut field = None;
+
+        loop {
+            match reader.read_event_into(&mut buffer)? {
+                Event::Start(e) if e.name().as_ref() == b"plugin" => {
+                    current = Some(plugin_entry(&e)?);
+                }
+                Event::Empty(e) if e.name().as_ref() == b"plugin" => {
+                    let (xml_id, version) = plugin_entry(&e)?;
+                    plugins.entry(xml_id).or_default().push(version);
+                }
+                Event::End(e) if e.name().as_ref() == b"plugin" => {
+                    if let Some((xml_id, version)) = current.take() {
+                        plugins.entry(xml_id).or_default().push(version);
+                    }
+                }
+                Event::Start(e) | Event::Empty(e) if current.is_some() => {
+                    let (_, version) = current.as_mut().unwrap();
+
+                    field = match e.name().as_ref() {
+                        b"idea-version" => {
+                            version.since = attribute(&e, b"since-build")?;
+                            version.until = attribute(&e, b"until-build")?;
+                            None
+                        }
+                        b"vendor" => {
+                            version.vendor = Some(RepoVendor {
+                                name: None,
+                                url: attribute(&e, b"url")?,
+                                email: attribute(&e, b"email")?,
+                            });
+                            Some(Field::Vendor)
+                        }
+                        b"name" => Some(Field::Name),
+                        b"description" => Some(Field::Description),
+                        b"change-notes" => Some(Field::ChangeNotes),
+                        b"depends" => Some(Field::Depends {
+                            optional: attribute(&e, b"optional")?.as_deref() == Some("true"),
+                        }),
+                        _ => None,
+                    };
+                }
+                Event::Text(text) => {
+                    if let (Some(field), Some((_, version))) = (&field, current.as_mut()) {
+                        let text = text.unescape()?.trim().to_owned();
+                        push_text(version, field, text);
+                    }
+                }
+                Event::CData(text) => {
+                    if let (Some(field), Some((_, version))) = (&field, current.as_mut()) {
+                        let text = String::from_utf8_lossy(&text).trim().to_owned();
+                        push_text(version, field, text);
+                    }
+                }
+                Event::End(_) => field = None,
+                Event::Eof => break,
+                _ => {}
+            }
+
+            buffer.clear();
+        }
+
+        Ok(Self { plugins })
+    }
+
+    pub fn xml_ids(&self) -> HashSet<String> {
+        self.plugins.keys().cloned().collect()
+    }
+
+    pub fn plugin_details(&self, xml_id: &str) -> Result<RepoPluginDetails, IndexerError> {
+        let versions = self.plugins.get(xml_id).ok_or(IndexerError::NotFound)?;
+
+        // Entries are usually listed oldest first, the last describes the plugin best
+        let latest = versions.last();
+
+        Ok(RepoPluginDetails {
+            id: synthetic_id(&[xml_id]),
+            name: latest.and_then(|v| v.name.clone()),
+            preview: latest.and_then(|v| v.description.clone()),
+            vendor: latest.and_then(|v| v.vendor.clone()),
+            link: None,
+            downloads: None,
+            rating: None,
+            pricing_model: None,
+            purchase_info: None,
+            urls: RepoPluginUrls::default(),
+            screens: Vec::new(),
+            tags: Vec::new(),
+            family: None,
+        })
+    }
+
+    pub fn plugin_versions(&self, plugin_id: u64) -> Vec<RepoUpdateVersion> {
+        self.plugin(plugin_id)
+            .into_iter()
+            .flatten()
+            .map(|v| RepoUpdateVersion {
+                id: v.update_id,
+                version: v.version.clone(),
+                channel: String::new(),
+            })
+            .collect()
+    }
+
+    pub fn update_metadata(
+        &self,
+        plugin_id: u64,
+        update_id: u64,
+    ) -> Result<RepoUpdateMetadata, IndexerError> {
+        let version = self
+            .plugin(plugin_id)
+            .into_iter()
+            .flatten()
+            .find(|v| v.update_id == update_id)
+            .ok_or(IndexerError::NotFound)?;
+
+        Ok(RepoUpdateMetadata {
+            dependencies: version.dependencies.clone(),
+            optional_dependencies: version.optional_dependencies.clone(),
+            since: version.since.clone(),
+            until: version.until.clone(),
+            notes: version.change_notes.clone(),
+            cdate: None,
+        })
+    }
+
+    /// The download URL of an update as listed, possibly relative.
+    pub fn download_url(&self, update_id: u64) -> Result<&str, IndexerError> {
+        self.plugins
+            .values()
+            .flatten()
+            .find(|v| v.update_id == update_id)
+            .map(|v| v.url.as_str())
+            .ok_or(IndexerError::NotFound)
+    }
+
+    fn plugin(&self, plugin_id: u64) -> Option<&Vec<CustomPluginVersion>> {
+        self.plugins
+            .iter()
+            .find(|(xml_id, _)| synthetic_id(&[xml_id]) == plugin_id)
+            .map(|(_, versions)| versions)
+    }
+}
+
+fn plugin_entry(element: &BytesStart) -> Result<(String, CustomPluginVersion), IndexerError> {
+    let missing = |name: &str| IndexerError::IncompleteData(format!("plugin entry without {}", name));
+
+    let xml_id = attribute(element, b"id")?.ok_or_else(|| missing("id"))?;
+    let url = attribute(element, b"url")?.ok_or_else(|| missing("url"))?;
+    let version = attribute(element, b"version")?.ok_or_else(|| missing("version"))?;
+
+    Ok((
+        xml_id.clone(),
+        CustomPluginVersion {
+            update_id: synthetic_id(&[&xml_id, &version, &url]),
+            version,
+            url,
+            ..Default::default()
+        },
+    ))
+}
+
+fn push_text(version: &mut CustomPluginVersion, field: &Field, text: String) {
+    if text.is_empty() {
+        return;
+    }
+
+    match field {
+        Field::Name => version.name = Some(text),
+        Field::Description => version.description = Some(text),
+        Field::ChangeNotes => version.change_notes = Some(text),
+        Field::Vendor => {
+            if let Some(vendor) = &mut version.vendor {
+                vendor.name = Some(text);
+            }
+        }
+        Field::Depends { optional: false } => version.dependencies.push(text),
+        Field::Depends { optional: true } => version.optional_dependencies.push(text),
+    }
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>, IndexerError> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+
+        if attribute.key.as_ref() == name {
+            return Ok(Some(attribute.unescape_value()?.trim().to_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn synthetic_id(parts: &[&str]) -> u64 {
+    let mut hasher = sha2::Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+
+    let digest = hasher.finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap()) & SYNTHETIC_ID_MASK
+}
@@ -1,16 +1,49 @@
+mod custom;
 mod models;
+mod retry;
+mod stats;
+mod throttle;
 pub use models::*;
-
-use crate::args::IndexerArgs;
+pub use stats::*;
+
+use crate::args::{ApiArgs, RepositoryKind};
+use crate::api::custom::CustomRepository;
+use crate::api::retry::{RateLimitGate, RetryPolicy};
+use crate::api::throttle::DownloadThrottle;
+use crate::chaos::{Chaos, ChaosConfig};
+use crate::artifacts::{ArtifactCache, PendingArtifact};
 use crate::error::IndexerError;
 use base64::Engine as _;
 use base64::prelude::BASE64_STANDARD;
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, HeaderValue};
 use reqwest::redirect::Policy;
-use reqwest::{Client, StatusCode, Url};
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response, StatusCode, Url};
 use sha2::Digest as _;
 use std::collections::HashSet;
-use std::sync::Arc;
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    /// When the current sync task first got a permit to send a request.
+    static TASK_STARTED: Arc<OnceLock<Instant>>;
+}
+
+/// Run a task, recording in `started` when it first got a permit to send
+/// a request.
+pub fn track_task_start<F: Future>(
+    started: Arc<OnceLock<Instant>>,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    TASK_STARTED.scope(started, future)
+}
+
+fn record_task_start() {
+    let _ = TASK_STARTED.try_with(|v| {
+        v.get_or_init(Instant::now);
+    });
+}
 
 #[derive(Debug, Clone)]
 pub struct JetbrainsRepoApi {
@@ -18,20 +51,55 @@ pub struct JetbrainsRepoApi {
     small_request_semaphore: Arc<Semaphore>,
     large_request_semaphore: Arc<Semaphore>,
     base: Url,
+    statistics: Arc<HttpStatistics>,
+    artifact_cache: Option<ArtifactCache>,
+    chaos: Option<Arc<Chaos>>,
+    retry_policy: RetryPolicy,
+    rate_limit: Arc<RateLimitGate>,
+    custom: Option<Arc<OnceCell<CustomRepository>>>,
+    authorization: Option<HeaderValue>,
+    download_throttle: Option<Arc<DownloadThrottle>>,
+    shutdown: CancellationToken,
+}
+
+/// An HTTP client with the configured proxy, certificates and timeouts.
+pub fn http_client(args: &ApiArgs) -> Result<Client, IndexerError> {
+    let mut client = Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .redirect(Policy::limited(10))
+        .hickory_dns(true)
+        .connect_timeout(Duration::from_millis(args.connect_timeout_ms))
+        .read_timeout(Duration::from_millis(args.read_timeout_ms));
+
+    if let Some(max) = args.pool_max_idle_per_host {
+        client = client.pool_max_idle_per_host(max);
+    }
+
+    if args.http1_only {
+        client = client.http1_only();
+    }
+
+    if let Some(proxy) = &args.http_proxy {
+        client = client.proxy(Proxy::all(proxy.clone())?);
+    }
+
+    if let Some(path) = &args.ca_bundle {
+        for certificate in Certificate::from_pem_bundle(&std::fs::read(path)?)? {
+            client = client.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(client.build()?)
 }
 
 impl JetbrainsRepoApi {
     /// Prepare the API client.
-    pub fn new(args: &IndexerArgs) -> Result<Self, IndexerError> {
-        let client = Client::builder()
-            .user_agent(concat!(
-                env!("CARGO_PKG_NAME"),
-                "/",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .redirect(Policy::limited(10))
-            .hickory_dns(true)
-            .build()?;
+    pub fn new(args: &ApiArgs, chaos: Option<ChaosConfig>) -> Result<Self, IndexerError> {
+        let client = http_client(args)?;
 
         let small_request_semaphore =
             Arc::new(Semaphore::new(args.max_parallel_small_requests.get()));
@@ -39,28 +107,55 @@ impl JetbrainsRepoApi {
         let large_request_semaphore =
             Arc::new(Semaphore::new(args.max_parallel_large_requests.get()));
 
-        let base = Url::parse("https://plugins.jetbrains.com/").unwrap();
+        let authorization = match &args.marketplace_token {
+            Some(token) => {
+                let mut value = HeaderValue::try_from(format!("Bearer {}", token.trim()))
+                    .map_err(|_| IndexerError::IncompleteData("invalid marketplace token".to_owned()))?;
+                value.set_sensitive(true);
+
+                Some(value)
+            }
+            None => None,
+        };
+
+        let custom = match args.repository_kind {
+            RepositoryKind::Marketplace => None,
+            RepositoryKind::Custom => Some(Arc::new(OnceCell::new())),
+        };
 
         Ok(Self {
             client,
             small_request_semaphore,
             large_request_semaphore,
-            base,
+            base: args.repository_url.clone(),
+            statistics: Arc::new(HttpStatistics::default()),
+            artifact_cache: args.artifact_cache.as_ref().map(ArtifactCache::new),
+            chaos: chaos.map(|v| Arc::new(Chaos::new(v))),
+            retry_policy: RetryPolicy::new(args),
+            rate_limit: Arc::new(RateLimitGate::default()),
+            custom,
+            authorization,
+            download_throttle: args
+                .max_download_rate
+                .map(|v| Arc::new(DownloadThrottle::new(v))),
+            shutdown: CancellationToken::new(),
         })
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn fetch_all_xml_ids(&self) -> Result<HashSet<String>, IndexerError> {
+        if let Some(custom) = self.custom_repository().await? {
+            return Ok(custom.xml_ids());
+        }
+
         let permit = self.acquire_small_permit().await;
 
         let response = self
-            .client
-            .get(self.path(["files", "pluginsXMLIds.json"]))
-            .send()
+            .send(self.client.get(self.path(["files", "pluginsXMLIds.json"])))
             .await?
             .error_for_status()?;
 
-        let data = response.bytes().await?;
+        let data = self.read_bytes(response).await?;
         drop(permit);
 
         serde_json::from_slice(&data).map_err(IndexerError::from)
@@ -71,38 +166,192 @@ impl JetbrainsRepoApi {
         &self,
         xml_id: &str,
     ) -> Result<RepoPluginDetails, IndexerError> {
+        if let Some(custom) = self.custom_repository().await? {
+            return custom.plugin_details(xml_id);
+        }
+
         let permit = self.acquire_small_permit().await;
 
         let response = self
-            .client
-            .get(self.path(["api", "plugins", "intellij", xml_id]))
-            .send()
+            .send(self.client.get(self.path(["api", "plugins", "intellij", xml_id])))
             .await?
             .error_for_status()?;
 
-        let data = response.bytes().await?;
+        let data = self.read_bytes(response).await?;
         drop(permit);
 
         serde_json::from_slice(&data).map_err(IndexerError::from)
     }
 
+    /// Fetch a page of plugins, the most recently updated first.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_recently_updated_plugins(
+        &self,
+        offset: usize,
+        max: usize,
+    ) -> Result<Vec<RepoSearchPlugin>, IndexerError> {
+        // Without update dates every plugin counts as recently updated
+        if let Some(custom) = self.custom_repository().await? {
+            let mut xml_ids = custom.xml_ids().into_iter().collect::<Vec<_>>();
+            xml_ids.sort();
+
+            return Ok(xml_ids
+                .into_iter()
+                .skip(offset)
+                .take(max)
+                .map(|xml_id| RepoSearchPlugin {
+                    xml_id,
+                    update_date: None,
+                })
+                .collect());
+        }
+
+        self.search_plugins(&[("orderBy", "update date")], offset, max)
+            .await
+    }
+
+    /// Fetch a page of plugins with a tag.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_plugins_with_tag(
+        &self,
+        tag: &str,
+        offset: usize,
+        max: usize,
+    ) -> Result<Vec<RepoSearchPlugin>, IndexerError> {
+        if self.custom.is_some() {
+            return Ok(Vec::new());
+        }
+
+        self.search_plugins(&[("tags", tag)], offset, max).await
+    }
+
+    /// Fetch a page of plugins of a family, optionally only those compatible
+    /// with an IDE product.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_plugins_in(
+        &self,
+        family: &str,
+        product: Option<&str>,
+        offset: usize,
+        max: usize,
+    ) -> Result<Vec<RepoSearchPlugin>, IndexerError> {
+        if self.custom.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let mut query = vec![("family", family)];
+        if let Some(product) = product {
+            query.push(("products", product));
+        }
+
+        self.search_plugins(&query, offset, max).await
+    }
+
+    async fn search_plugins(
+        &self,
+        query: &[(&str, &str)],
+        offset: usize,
+        max: usize,
+    ) -> Result<Vec<RepoSearchPlugin>, IndexerError> {
+        let permit = self.acquire_small_permit().await;
+
+        let response = self
+            .send(
+                self.client
+                    .get(self.path(["api", "searchPlugins"]))
+                    .query(query)
+                    .query(&[("offset", offset), ("max", max)]),
+            )
+            .await?
+            .error_for_status()?;
+
+        let data = self.read_bytes(response).await?;
+        drop(permit);
+
+        serde_json::from_slice::<RepoSearchResult>(&data)
+            .map(|v| v.plugins)
+            .map_err(IndexerError::from)
+    }
+
+    /// Fetch the codes of the IDE products a plugin is compatible with.
+    ///
+    /// Returns no products if the marketplace does not know the plugin.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_compatible_products(&self, plugin_id: u64) -> Result<Vec<String>, IndexerError> {
+        if self.custom.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let permit = self.acquire_small_permit().await;
+
+        let plugin_id_str = plugin_id.to_string();
+
+        let response = self
+            .send(self.client.get(self.path([
+                "api",
+                "plugins",
+                &plugin_id_str,
+                "compatible-products",
+            ])))
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let data = self.read_bytes(response.error_for_status()?).await?;
+        drop(permit);
+
+        serde_json::from_slice(&data).map_err(IndexerError::from)
+    }
+
+    /// Fetch licensing details of a paid plugin from the products endpoint.
+    ///
+    /// Returns `None` if the marketplace does not know the product.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_product(&self, product_code: &str) -> Result<Option<RepoProduct>, IndexerError> {
+        if self.custom.is_some() {
+            return Ok(None);
+        }
+
+        let permit = self.acquire_small_permit().await;
+
+        let response = self
+            .send(self.client.get(self.path(["api", "products", product_code])))
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let data = self.read_bytes(response.error_for_status()?).await?;
+        drop(permit);
+
+        serde_json::from_slice(&data).map(Some).map_err(IndexerError::from)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn fetch_plugin_versions(
         &self,
         plugin_id: u64,
     ) -> Result<Vec<RepoUpdateVersion>, IndexerError> {
+        if let Some(custom) = self.custom_repository().await? {
+            return Ok(custom.plugin_versions(plugin_id));
+        }
+
         let permit = self.acquire_small_permit().await;
 
         let plugin_id_str = plugin_id.to_string();
 
         let response = self
-            .client
-            .get(self.path(["api", "plugins", &plugin_id_str, "updateVersions"]))
-            .send()
+            .send(
+                self.client
+                    .get(self.path(["api", "plugins", &plugin_id_str, "updateVersions"])),
+            )
             .await?
             .error_for_status()?;
 
-        let data = response.bytes().await?;
+        let data = self.read_bytes(response).await?;
         drop(permit);
 
         serde_json::from_slice(&data).map_err(IndexerError::from)
@@ -114,19 +363,59 @@ impl JetbrainsRepoApi {
         plugin_id: u64,
         update_id: u64,
     ) -> Result<RepoUpdateMetadata, IndexerError> {
+        if let Some(custom) = self.custom_repository().await? {
+            return custom.update_metadata(plugin_id, update_id);
+        }
+
         let permit = self.acquire_small_permit().await;
 
         let plugin_id_str = plugin_id.to_string();
         let update_id_str = update_id.to_string();
 
         let response = self
-            .client
-            .get(self.path(["files", &plugin_id_str, &update_id_str, "meta.json"]))
-            .send()
+            .send(
+                self.client
+                    .get(self.path(["files", &plugin_id_str, &update_id_str, "meta.json"])),
+            )
             .await?
             .error_for_status()?;
 
-        let data = response.bytes().await?;
+        let data = self.read_bytes(response).await?;
+        drop(permit);
+
+        serde_json::from_slice(&data).map_err(IndexerError::from)
+    }
+
+    /// Fetch the plugin verifier results for an update.
+    ///
+    /// Updates which have never been verified yield an empty list.
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch_update_verification(
+        &self,
+        update_id: u64,
+    ) -> Result<Vec<RepoVerificationResult>, IndexerError> {
+        if self.custom.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let permit = self.acquire_small_permit().await;
+
+        let update_id_str = update_id.to_string();
+
+        let response = self
+            .send(self.client.get(self.path([
+                "api",
+                "updates",
+                &update_id_str,
+                "verification-results",
+            ])))
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let data = self.read_bytes(response.error_for_status()?).await?;
         drop(permit);
 
         serde_json::from_slice(&data).map_err(IndexerError::from)
@@ -137,13 +426,32 @@ impl JetbrainsRepoApi {
         &self,
         update_id: u64,
     ) -> Result<RepoDownloadInfo, IndexerError> {
+        if let Some(custom) = self.custom_repository().await? {
+            let url = self.absolute_url(custom.download_url(update_id)?).map_err(|e| {
+                IndexerError::IncompleteData(format!("bad download url of update {}: {}", update_id, e))
+            })?;
+            let file_name = url
+                .path_segments()
+                .and_then(|mut v| v.next_back())
+                .filter(|v| !v.is_empty())
+                .map(ToOwned::to_owned);
+
+            return Ok(RepoDownloadInfo {
+                url,
+                etag: None,
+                file_name,
+                size: None,
+            });
+        }
+
         let permit = self.acquire_small_permit().await;
 
         let response = self
-            .client
-            .head(self.path(["plugin", "download"]))
-            .query(&[("updateId", update_id)])
-            .send()
+            .send(
+                self.client
+                    .head(self.path(["plugin", "download"]))
+                    .query(&[("updateId", update_id)]),
+            )
             .await?
             .error_for_status()?;
 
@@ -151,13 +459,7 @@ impl JetbrainsRepoApi {
 
         let url = response.url().clone();
 
-        let etag = response.headers().get("etag").and_then(|v| {
-            let v = v.to_str().ok()?.trim();
-
-            v.strip_prefix('"')?
-                .strip_suffix('"')
-                .map(ToOwned::to_owned)
-        });
+        let etag = response_etag(&response);
 
         let file_name = response.headers().get("content-disposition").and_then(|v| {
             let v = v.to_str().ok()?.trim();
@@ -167,28 +469,44 @@ impl JetbrainsRepoApi {
                 .map(ToOwned::to_owned)
         });
 
+        // The body of a HEAD response is empty, so the header has to be read
+        let size = response_content_length(&response);
+
         Ok(RepoDownloadInfo {
             url,
             etag,
             file_name,
+            size,
         })
     }
 
-    #[tracing::instrument(skip_all, fields(url = url.as_str()))]
-    pub async fn hash_download_url(&self, url: &Url) -> Result<RepoDownloadHash, IndexerError> {
+    /// Hash the artifact of resolved download info.
+    ///
+    /// If the artifact has to be downloaded, it must still be the one the
+    /// download info was resolved for, otherwise the download fails with
+    /// [`IndexerError::ArtifactChanged`].
+    #[tracing::instrument(skip_all, fields(url = info.url.as_str()))]
+    pub async fn hash_download_url(&self, info: &RepoDownloadInfo) -> Result<RepoDownloadHash, IndexerError> {
+        let url = &info.url;
+
         #[derive(serde::Deserialize)]
         struct DownloadHashData {
             algorithm: String,
             hash: String,
         }
 
+        // Custom repositories only serve the artifacts themselves
+        if self.custom.is_some() {
+            return self.fetch_and_hash(url, Some(info)).await;
+        }
+
         // First attempt: append .hash.json to the URL path
 
         let mut hash_url = url.clone();
         hash_url.set_path(&(url.path().to_owned() + ".hash.json"));
 
         let permit = self.acquire_small_permit().await;
-        let response = self.client.get(hash_url).send().await?;
+        let response = self.send(self.client.get(hash_url)).await?;
 
         let hash = if matches!(
             response.status(),
@@ -203,28 +521,9 @@ impl JetbrainsRepoApi {
                 response.status().as_str()
             );
 
-            let permit = self
-                .large_request_semaphore
-                .clone()
-                .acquire_owned()
-                .await
-                .unwrap();
-
-            let mut hasher = sha2::Sha256::new();
-
-            let mut response = self.client.get(url.clone()).send().await?.error_for_status()?;
-            while let Some(chunk) = response.chunk().await? {
-                hasher.update(&chunk);
-            }
-
-            drop(permit);
-
-            RepoDownloadHash {
-                algorithm: "SHA-256".to_owned(),
-                value: hasher.finalize().to_vec(),
-            }
+            self.fetch_and_hash(url, Some(info)).await?
         } else {
-            let data = response.bytes().await?;
+            let data = self.read_bytes(response).await?;
             drop(permit);
 
             let data: DownloadHashData =
@@ -234,12 +533,275 @@ impl JetbrainsRepoApi {
             RepoDownloadHash {
                 algorithm: data.algorithm,
                 value: decoded,
+                size: None,
+            }
+        };
+
+        Ok(hash)
+    }
+
+    /// Download an artifact and hash it ourselves, retaining it in the
+    /// artifact cache if enabled.
+    #[tracing::instrument(skip_all, fields(url = url.as_str()))]
+    pub async fn download_hash(&self, url: &Url) -> Result<RepoDownloadHash, IndexerError> {
+        self.fetch_and_hash(url, None).await
+    }
+
+    /// Download and hash an artifact, which must match the expected etag
+    /// and size if known.
+    async fn fetch_and_hash(
+        &self,
+        url: &Url,
+        expected: Option<&RepoDownloadInfo>,
+    ) -> Result<RepoDownloadHash, IndexerError> {
+        let permit = self
+            .large_request_semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+        record_task_start();
+
+        let response = self
+            .send(self.client.get(url.clone()))
+            .await?
+            .error_for_status()?;
+
+        if let Some(expected) = expected {
+            check_unchanged(expected.etag.as_deref(), response_etag(&response).as_deref(), "etag")?;
+            check_unchanged(expected.size, response_content_length(&response), "size")?;
+        }
+
+        let mut artifact = match &self.artifact_cache {
+            Some(cache) => Some(cache.begin().await?),
+            None => None,
+        };
+
+        let hash = self
+            .hash_response(response, artifact.as_mut())
+            .await
+            .and_then(|hash| match expected {
+                Some(expected) => {
+                    check_unchanged(expected.size, hash.size, "size")?;
+                    Ok(hash)
+                }
+                None => Ok(hash),
+            });
+
+        let hash = match hash {
+            Ok(v) => v,
+            Err(err) => {
+                if let Some(artifact) = artifact {
+                    artifact.discard().await;
+                }
+
+                return Err(err);
             }
         };
 
+        drop(permit);
+
+        if let (Some(cache), Some(artifact)) = (&self.artifact_cache, artifact) {
+            let path = cache.commit(artifact, &hash.algorithm, &hash.value).await?;
+            tracing::debug!("Retained artifact of {} at {}", url, path.display());
+        }
+
         Ok(hash)
     }
 
+    /// Hash a response body, optionally writing it to an artifact.
+    async fn hash_response(
+        &self,
+        mut response: Response,
+        mut artifact: Option<&mut PendingArtifact>,
+    ) -> Result<RepoDownloadHash, IndexerError> {
+        let mut hasher = sha2::Sha256::new();
+        let mut size = 0;
+
+        while let Some(chunk) = response.chunk().await? {
+            size += chunk.len() as u64;
+
+            if let Some(throttle) = &self.download_throttle {
+                throttle.consume(chunk.len()).await;
+            }
+
+            self.statistics.record_bytes(chunk.len());
+            hasher.update(&chunk);
+
+            if let Some(artifact) = artifact.as_mut() {
+                artifact.write(&chunk).await?;
+            }
+        }
+
+        Ok(RepoDownloadHash {
+            algorithm: "SHA-256".to_owned(),
+            value: hasher.finalize().to_vec(),
+            size: Some(size),
+        })
+    }
+
+    /// The cache downloaded artifacts are retained in, if enabled.
+    pub fn artifact_cache(&self) -> Option<&ArtifactCache> {
+        self.artifact_cache.as_ref()
+    }
+
+    /// Statistics about all requests made so far.
+    pub fn statistics(&self) -> HttpStatisticsSnapshot {
+        self.statistics.snapshot()
+    }
+
+    /// Refuse to send any more requests, requests already sent complete.
+    pub fn shut_down(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Wait until [`JetbrainsRepoApi::shut_down`] was called.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    /// Send a request, retrying transient failures.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, IndexerError> {
+        let mut retries = 0;
+
+        loop {
+            if self.shutdown.is_cancelled() {
+                return Err(IndexerError::ShuttingDown);
+            }
+
+            // Requests with streaming bodies can't be cloned, but we never send those
+            let Some(attempt) = request.try_clone() else {
+                return self.send_once(request).await;
+            };
+
+            let result = self.send_once(attempt).await;
+            if let Ok(response) = &result
+                && response.status() == StatusCode::TOO_MANY_REQUESTS
+            {
+                self.rate_limit.pause(response);
+            }
+            if !self.retry_policy.should_retry(retries, &result) {
+                return result;
+            }
+
+            retries += 1;
+            let delay = self.retry_policy.delay(retries);
+
+            match &result {
+                Ok(response) => tracing::debug!("Retrying after status {} in {:?}", response.status(), delay),
+                Err(err) => tracing::debug!("Retrying after error {} in {:?}", err, delay),
+            }
+
+            self.statistics.record_retry();
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = self.shutdown.cancelled() => {},
+            }
+        }
+    }
+
+    /// Send a request once and record it in the statistics.
+    async fn send_once(&self, request: RequestBuilder) -> Result<Response, IndexerError> {
+        self.rate_limit.wait().await;
+
+        let start = Instant::now();
+
+        if let Some(chaos) = &self.chaos {
+            if chaos.inject_timeout() {
+                self.statistics.record_failure(start.elapsed());
+                return Err(IndexerError::InjectedFault("request timed out"));
+            }
+
+            if chaos.inject_rate_limit() {
+                let response = http::Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header("retry-after", "1")
+                    .body(Vec::new())
+                    .unwrap();
+
+                self.statistics
+                    .record_response(response.status(), start.elapsed());
+                return Ok(response.into());
+            }
+        }
+
+        let mut request = request.build()?;
+
+        // Artifacts are served from other hosts, which must not see the token
+        if let Some(authorization) = &self.authorization
+            && request.url().host() == self.base.host()
+        {
+            request.headers_mut().insert(AUTHORIZATION, authorization.clone());
+        }
+
+        match self.client.execute(request).await {
+            Ok(response) => {
+                self.statistics
+                    .record_response(response.status(), start.elapsed());
+                Ok(response)
+            }
+            Err(err) => {
+                self.statistics.record_failure(start.elapsed());
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Read the full body of a response and record its size.
+    async fn read_bytes(&self, response: Response) -> Result<bytes::Bytes, IndexerError> {
+        let data = response.bytes().await?;
+        self.statistics.record_bytes(data.len());
+
+        if let Some(chaos) = &self.chaos
+            && chaos.inject_malformed_json()
+        {
+            return Ok(bytes::Bytes::from_static(b"{\"injected\":"));
+        }
+
+        Ok(data)
+    }
+
+    /// The custom repository, fetched on first use, if this is one.
+    async fn custom_repository(&self) -> Result<Option<&CustomRepository>, IndexerError> {
+        let Some(custom) = &self.custom else {
+            return Ok(None);
+        };
+
+        custom
+            .get_or_try_init(|| async {
+                let permit = self.acquire_small_permit().await;
+
+                let response = self
+                    .send(self.client.get(self.base.clone()))
+                    .await?
+                    .error_for_status()?;
+
+                let data = self.read_bytes(response).await?;
+                drop(permit);
+
+                CustomRepository::parse(&data)
+            })
+            .await
+            .map(Some)
+    }
+
+    /// Resolve a possibly relative URL returned by the marketplace.
+    pub fn absolute_url(&self, url: &str) -> Result<Url, url::ParseError> {
+        self.base.join(url)
+    }
+
+    /// The download URL of an update which redirects to its current
+    /// artifact, unknown for custom repositories.
+    pub fn stable_download_url(&self, update_id: u64) -> Option<Url> {
+        if self.custom.is_some() {
+            return None;
+        }
+
+        let mut url = self.path(["plugin", "download"]);
+        url.query_pairs_mut().append_pair("updateId", &update_id.to_string());
+        Some(url)
+    }
+
     fn path(&self, segments: impl IntoIterator<Item = impl AsRef<str>>) -> Url {
         let mut new_path = self.base.clone();
         new_path.path_segments_mut().unwrap().extend(segments);
@@ -249,10 +811,45 @@ impl JetbrainsRepoApi {
 
     #[tracing::instrument(skip(self))]
     async fn acquire_small_permit(&self) -> OwnedSemaphorePermit {
-        self.small_request_semaphore
+        let permit = self
+            .small_request_semaphore
             .clone()
             .acquire_owned()
             .await
-            .unwrap()
+            .unwrap();
+        record_task_start();
+
+        permit
+    }
+}
+
+/// The etag of a response without its quotes.
+fn response_etag(response: &Response) -> Option<String> {
+    let v = response.headers().get("etag")?.to_str().ok()?.trim();
+
+    v.strip_prefix('"')?
+        .strip_suffix('"')
+        .map(ToOwned::to_owned)
+}
+
+fn response_content_length(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.trim().parse().ok())
+}
+
+/// Make sure a property of an artifact did not change since it was resolved,
+/// properties unknown on either side are skipped.
+fn check_unchanged<T: PartialEq + std::fmt::Display>(
+    expected: Option<T>,
+    actual: Option<T>,
+    property: &str,
+) -> Result<(), IndexerError> {
+    match (expected, actual) {
+        (Some(expected), Some(actual)) if expected != actual => Err(IndexerError::ArtifactChanged(
+            format!("{} changed from {} to {}", property, expected, actual),
+        )),
+        _ => Ok(()),
     }
 }
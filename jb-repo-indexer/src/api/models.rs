@@ -1,11 +1,127 @@
 use reqwest::Url;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RepoPluginDetails {
-    pub xml_id: String,
     pub id: u64,
+
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Short plain text description, the full one is HTML.
+    #[serde(default)]
+    pub preview: Option<String>,
+
+    #[serde(default)]
+    pub vendor: Option<RepoVendor>,
+
+    /// Path of the plugin page, relative to the marketplace.
+    #[serde(default)]
+    pub link: Option<String>,
+
+    #[serde(default)]
+    pub downloads: Option<u64>,
+
+    /// Mean rating between 0 and 5.
+    #[serde(default)]
+    pub rating: Option<f64>,
+
+    #[serde(default)]
+    pub pricing_model: Option<String>,
+
+    #[serde(default)]
+    pub purchase_info: Option<RepoPurchaseInfo>,
+
+    #[serde(default)]
+    pub urls: RepoPluginUrls,
+
+    #[serde(default)]
+    pub screens: Vec<RepoPluginScreenshot>,
+
+    #[serde(default)]
+    pub tags: Vec<RepoPluginTag>,
+
+    /// The kind of product the plugin extends, such as `intellij`.
+    #[serde(default)]
+    pub family: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoPluginTag {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoVendor {
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Contact address of the vendor, not every vendor publishes one.
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoPluginScreenshot {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoPluginUrls {
+    #[serde(default)]
+    pub source_code_url: Option<String>,
+
+    #[serde(default)]
+    pub bugtracker_url: Option<String>,
+
+    #[serde(default)]
+    pub doc_url: Option<String>,
+
+    /// Where the declared license of the plugin can be read.
+    #[serde(default)]
+    pub license_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoPurchaseInfo {
+    pub product_code: Option<String>,
+    pub buy_url: Option<String>,
+    pub trial_period: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoProduct {
+    #[serde(default)]
+    pub trial_available: Option<bool>,
+
+    #[serde(default)]
+    pub trial_days: Option<u32>,
+
+    #[serde(default)]
+    pub buy_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoSearchResult {
+    #[serde(default)]
+    pub plugins: Vec<RepoSearchPlugin>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoSearchPlugin {
+    pub xml_id: String,
+
+    /// Milliseconds since the unix epoch.
+    #[serde(default, alias = "cdate")]
+    pub update_date: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -23,6 +139,36 @@ pub struct RepoUpdateMetadata {
 
     #[serde(default)]
     pub optional_dependencies: Vec<String>,
+
+    #[serde(default)]
+    pub since: Option<String>,
+
+    #[serde(default)]
+    pub until: Option<String>,
+
+    /// Change notes of the update as HTML.
+    #[serde(default)]
+    pub notes: Option<String>,
+
+    /// Upload time in milliseconds since the unix epoch.
+    #[serde(default, deserialize_with = "timestamp")]
+    pub cdate: Option<i64>,
+}
+
+/// A timestamp the marketplace sends either as a number or as a string.
+fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Number(i64),
+        String(String),
+    }
+
+    Ok(match Option::<Timestamp>::deserialize(deserializer)? {
+        Some(Timestamp::Number(v)) => Some(v),
+        Some(Timestamp::String(v)) => v.trim().parse().ok(),
+        None => None,
+    })
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -30,10 +176,33 @@ pub struct RepoDownloadInfo {
     pub url: Url,
     pub etag: Option<String>,
     pub file_name: Option<String>,
+
+    /// Size of the artifact in bytes, if the server announced it.
+    pub size: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RepoDownloadHash {
     pub algorithm: String,
     pub value: Vec<u8>,
+
+    /// Size of the artifact in bytes, only known if it was downloaded.
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoVerificationResult {
+    pub ide_version: String,
+    pub result: String,
+}
+
+impl RepoVerificationResult {
+    /// Whether the verifier found the update to work with the IDE build.
+    ///
+    /// Warnings don't prevent the plugin from loading, so they count as
+    /// compatible.
+    pub fn is_compatible(&self) -> bool {
+        matches!(self.result.as_str(), "OK" | "COMPATIBLE" | "WARNINGS")
+    }
 }
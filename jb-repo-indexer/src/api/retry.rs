@@ -0,0 +1,116 @@
+use crate::args::ApiArgs;
+use crate::error::IndexerError;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When and how long to wait before a failed request is sent again.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(args: &ApiArgs) -> Self {
+        Self {
+            max_retries: args.max_retries,
+            base_delay: Duration::from_millis(args.retry_base_delay_ms),
+            max_delay: Duration::from_millis(args.retry_max_delay_ms),
+        }
+    }
+
+    /// Whether another attempt should be made after the given number of retries.
+    pub fn should_retry(&self, retries: u32, result: &Result<Response, IndexerError>) -> bool {
+        if retries >= self.max_retries {
+            return false;
+        }
+
+        match result {
+            Ok(response) => is_retryable_status(response.status()),
+            Err(err) => is_retryable_error(err),
+        }
+    }
+
+    /// Exponential backoff with jitter, so tasks which failed together
+    /// don't retry together.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        half + half.mul_f64(random_fraction())
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_retryable_error(err: &IndexerError) -> bool {
+    match err {
+        IndexerError::HttpClientError(err) => err.is_timeout() || err.is_connect() || err.is_request(),
+        IndexerError::InjectedFault(_) => true,
+        _ => false,
+    }
+}
+
+/// A value in `[0, 1)`, good enough for jitter.
+fn random_fraction() -> f64 {
+    let value = RandomState::new().hash_one(Instant::now());
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Used when a rate limited response does not say how long to wait.
+const DEFAULT_RATE_LIMIT_PAUSE: Duration = Duration::from_secs(10);
+
+/// Longest pause accepted from a `Retry-After` header.
+const MAX_RATE_LIMIT_PAUSE: Duration = Duration::from_secs(300);
+
+/// Pauses all requests after the marketplace rate limited one of them.
+#[derive(Debug, Default)]
+pub struct RateLimitGate {
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl RateLimitGate {
+    /// Pause all requests as requested by a rate limited response.
+    pub fn pause(&self, response: &Response) {
+        let duration = retry_after(response)
+            .unwrap_or(DEFAULT_RATE_LIMIT_PAUSE)
+            .min(MAX_RATE_LIMIT_PAUSE);
+        let until = Instant::now() + duration;
+
+        let mut paused_until = self.paused_until.lock().unwrap();
+        if paused_until.is_none_or(|v| v < until) {
+            tracing::warn!("Rate limited, pausing all requests for {:?}", duration);
+            *paused_until = Some(until);
+        }
+    }
+
+    /// Wait until requests may be sent again.
+    pub async fn wait(&self) {
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+
+            match paused_until {
+                // Sleep again afterward, the pause may have been extended
+                Some(until) if until > Instant::now() => {
+                    tokio::time::sleep_until(until.into()).await
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// The `Retry-After` header in its delay-seconds form.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
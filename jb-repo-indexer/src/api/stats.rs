@@ -0,0 +1,88 @@
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters about the HTTP requests made during a run.
+#[derive(Debug, Default)]
+pub struct HttpStatistics {
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+    retries: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    total_latency_micros: AtomicU64,
+    status_codes: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl HttpStatistics {
+    pub fn record_response(&self, status: StatusCode, latency: Duration) {
+        self.record_request(latency);
+        *self
+            .status_codes
+            .lock()
+            .unwrap()
+            .entry(status.as_u16())
+            .or_default() += 1;
+    }
+
+    /// Record a request which did not produce a response at all.
+    pub fn record_failure(&self, latency: Duration) {
+        self.record_request(latency);
+        self.failed_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bytes(&self, bytes: usize) {
+        self.bytes_downloaded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn record_request(&self, latency: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HttpStatisticsSnapshot {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let total_latency = self.total_latency_micros.load(Ordering::Relaxed);
+
+        HttpStatisticsSnapshot {
+            requests,
+            failed_requests: self.failed_requests.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            average_latency: Duration::from_micros(total_latency.checked_div(requests).unwrap_or(0)),
+            status_codes: self.status_codes.lock().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpStatisticsSnapshot {
+    pub requests: u64,
+    pub failed_requests: u64,
+    pub retries: u64,
+    pub bytes_downloaded: u64,
+    pub average_latency: Duration,
+    pub status_codes: BTreeMap<u16, u64>,
+}
+
+impl HttpStatisticsSnapshot {
+    /// Log a summary block of the statistics.
+    pub fn log_summary(&self) {
+        tracing::info!("HTTP requests: {}", self.requests);
+        tracing::info!("- without response: {}", self.failed_requests);
+        tracing::info!("- retries: {}", self.retries);
+        tracing::info!("- downloaded: {:.2} MiB", self.bytes_downloaded as f64 / (1024.0 * 1024.0));
+        tracing::info!("- average latency: {:?}", self.average_latency);
+
+        for (status, count) in &self.status_codes {
+            tracing::info!("- status {}: {}", status, count);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket shared by all downloads, limiting their combined rate.
+///
+/// The bucket holds up to one second worth of bytes, so short bursts are
+/// allowed while the long term average stays below the rate.
+#[derive(Debug)]
+pub struct DownloadThrottle {
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl DownloadThrottle {
+    pub fn new(bytes_per_second: NonZeroU64) -> Self {
+        let bytes_per_second = bytes_per_second.get() as f64;
+
+        Self {
+            bytes_per_second,
+            bucket: Mutex::new(Bucket {
+                available: bytes_per_second,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take bytes out of the bucket, waiting until the rate permits them.
+    pub async fn consume(&self, bytes: usize) {
+        let deficit = {
+            let mut bucket = self.bucket.lock().unwrap();
+
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.bytes_per_second;
+            bucket.available = (bucket.available + refill).min(self.bytes_per_second);
+            bucket.refilled_at = now;
+
+            // Going negative makes later callers wait for this debt as well
+            bucket.available -= bytes as f64;
+            -bucket.available
+        };
+
+        if deficit > 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(deficit / self.bytes_per_second)).await;
+        }
+    }
+}
@@ -1,24 +1,793 @@
-use std::num::NonZeroUsize;
+use std::net::SocketAddr;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
-use clap::Parser;
+use crate::chaos::ChaosConfig;
+use crate::meta::TreeLocation;
+use crate::statistics::FailureCategory;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use url::Url;
 
 #[derive(Debug, Clone, Parser)]
 pub struct IndexerArgs {
-    #[arg(short, long, default_value = "indexer.db", env = "JB_REPO_INDEXER_DB")]
+    #[arg(short, long, global = true, default_value = "indexer.db", env = "JB_REPO_INDEXER_DB")]
     pub database: PathBuf,
 
+    /// Open the database read-only, such as while another process syncs it.
+    #[arg(long, global = true, default_value_t = false, env = "JB_REPO_INDEXER_READ_ONLY")]
+    pub read_only: bool,
+
+    #[arg(long, global = true, hide = true, env = "JB_REPO_INDEXER_CHAOS")]
+    pub chaos: Option<ChaosConfig>,
+
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Human, env = "JB_REPO_INDEXER_LOG_FORMAT")]
+    pub log_format: LogFormat,
+
+    /// Only log errors and hide the progress bars.
+    #[arg(short, long, global = true, default_value_t = false, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log more, `-v` for info, `-vv` for debug and `-vvv` for trace.
+    /// Directives in `JB_REPO_INDEXER_LOG` take precedence.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(subcommand)]
+    pub command: IndexerCommand,
+}
+
+/// Options for talking to the marketplace.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ApiArgs {
     #[arg(long, default_value = "32")]
     pub max_parallel_small_requests: NonZeroUsize,
 
     #[arg(long, default_value = "4")]
     pub max_parallel_large_requests: NonZeroUsize,
 
+    #[arg(long, env = "JB_REPO_INDEXER_ARTIFACT_CACHE")]
+    pub artifact_cache: Option<PathBuf>,
+
+    /// Combined bytes per second of all artifacts downloaded for hashing.
+    #[arg(long)]
+    pub max_download_rate: Option<NonZeroU64>,
+
+    #[arg(long, default_value = "3")]
+    pub max_retries: u32,
+
+    #[arg(long, default_value = "500")]
+    pub retry_base_delay_ms: u64,
+
+    #[arg(long, default_value = "30000")]
+    pub retry_max_delay_ms: u64,
+
+    #[arg(long, default_value = "10000")]
+    pub connect_timeout_ms: u64,
+
+    /// Give up on a response which sent nothing for this long.
+    #[arg(long, default_value = "60000")]
+    pub read_timeout_ms: u64,
+
+    /// Idle connections kept open per host, unlimited by default.
+    #[arg(long)]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// Never negotiate HTTP/2, for proxies which mishandle it.
+    #[arg(long, default_value_t = false)]
+    pub http1_only: bool,
+
+    /// Base URL of the marketplace, or the `updatePlugins.xml` of a custom repository.
+    #[arg(long, default_value = "https://plugins.jetbrains.com/", env = "JB_REPO_INDEXER_REPOSITORY_URL")]
+    pub repository_url: Url,
+
+    #[arg(long, value_enum, default_value_t = RepositoryKind::Marketplace)]
+    pub repository_kind: RepositoryKind,
+
+    /// Marketplace API token, only sent to the repository itself.
+    #[arg(long, env = "JB_REPO_INDEXER_MARKETPLACE_TOKEN", hide_env_values = true)]
+    pub marketplace_token: Option<String>,
+
+    /// Proxy for all requests, `HTTPS_PROXY` and friends are honored without it.
+    #[arg(long)]
+    pub http_proxy: Option<Url>,
+
+    /// PEM bundle of additional CA certificates to trust, such as the one of
+    /// a TLS intercepting proxy.
+    #[arg(long, env = "JB_REPO_INDEXER_CA_BUNDLE")]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct SyncArgs {
+    #[command(flatten)]
+    pub api: ApiArgs,
+
+    #[arg(long = "plugin")]
+    pub plugins: Vec<String>,
+
+    #[arg(long)]
+    pub plugin_file: Option<PathBuf>,
+
+    /// Never sync these plugins and remove them if they were synced before.
+    #[arg(long = "exclude")]
+    pub excluded: Vec<String>,
+
+    /// File of plugins to exclude, one per line like `--plugin-file`.
+    #[arg(long, env = "JB_REPO_INDEXER_EXCLUDE_FILE")]
+    pub exclude_file: Option<PathBuf>,
+
+    /// Only sync plugins with any of these marketplace tags.
+    #[arg(long = "filter-tag")]
+    pub filter_tags: Vec<String>,
+
+    /// Only index plugins of these families, others are left as they are.
+    #[arg(long = "family", value_enum, value_delimiter = ',', ignore_case = true)]
+    pub families: Vec<PluginFamily>,
+
+    /// Only index plugins compatible with these IDE products, such as
+    /// `idea` or `pycharm`, others are left as they are.
+    #[arg(long = "product", value_delimiter = ',')]
+    pub products: Vec<String>,
+
+    /// Name the synced plugins are recorded under, so several repositories
+    /// can be synced into one database.
+    #[arg(long, default_value = "marketplace")]
+    pub repository_name: String,
+
+    #[arg(long, default_value_t = false)]
+    pub resume: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub incremental: bool,
+
+    #[arg(long, default_value = "50")]
+    pub incremental_max_pages: usize,
+
+    #[arg(long, default_value_t = false)]
+    pub bootstrap_latest_only: bool,
+
+    /// Only keep this many of the newest versions of every channel.
+    #[arg(long)]
+    pub max_versions_per_plugin: Option<NonZeroUsize>,
+
+    /// Only sync versions of these channels, the default one is `stable`.
+    #[arg(long, value_delimiter = ',')]
+    pub channels: Vec<String>,
+
+    /// Which form of download URL is stored and published.
+    #[arg(long, value_enum, default_value_t = DownloadUrlStyle::Resolved)]
+    pub download_url_style: DownloadUrlStyle,
+
+    /// Query parameters removed from resolved download URLs, because they
+    /// change between runs.
+    #[arg(long = "strip-download-url-param", value_delimiter = ',')]
+    pub strip_download_url_params: Vec<String>,
+
+    #[arg(long, default_value_t = false)]
+    pub sync_verification: bool,
+
+    #[arg(long, value_parser = parse_fraction)]
+    pub reverify: Option<f64>,
+
+    #[arg(long)]
+    pub reverify_older_than_days: Option<u32>,
+
+    /// Dispatch failed tasks again this often at the end of a sync.
+    #[arg(long, default_value = "1")]
+    pub retry_rounds: usize,
+
+    /// On SIGINT or SIGTERM, let running tasks finish for this many seconds
+    /// before giving up on them.
+    #[arg(long, default_value = "30")]
+    pub shutdown_timeout_secs: u64,
+
+    /// Give up on a single plugin or update task after this many seconds,
+    /// counted from its first request.
+    #[arg(long)]
+    pub task_timeout_secs: Option<NonZeroU64>,
+
+    /// Write the outcome of all tasks to this file as JSON.
+    #[arg(long)]
+    pub report_out: Option<PathBuf>,
+
+    /// Exit with the code of the first of these categories any task failed
+    /// with, instead of succeeding despite failed tasks.
+    #[arg(long, value_delimiter = ',')]
+    pub fail_on: Vec<FailureCategory>,
+
+    /// Sync into a throwaway copy of the database and only report what
+    /// would change.
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub metrics: MetricsArgs,
+
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+}
+
+/// Options for notifying a webhook once a sync finished.
+#[derive(Debug, Clone, clap::Args)]
+pub struct NotifyArgs {
+    /// Post a summary of the sync to this URL.
+    #[arg(long, env = "JB_REPO_INDEXER_WEBHOOK_URL")]
+    pub webhook_url: Option<Url>,
+
+    #[arg(long, value_enum, default_value_t = WebhookFormat::Json, requires = "webhook_url")]
+    pub webhook_format: WebhookFormat,
+
+    /// Only notify once at least this many tasks failed.
+    #[arg(long, requires = "webhook_url")]
+    pub webhook_min_failures: Option<usize>,
+}
+
+/// Options for exporting Prometheus metrics.
+#[derive(Debug, Clone, clap::Args)]
+pub struct MetricsArgs {
+    /// Serve metrics for scraping on this address while running.
+    #[arg(long)]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Push the metrics to this pushgateway at the end of the run.
+    #[arg(long, env = "JB_REPO_INDEXER_PUSHGATEWAY")]
+    pub metrics_push_url: Option<Url>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct GenerateArgs {
     #[arg(short, long, default_value = "meta", env = "JB_REPO_INDEXER_OUTPUT_DIRECTORY")]
     pub output_directory: PathBuf,
 
+    #[arg(long, default_value = "10")]
+    pub max_index_shrink: f64,
+
+    #[arg(long, default_value = "1048576")]
+    pub max_metadata_file_size: usize,
+
+    #[arg(long, value_enum, default_value_t = LatestStrategy::Version)]
+    pub latest_strategy: LatestStrategy,
+
+    #[arg(long, env = "JB_REPO_INDEXER_OVERRIDES")]
+    pub overrides: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    pub retain_archived_versions: bool,
+
+    /// Publish versions which vanished upstream as tombstones for at least
+    /// this many days, instead of dropping them right away.
+    #[arg(long)]
+    pub tombstone_days: Option<u32>,
+
+    /// Publish the change notes of every version as plain text.
     #[arg(long, default_value_t = false)]
-    pub no_sync: bool,
+    pub include_change_notes: bool,
 
+    /// Characters of the change notes published, longer ones are truncated.
+    #[arg(long, default_value = "1000", requires = "include_change_notes")]
+    pub change_notes_max_length: NonZeroUsize,
+
+    /// Only publish this many of the newest versions of every channel.
+    #[arg(long)]
+    pub max_versions_per_plugin: Option<NonZeroUsize>,
+
+    /// Only publish versions of these channels, the default one is `stable`.
+    #[arg(long, value_delimiter = ',')]
+    pub channels: Vec<String>,
+
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+
+    #[arg(long)]
+    pub fail_on_stale: Option<usize>,
+
+    /// How many plugins are generated at the same time.
+    #[arg(long, default_value = "16", alias = "max-parallel-generation")]
+    pub generate_jobs: NonZeroUsize,
+
+    #[arg(long, value_enum, default_value_t = SwapStrategy::Rename)]
+    pub swap: SwapStrategy,
+
+    /// Generate all plugins again, instead of only those which changed.
+    #[arg(long, default_value_t = false)]
+    pub full: bool,
+
+    /// Keep an immutable copy of the generated tree below `snapshots/`,
+    /// listed in `snapshots.json`, so consumers can pin a generation.
     #[arg(long, default_value_t = false)]
-    pub no_generate: bool,
+    pub snapshot: bool,
+
+    /// Only keep this many of the newest snapshots.
+    #[arg(long)]
+    pub max_snapshots: Option<NonZeroUsize>,
+
+    /// Keep running and generate again whenever plugins in the database
+    /// change, such as by a separate sync process.
+    #[arg(long, default_value_t = false)]
+    pub watch: bool,
+
+    /// How often the database is checked for changes with `--watch`.
+    #[arg(long, default_value = "30000", requires = "watch")]
+    pub watch_interval_ms: u64,
+
+    /// Also write compressed variants of every file.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub compress: Vec<CompressionFormat>,
+
+    /// Only write the compressed variants.
+    #[arg(long, default_value_t = false, requires = "compress")]
+    pub no_uncompressed: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub output_format: OutputFormat,
+
+    /// File with the base64 encoded ed25519 seed to sign all generated JSON
+    /// files with.
+    #[arg(long, env = "JB_REPO_INDEXER_SIGNING_KEY")]
+    pub signing_key: Option<PathBuf>,
+
+    /// Also write the metadata of all plugins into a single file.
+    #[arg(long, value_enum)]
+    pub bundle: Option<BundleFormat>,
+
+    /// Point download URLs of mirrored artifacts at this base URL.
+    #[arg(long, requires = "mirror_directory", env = "JB_REPO_INDEXER_MIRROR_URL")]
+    pub mirror_url: Option<Url>,
+
+    /// The directory populated by the `mirror` subcommand.
+    #[arg(long, env = "JB_REPO_INDEXER_MIRROR_DIRECTORY")]
+    pub mirror_directory: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub s3: S3Args,
+}
+
+/// Options for publishing the generated tree to S3 compatible storage.
+#[derive(Debug, Clone, clap::Args)]
+pub struct S3Args {
+    /// Publish the validated tree to this bucket.
+    #[arg(long, env = "JB_REPO_INDEXER_S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+
+    /// Key prefix of the tree inside the bucket.
+    #[arg(long, default_value = "", env = "JB_REPO_INDEXER_S3_PREFIX")]
+    pub s3_prefix: String,
+
+    /// Endpoint of S3 compatible services, such as R2 or GCS.
+    #[arg(long, env = "JB_REPO_INDEXER_S3_ENDPOINT")]
+    pub s3_endpoint: Option<Url>,
+
+    #[arg(long, default_value = "us-east-1", env = "AWS_REGION")]
+    pub s3_region: String,
+
+    #[arg(long, env = "AWS_ACCESS_KEY_ID", hide_env_values = true)]
+    pub s3_access_key_id: Option<String>,
+
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    pub s3_secret_access_key: Option<String>,
+
+    #[arg(long, env = "AWS_SESSION_TOKEN", hide_env_values = true)]
+    pub s3_session_token: Option<String>,
+}
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| (0.0..=1.0).contains(v))
+        .ok_or_else(|| "must be a number between 0 and 1".to_owned())
+}
+
+#[derive(Debug, Clone, Subcommand)]
+#[allow(clippy::large_enum_variant)]
+pub enum IndexerCommand {
+    /// Sync the database with the marketplace.
+    Sync(SyncArgs),
+
+    /// Generate the metadata tree from the database.
+    Generate(GenerateArgs),
+
+    /// Write an allowlist of the plugins referenced by existing Nix files.
+    ImportConfig(ImportConfigArgs),
+
+    /// Report how the repository and syncs developed over past runs.
+    Trends(TrendsArgs),
+
+    /// List plugins which failed to sync in several runs.
+    Failures(FailuresArgs),
+
+    /// Check a generated tree against the database.
+    CheckOutput(CheckOutputArgs),
+
+    /// Check the signatures of a generated tree.
+    VerifySignature(VerifySignatureArgs),
+
+    /// Download a sample of published artifacts and check their hashes.
+    Verify(VerifyArgs),
+
+    /// Hash a sample of synced artifacts again and flag those which changed.
+    DriftCheck(DriftCheckArgs),
+
+    /// Serve a generated tree over HTTP.
+    Serve(ServeArgs),
+
+    /// Download the artifacts of all published versions into a mirror.
+    Mirror(MirrorArgs),
+
+    /// Print what the database knows about a plugin or update.
+    Query(QueryArgs),
+
+    /// Delete orphaned rows and compact the database.
+    Prune(PruneArgs),
+
+    /// Generate a tree of only some plugins and the plugins they require.
+    Closure(ClosureArgs),
+
+    /// Write a consistent snapshot of the database, also while it is in use.
+    Backup(BackupArgs),
+
+    /// Replace the database with a snapshot written by `backup`.
+    Restore(RestoreArgs),
+
+    /// Write all rows of the database as newline delimited JSON.
+    ExportDb(ExportDbArgs),
+
+    /// Fill an empty database from a dump written by `export-db`.
+    ImportDb(ImportDbArgs),
+
+    /// Seed the database from a generated tree instead of a full sync.
+    ImportMeta(ImportMetaArgs),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ImportConfigArgs {
+    /// Nix file or directory to scan for plugin references.
+    pub source: PathBuf,
+
+    #[arg(long, default_value = "allowlist.txt")]
+    pub allowlist: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct TrendsArgs {
+    /// Only consider runs from the last this many days.
+    #[arg(long, default_value = "30")]
+    pub days: u32,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    pub format: ReportFormat,
+
+    /// Write the report to a file instead of stdout.
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct FailuresArgs {
+    /// Only consider failures from the last this many days.
+    #[arg(long, default_value = "30")]
+    pub days: u32,
+
+    /// Only list plugins which failed in at least this many runs.
+    #[arg(long, default_value = "2")]
+    pub min_runs: u32,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    pub format: ReportFormat,
+
+    /// Write the report to a file instead of stdout.
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CheckOutputArgs {
+    /// The generated tree, usually the output directory.
+    pub directory: PathBuf,
+
+    /// The overrides the tree was generated with, pinned versions are
+    /// compared against them instead of the database.
+    #[arg(long, env = "JB_REPO_INDEXER_OVERRIDES")]
+    pub overrides: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct VerifySignatureArgs {
+    /// The generated tree, usually the output directory.
+    pub directory: PathBuf,
+
+    /// Base64 encoded public key the tree must be signed with, instead of
+    /// trusting the one in `keys.json`.
+    #[arg(long)]
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub api: ApiArgs,
+
+    /// The generated tree, usually the output directory.
+    pub directory: PathBuf,
+
+    /// Number of plugins to verify the latest versions of.
+    #[arg(long, default_value = "20")]
+    pub sample: usize,
+
+    /// Verify these plugins instead of a random sample.
+    #[arg(long = "plugin")]
+    pub plugins: Vec<String>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct DriftCheckArgs {
+    #[command(flatten)]
+    pub api: ApiArgs,
+
+    /// Number of artifacts to check.
+    #[arg(long, default_value = "20")]
+    pub sample: u64,
+
+    /// Check all versions of these plugins instead of a random sample.
+    #[arg(long = "plugin")]
+    pub plugins: Vec<String>,
+
+    /// Always download the artifacts instead of trusting the published
+    /// `.hash.json` files.
+    #[arg(long)]
+    pub download: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ServeArgs {
+    #[arg(default_value = "meta", env = "JB_REPO_INDEXER_OUTPUT_DIRECTORY")]
+    pub directory: PathBuf,
+
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    /// Seconds clients may cache responses for without revalidating.
+    #[arg(long, default_value = "300")]
+    pub max_age: u32,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct MirrorArgs {
+    #[command(flatten)]
+    pub api: ApiArgs,
+
+    /// Content-addressed directory the artifacts are stored in.
+    #[arg(long, env = "JB_REPO_INDEXER_MIRROR_DIRECTORY")]
+    pub directory: PathBuf,
+
+    /// Only mirror these plugins instead of all.
+    #[arg(long = "plugin")]
+    pub plugins: Vec<String>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct QueryArgs {
+    #[arg(required_unless_present = "update_id")]
+    pub xml_id: Option<String>,
+
+    /// Query an update instead of a plugin.
+    #[arg(long, conflicts_with = "xml_id")]
+    pub update_id: Option<u64>,
+
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ClosureArgs {
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub plugins: Vec<String>,
+
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct PruneArgs {
+    /// Also delete all but the newest this many versions of each plugin.
+    ///
+    /// Syncing adds them back, unless it is limited the same way.
+    #[arg(long)]
+    pub keep_versions: Option<usize>,
+
+    /// Skip compacting the database file afterward.
+    #[arg(long, default_value_t = false)]
+    pub no_vacuum: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct BackupArgs {
+    pub path: PathBuf,
+
+    /// Replace the snapshot if it exists already.
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RestoreArgs {
+    /// The snapshot to restore, no other process may use the database meanwhile.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExportDbArgs {
+    /// Where to write the dump, it is compressed if the name ends with `.gz`.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ImportDbArgs {
+    /// The dump to import, decompressed if the name ends with `.gz`.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ImportMetaArgs {
+    #[command(flatten)]
+    pub api: ApiArgs,
+
+    /// Directory or URL of the tree, as written by `generate`.
+    pub source: TreeLocation,
+
+    /// How many plugins are read from the tree at the same time.
+    #[arg(long, default_value = "16")]
+    pub jobs: NonZeroUsize,
+}
+
+/// How log events are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Readable lines below the progress bars.
+    Human,
+
+    /// One JSON object per line on stderr, with the spans the event
+    /// happened in, without progress bars.
+    Json,
+}
+
+/// Which API the plugin repository speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RepositoryKind {
+    /// The JetBrains marketplace or a compatible mirror.
+    Marketplace,
+
+    /// A classic custom repository listing its plugins in `updatePlugins.xml`.
+    Custom,
+}
+
+/// The kind of product a marketplace plugin extends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PluginFamily {
+    Intellij,
+    Teamcity,
+    Hub,
+    Youtrack,
+    Dotnet,
+    Edu,
+    Fleet,
+    Toolbox,
+}
+
+impl PluginFamily {
+    /// The family as spelled by the marketplace API.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Intellij => "intellij",
+            Self::Teamcity => "teamcity",
+            Self::Hub => "hub",
+            Self::Youtrack => "youtrack",
+            Self::Dotnet => "dotnet",
+            Self::Edu => "edu",
+            Self::Fleet => "fleet",
+            Self::Toolbox => "toolbox",
+        }
+    }
+}
+
+/// The form download URLs are stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DownloadUrlStyle {
+    /// The URL the download redirects to, usually on a CDN.
+    Resolved,
+
+    /// The `plugin/download?updateId=` URL of the marketplace, custom
+    /// repositories keep the resolved one.
+    Stable,
+}
+
+/// Payload posted to a webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WebhookFormat {
+    /// The statistics as a JSON object.
+    Json,
+
+    /// A Slack message.
+    Slack,
+
+    /// A Discord message.
+    Discord,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+/// How the latest version of a channel is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatestStrategy {
+    /// The highest version number.
+    Version,
+
+    /// The most recently uploaded version.
+    Upload,
+}
+
+/// How a newly generated tree replaces the old one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SwapStrategy {
+    /// Rename the new directory into place, for trees committed to git.
+    Rename,
+
+    /// Keep generations next to the output directory and atomically switch
+    /// a symlink between them.
+    Symlink,
+}
+
+/// Which files describe each plugin in the generated tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// `metadata.json`, possibly split into pages.
+    Json,
+
+    /// `default.nix` attrsets which can be imported directly.
+    Nix,
+
+    /// Both of the above.
+    Both,
+
+    /// `metadata.json` plus an `updatePlugins.xml` per channel, which IDEs
+    /// can use as a custom plugin repository.
+    UpdatePluginsXml,
+}
+
+impl OutputFormat {
+    pub fn writes_json(self) -> bool {
+        matches!(self, Self::Json | Self::Both | Self::UpdatePluginsXml)
+    }
+
+    pub fn writes_update_plugins_xml(self) -> bool {
+        self == Self::UpdatePluginsXml
+    }
+
+    pub fn writes_nix(self) -> bool {
+        matches!(self, Self::Nix | Self::Both)
+    }
+}
+
+/// Layout of the file bundling all plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BundleFormat {
+    /// `all-plugins.json`, an object keyed by plugin id.
+    Json,
+
+    /// `all-plugins.ndjson`, one plugin per line.
+    Ndjson,
+}
+
+/// Compressed variants written next to the generated files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompressionFormat {
+    /// `.gz` files.
+    Gzip,
+
+    /// `.zst` files.
+    Zstd,
 }
@@ -0,0 +1,101 @@
+use crate::error::IndexerError;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt as _;
+
+/// Content-addressed storage for downloaded plugin artifacts.
+///
+/// Artifacts are stored as `<root>/<algorithm>/<xx>/<hex digest>`.
+#[derive(Debug, Clone)]
+pub struct ArtifactCache {
+    root: PathBuf,
+}
+
+impl ArtifactCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of an artifact with the given hash.
+    pub fn path_for(&self, algorithm: &str, hash: &[u8]) -> PathBuf {
+        self.root.join(Self::key_for(algorithm, hash))
+    }
+
+    /// Location of an artifact relative to the root, separated by slashes
+    /// so it can be used in URLs as well.
+    pub fn key_for(algorithm: &str, hash: &[u8]) -> String {
+        let hex_digest = crate::hex::encode(hash);
+
+        format!(
+            "{}/{}/{}",
+            algorithm_directory(algorithm),
+            &hex_digest[0..2],
+            hex_digest
+        )
+    }
+
+    /// Whether an artifact with the given hash is stored.
+    pub async fn contains(&self, algorithm: &str, hash: &[u8]) -> Result<bool, IndexerError> {
+        Ok(tokio::fs::try_exists(self.path_for(algorithm, hash)).await?)
+    }
+
+    /// Start writing a new artifact whose hash is not known yet.
+    pub async fn begin(&self) -> Result<PendingArtifact, IndexerError> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let tmp_directory = self.root.join("tmp");
+        tokio::fs::create_dir_all(&tmp_directory).await?;
+
+        let path = tmp_directory.join(format!(
+            "{}-{}.part",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = tokio::fs::File::create(&path).await?;
+
+        Ok(PendingArtifact { path, file })
+    }
+
+    /// Move a completely written artifact to its final location.
+    pub async fn commit(
+        &self,
+        mut pending: PendingArtifact,
+        algorithm: &str,
+        hash: &[u8],
+    ) -> Result<PathBuf, IndexerError> {
+        pending.file.flush().await?;
+        drop(pending.file);
+
+        let target = self.path_for(algorithm, hash);
+        tokio::fs::create_dir_all(target.parent().unwrap()).await?;
+        tokio::fs::rename(&pending.path, &target).await?;
+
+        Ok(target)
+    }
+}
+
+/// An artifact which is still being downloaded.
+pub struct PendingArtifact {
+    path: PathBuf,
+    file: tokio::fs::File,
+}
+
+impl PendingArtifact {
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), IndexerError> {
+        self.file.write_all(data).await?;
+        Ok(())
+    }
+
+    /// Throw away a partially written artifact.
+    pub async fn discard(self) {
+        drop(self.file);
+
+        if let Err(err) = tokio::fs::remove_file(&self.path).await {
+            tracing::warn!("Failed to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+pub fn algorithm_directory(algorithm: &str) -> String {
+    algorithm.to_lowercase().replace('-', "")
+}
@@ -0,0 +1,109 @@
+use crate::error::IndexerError;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Rates at which faults are injected, each between 0 and 1.
+///
+/// Parsed from `timeout=0.05,rate-limit=0.05,malformed-json=0.01,database=0.01,seed=42`,
+/// omitted faults are never injected.
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    timeout: f64,
+    rate_limit: f64,
+    malformed_json: f64,
+    database: f64,
+    seed: u64,
+}
+
+impl FromStr for ChaosConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for part in s.split(',').filter(|v| !v.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+
+            if key == "seed" {
+                config.seed = value.parse().map_err(|e| format!("bad seed: {}", e))?;
+                continue;
+            }
+
+            let rate = value
+                .parse::<f64>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| format!("rate of {} must be between 0 and 1", key))?;
+
+            match key {
+                "timeout" => config.timeout = rate,
+                "rate-limit" => config.rate_limit = rate,
+                "malformed-json" => config.malformed_json = rate,
+                "database" => config.database = rate,
+                _ => return Err(format!("unknown fault '{}'", key)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Injects simulated faults into the API and database layers.
+///
+/// Faults are drawn from a seeded generator, so a sequential run always
+/// fails the same way.
+#[derive(Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+    state: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        tracing::warn!("Fault injection is enabled: {:?}", config);
+
+        Self {
+            state: AtomicU64::new(config.seed),
+            config,
+        }
+    }
+
+    pub fn inject_timeout(&self) -> bool {
+        self.roll(self.config.timeout)
+    }
+
+    pub fn inject_rate_limit(&self) -> bool {
+        self.roll(self.config.rate_limit)
+    }
+
+    pub fn inject_malformed_json(&self) -> bool {
+        self.roll(self.config.malformed_json)
+    }
+
+    pub fn inject_database_error(&self) -> Result<(), IndexerError> {
+        match self.roll(self.config.database) {
+            true => Err(IndexerError::InjectedFault("database error")),
+            false => Ok(()),
+        }
+    }
+
+    fn roll(&self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+
+        // splitmix64
+        let mut z = self
+            .state
+            .fetch_add(0x9E3779B97F4A7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+
+        let sample = (z >> 11) as f64 / (1u64 << 53) as f64;
+        sample < rate
+    }
+}
@@ -0,0 +1,48 @@
+use crate::args::{BackupArgs, IndexerArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+use std::path::{Path, PathBuf};
+
+pub async fn run(args: &IndexerArgs, command: &BackupArgs) -> Result<(), IndexerError> {
+    if !command.force && tokio::fs::try_exists(&command.path).await? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists, pass --force to replace it", command.path.display()),
+        )
+        .into());
+    }
+
+    // Backing up must neither migrate nor otherwise write to the database
+    let database = Database::setup(&IndexerArgs {
+        read_only: true,
+        ..args.clone()
+    })
+    .await?;
+
+    // Written next to the target first, so an existing snapshot is only
+    // replaced by a complete one
+    let partial = with_suffix(&command.path, ".partial");
+    remove_file_if_exists(&partial).await?;
+
+    tracing::info!("Writing snapshot to {}...", command.path.display());
+    database.backup_into(&partial).await?;
+    Database::check_snapshot(&partial).await?;
+    tokio::fs::rename(&partial, &command.path).await?;
+
+    tracing::info!("Done.");
+    Ok(())
+}
+
+pub(super) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+pub(super) async fn remove_file_if_exists(path: &Path) -> Result<(), IndexerError> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
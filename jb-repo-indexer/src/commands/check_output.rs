@@ -0,0 +1,25 @@
+use crate::args::{CheckOutputArgs, IndexerArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+use crate::overrides::Overrides;
+
+pub async fn run(args: &IndexerArgs, command: &CheckOutputArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    let overrides = Overrides::load(command.overrides.as_deref()).await?;
+
+    let issues = crate::meta::check_tree(&command.directory, &database, &overrides).await?;
+    if issues.is_empty() {
+        tracing::info!("{} is consistent with the database", command.directory.display());
+        return Ok(());
+    }
+
+    for issue in &issues {
+        tracing::error!("- {}", issue);
+    }
+
+    Err(IndexerError::OutputValidation(format!(
+        "found {} inconsistencies in {}",
+        issues.len(),
+        command.directory.display()
+    )))
+}
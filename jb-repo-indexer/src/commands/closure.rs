@@ -0,0 +1,10 @@
+use crate::args::{ClosureArgs, IndexerArgs};
+use crate::error::IndexerError;
+
+pub async fn run(args: &IndexerArgs, command: &ClosureArgs) -> Result<(), IndexerError> {
+    tracing::info!("Starting to generate the closure of {} plugins...", command.plugins.len());
+    crate::meta::generate_closure(args, command).await?;
+    tracing::info!("Done.");
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use crate::api::{JetbrainsRepoApi, RepoDownloadHash, RepoDownloadInfo};
+use crate::args::{DriftCheckArgs, IndexerArgs};
+use crate::db::{CachedArtifact, Database};
+use crate::error::IndexerError;
+use crate::meta::sri_hash;
+use futures::StreamExt as _;
+
+pub async fn run(args: &IndexerArgs, command: &DriftCheckArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    database.ensure_writable("record hash drift")?;
+
+    let repo = JetbrainsRepoApi::new(&command.api, args.chaos.clone())?;
+
+    let artifacts = database
+        .get_drift_check_artifacts(&command.plugins, command.sample)
+        .await?;
+    tracing::info!("Checking {} artifacts for hash drift", artifacts.len());
+
+    let results = futures::stream::iter(artifacts)
+        .map(|artifact| {
+            let repo = &repo;
+            async move {
+                let hash = current_hash(repo, &artifact, command.download).await;
+                (artifact, hash)
+            }
+        })
+        .buffer_unordered(command.api.max_parallel_large_requests.get())
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut drifted = 0;
+    for (artifact, hash) in results {
+        let name = format!("{}@{}", artifact.plugin_xml_id, artifact.update_id);
+
+        let hash = match hash {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!("Failed to hash {}: {}", name, err);
+                continue;
+            }
+        };
+
+        if hash.algorithm != artifact.hash_algorithm {
+            tracing::warn!(
+                "Skipping {}, it was hashed with {} but is now published with {}",
+                name,
+                artifact.hash_algorithm,
+                hash.algorithm
+            );
+            continue;
+        }
+
+        if hash.value == artifact.hash {
+            if database.clear_hash_drift(artifact.update_id).await? {
+                tracing::info!("{} matches its stored hash again", name);
+                database.mark_update_plugins_dirty(artifact.update_id).await?;
+            }
+            continue;
+        }
+
+        tracing::error!(
+            "{} is stored as {} but now hashes to {}",
+            name,
+            sri_hash(&artifact.hash_algorithm, &artifact.hash).unwrap_or_default(),
+            sri_hash(&hash.algorithm, &hash.value).unwrap_or_default()
+        );
+        database
+            .record_hash_drift(artifact.update_id, &hash.algorithm, &artifact.hash, &hash.value)
+            .await?;
+        database.mark_update_plugins_dirty(artifact.update_id).await?;
+        drifted += 1;
+    }
+
+    if drifted > 0 {
+        return Err(IndexerError::HashDrift(drifted));
+    }
+
+    tracing::info!("All checked artifacts match their stored hashes");
+    Ok(())
+}
+
+/// Hash the artifact behind the stored download URL, downloading it if the
+/// published hash uses a different algorithm than the stored one.
+async fn current_hash(
+    repo: &JetbrainsRepoApi,
+    artifact: &CachedArtifact,
+    download: bool,
+) -> Result<RepoDownloadHash, IndexerError> {
+    let url = repo.absolute_url(&artifact.download_url).map_err(|e| {
+        IndexerError::IncompleteData(format!(
+            "bad download url of update {}: {}",
+            artifact.update_id, e
+        ))
+    })?;
+
+    if download {
+        return repo.download_hash(&url).await;
+    }
+
+    let info = RepoDownloadInfo {
+        url,
+        etag: None,
+        file_name: None,
+        size: None,
+    };
+    let hash = repo.hash_download_url(&info).await?;
+    if hash.algorithm == artifact.hash_algorithm {
+        return Ok(hash);
+    }
+
+    repo.download_hash(&info.url).await
+}
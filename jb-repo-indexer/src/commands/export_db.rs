@@ -0,0 +1,29 @@
+use crate::args::{ExportDbArgs, IndexerArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::{BufWriter, Write};
+
+pub async fn run(args: &IndexerArgs, command: &ExportDbArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+
+    let file = BufWriter::new(std::fs::File::create(&command.path)?);
+    let rows = match command.path.extension().is_some_and(|v| v == "gz") {
+        true => {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            let rows = database.export_dump(&mut encoder).await?;
+            encoder.finish()?.flush()?;
+            rows
+        }
+        false => {
+            let mut file = file;
+            let rows = database.export_dump(&mut file).await?;
+            file.flush()?;
+            rows
+        }
+    };
+
+    tracing::info!("Exported {} rows to {}", rows, command.path.display());
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use crate::args::{FailuresArgs, IndexerArgs, ReportFormat};
+use crate::db::{CachedPluginFailures, Database};
+use crate::error::IndexerError;
+use crate::meta::unix_timestamp;
+use serde::Serialize;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize)]
+struct FailuresReport {
+    since: i64,
+    plugins: Vec<PluginFailures>,
+}
+
+#[derive(Debug, Serialize)]
+struct PluginFailures {
+    plugin: String,
+    runs: u64,
+    failures: u64,
+    last_failed_at: i64,
+    last_category: String,
+    last_error: String,
+}
+
+impl From<CachedPluginFailures> for PluginFailures {
+    fn from(value: CachedPluginFailures) -> Self {
+        Self {
+            plugin: value.plugin_xml_id,
+            runs: value.runs,
+            failures: value.failures,
+            last_failed_at: value.last_failed_at,
+            last_category: value.last_category,
+            last_error: value.last_error,
+        }
+    }
+}
+
+pub async fn run(args: &IndexerArgs, command: &FailuresArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+
+    let since = unix_timestamp() - i64::from(command.days) * SECONDS_PER_DAY;
+    let plugins = database
+        .get_repeated_failures(since, command.min_runs)
+        .await?
+        .into_iter()
+        .map(PluginFailures::from)
+        .collect::<Vec<_>>();
+
+    let output = match command.format {
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(&FailuresReport { since, plugins })? + "\n"
+        }
+        ReportFormat::Csv => to_csv(&plugins),
+    };
+
+    match &command.out {
+        Some(path) => tokio::fs::write(path, output).await?,
+        None => print!("{}", output),
+    }
+
+    Ok(())
+}
+
+fn to_csv(plugins: &[PluginFailures]) -> String {
+    let mut csv = String::from("plugin,runs,failures,last_failed_at,last_category,last_error\n");
+
+    for plugin in plugins {
+        csv.push_str(&format!(
+            "\"{}\",{},{},{},{},\"{}\"\n",
+            plugin.plugin.replace('"', "\"\""),
+            plugin.runs,
+            plugin.failures,
+            plugin.last_failed_at,
+            plugin.last_category,
+            plugin.last_error.replace('"', "\"\""),
+        ));
+    }
+
+    csv
+}
@@ -0,0 +1,10 @@
+use crate::args::{GenerateArgs, IndexerArgs};
+use crate::error::IndexerError;
+
+pub async fn run(args: &IndexerArgs, command: &GenerateArgs) -> Result<(), IndexerError> {
+    tracing::info!("Starting to generate metadata...");
+    crate::meta::generate_metadata(args, command).await?;
+    tracing::info!("Done.");
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use crate::args::{ImportConfigArgs, IndexerArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+/// URL prefixes which are followed by the numeric id of a plugin.
+const NUMERIC_ID_PREFIXES: &[&str] = &[
+    "plugins.jetbrains.com/plugin/",
+    "plugins.jetbrains.com/files/",
+];
+
+#[derive(Debug, Default)]
+struct References {
+    strings: HashSet<String>,
+    numeric_ids: BTreeSet<u64>,
+}
+
+pub async fn run(args: &IndexerArgs, command: &ImportConfigArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+
+    let source = command.source.clone();
+    let references = tokio::task::spawn_blocking(move || {
+        let mut references = References::default();
+
+        for file in collect_nix_files(&source)? {
+            tracing::debug!("Scanning {}", file.display());
+            extract_references(&std::fs::read_to_string(&file)?, &mut references);
+        }
+
+        Ok::<_, IndexerError>(references)
+    })
+    .await
+    .unwrap()?;
+
+    let known_xml_ids = database.known_plugin_xml_ids().await?;
+
+    let mut allowlist = references
+        .strings
+        .into_iter()
+        .filter(|v| known_xml_ids.contains(v))
+        .collect::<BTreeSet<_>>();
+
+    for numeric_id in references.numeric_ids {
+        match database.get_plugin_by_numeric_id(numeric_id).await? {
+            Some(plugin) => {
+                allowlist.insert(plugin.xml_id);
+            }
+            None => tracing::warn!("Referenced plugin {} is not known", numeric_id),
+        }
+    }
+
+    let mut content = format!("# Plugins referenced by {}\n", command.source.display());
+    for xml_id in &allowlist {
+        content.push_str(xml_id);
+        content.push('\n');
+    }
+
+    tokio::fs::write(&command.allowlist, content).await?;
+
+    tracing::info!(
+        "Wrote {} plugins to {}",
+        allowlist.len(),
+        command.allowlist.display()
+    );
+
+    Ok(())
+}
+
+/// The file itself, or all `.nix` files below a directory.
+fn collect_nix_files(source: &Path) -> Result<Vec<PathBuf>, IndexerError> {
+    if !source.is_dir() {
+        return Ok(vec![source.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![source.to_path_buf()];
+
+    while let Some(directory) = pending.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            // Skips .git and friends
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|v| v == "nix") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Collect string literals and numeric plugin ids from marketplace URLs.
+///
+/// This is plain text matching, string literals are later narrowed down
+/// to known xml ids.
+fn extract_references(text: &str, references: &mut References) {
+    for literal in text.split('"').skip(1).step_by(2) {
+        let literal = literal.trim();
+        if !literal.is_empty() && !literal.contains(char::is_whitespace) {
+            references.strings.insert(literal.to_owned());
+        }
+    }
+
+    for prefix in NUMERIC_ID_PREFIXES {
+        for (index, _) in text.match_indices(prefix) {
+            let digits = text[index + prefix.len()..]
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap_or_default();
+
+            if let Ok(numeric_id) = digits.parse() {
+                references.numeric_ids.insert(numeric_id);
+            }
+        }
+    }
+}
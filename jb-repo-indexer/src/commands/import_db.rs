@@ -0,0 +1,23 @@
+use crate::args::{ImportDbArgs, IndexerArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+use flate2::read::GzDecoder;
+use std::io::BufReader;
+
+pub async fn run(args: &IndexerArgs, command: &ImportDbArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    database.ensure_writable("import a dump")?;
+
+    let file = std::fs::File::open(&command.path)?;
+    let rows = match command.path.extension().is_some_and(|v| v == "gz") {
+        true => {
+            database
+                .import_dump(BufReader::new(GzDecoder::new(file)))
+                .await?
+        }
+        false => database.import_dump(BufReader::new(file)).await?,
+    };
+
+    tracing::info!("Imported {} rows from {}", rows, command.path.display());
+    Ok(())
+}
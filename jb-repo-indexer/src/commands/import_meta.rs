@@ -0,0 +1,10 @@
+use crate::args::{ImportMetaArgs, IndexerArgs};
+use crate::error::IndexerError;
+
+pub async fn run(args: &IndexerArgs, command: &ImportMetaArgs) -> Result<(), IndexerError> {
+    tracing::info!("Importing metadata from {:?}...", command.source);
+    crate::meta::import_meta(args, command).await?;
+    tracing::info!("Done.");
+
+    Ok(())
+}
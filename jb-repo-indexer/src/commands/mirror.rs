@@ -0,0 +1,108 @@
+use crate::api::JetbrainsRepoApi;
+use crate::args::{IndexerArgs, MirrorArgs};
+use crate::artifacts::{ArtifactCache, algorithm_directory};
+use crate::db::{CachedArtifact, Database};
+use crate::error::IndexerError;
+use futures::StreamExt as _;
+use std::collections::HashSet;
+
+pub async fn run(args: &IndexerArgs, command: &MirrorArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    database.ensure_writable("mirror")?;
+
+    // Downloads are retained in the mirror, which is content-addressed the
+    // same way as the artifact cache
+    let mut api_args = command.api.clone();
+    api_args.artifact_cache = Some(command.directory.clone());
+    let repo = JetbrainsRepoApi::new(&api_args, args.chaos.clone())?;
+    let mirror = ArtifactCache::new(&command.directory);
+
+    let plugins = command.plugins.iter().collect::<HashSet<_>>();
+    let artifacts = database
+        .get_published_artifacts()
+        .await?
+        .into_iter()
+        .filter(|v| plugins.is_empty() || plugins.contains(&v.plugin_xml_id))
+        .collect::<Vec<_>>();
+
+    tracing::info!("Mirroring {} artifacts", artifacts.len());
+
+    let outcomes = futures::stream::iter(artifacts)
+        .map(|artifact| {
+            let (repo, mirror, database) = (&repo, &mirror, &database);
+
+            async move {
+                let downloaded = mirror_artifact(repo, mirror, &artifact).await?;
+
+                // Its download url changes once the plugin is generated again
+                if downloaded {
+                    database.mark_update_plugins_dirty(artifact.update_id).await?;
+                }
+
+                Ok::<_, IndexerError>(downloaded)
+            }
+        })
+        .buffer_unordered(command.api.max_parallel_large_requests.get())
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut downloaded = 0;
+    let mut failed = 0;
+
+    for outcome in outcomes {
+        match outcome {
+            Ok(true) => downloaded += 1,
+            Ok(false) => {}
+            Err(err) => {
+                tracing::error!("- {}", err);
+                failed += 1;
+            }
+        }
+    }
+
+    tracing::info!("Downloaded {} new artifacts", downloaded);
+
+    if failed > 0 {
+        return Err(IndexerError::IncompleteData(format!(
+            "{} artifacts could not be mirrored",
+            failed
+        )));
+    }
+
+    Ok(())
+}
+
+/// Download an artifact into the mirror, returning whether it was missing.
+async fn mirror_artifact(
+    repo: &JetbrainsRepoApi,
+    mirror: &ArtifactCache,
+    artifact: &CachedArtifact,
+) -> Result<bool, IndexerError> {
+    if mirror.contains(&artifact.hash_algorithm, &artifact.hash).await? {
+        return Ok(false);
+    }
+
+    // We can only compute SHA-256 hashes ourselves
+    if algorithm_directory(&artifact.hash_algorithm) != "sha256" {
+        tracing::warn!(
+            "Skipping update {}, {} hashes can not be mirrored",
+            artifact.update_id,
+            artifact.hash_algorithm
+        );
+        return Ok(false);
+    }
+
+    let url = repo.absolute_url(&artifact.download_url).map_err(|e| {
+        IndexerError::IncompleteData(format!(
+            "update {} has an invalid download url: {}",
+            artifact.update_id, e
+        ))
+    })?;
+
+    let hash = repo.download_hash(&url).await?;
+    if hash.value != artifact.hash {
+        return Err(IndexerError::HashMismatch(artifact.update_id));
+    }
+
+    Ok(true)
+}
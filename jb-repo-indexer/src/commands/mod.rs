@@ -0,0 +1,19 @@
+pub mod backup;
+pub mod check_output;
+pub mod closure;
+pub mod drift_check;
+pub mod export_db;
+pub mod failures;
+pub mod generate;
+pub mod import_config;
+pub mod import_db;
+pub mod import_meta;
+pub mod mirror;
+pub mod prune;
+pub mod query;
+pub mod restore;
+pub mod serve;
+pub mod sync;
+pub mod trends;
+pub mod verify;
+pub mod verify_signature;
@@ -0,0 +1,23 @@
+use crate::args::{IndexerArgs, PruneArgs};
+use crate::db::Database;
+use crate::error::IndexerError;
+
+pub async fn run(args: &IndexerArgs, command: &PruneArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    database.ensure_writable("prune")?;
+
+    let counts = database.prune(command.keep_versions).await?;
+    tracing::info!(
+        "Deleted {} versions, {} updates and {} rows of deleted updates",
+        counts.versions,
+        counts.updates,
+        counts.update_rows
+    );
+
+    if !command.no_vacuum {
+        tracing::info!("Compacting database...");
+        database.vacuum().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use crate::args::{IndexerArgs, QueryArgs};
+use crate::db::{CachedPluginVersion, Database};
+use crate::error::IndexerError;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct PluginReport {
+    xml_id: String,
+    numeric_id: u64,
+    dirty: bool,
+    versions: Vec<VersionReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct VersionReport {
+    version: String,
+    channel: String,
+    update: UpdateReport,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateQueryReport {
+    /// Versions published as this update, usually just one.
+    versions: Vec<PublishedAs>,
+    update: UpdateReport,
+}
+
+#[derive(Debug, Serialize)]
+struct PublishedAs {
+    plugin_xml_id: String,
+    version: String,
+    channel: String,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateReport {
+    id: u64,
+    stale: bool,
+    download_url: Option<String>,
+    file_name: Option<String>,
+    hash_algorithm: Option<String>,
+    hash: Option<String>,
+    sri_hash: Option<String>,
+    size: Option<u64>,
+    since_build: Option<String>,
+    until_build: Option<String>,
+    dependencies: Vec<DependencyReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyReport {
+    xml_id: String,
+    optional: bool,
+}
+
+pub async fn run(args: &IndexerArgs, command: &QueryArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+
+    let output = match (&command.xml_id, command.update_id) {
+        (_, Some(update_id)) => {
+            let report = UpdateQueryReport {
+                versions: database
+                    .get_versions_for_update(update_id)
+                    .await?
+                    .into_iter()
+                    .map(|v| PublishedAs {
+                        plugin_xml_id: v.plugin_xml_id,
+                        version: v.version,
+                        channel: v.channel,
+                    })
+                    .collect(),
+                update: update_report(&database, update_id).await?,
+            };
+
+            match command.json {
+                true => serde_json::to_string_pretty(&report)? + "\n",
+                false => format_update_query(&report),
+            }
+        }
+        (Some(xml_id), None) => {
+            let plugin = database
+                .get_plugin(xml_id)
+                .await?
+                .ok_or(IndexerError::NotFound)?;
+
+            let mut versions = database.get_versions_for_plugin(xml_id).await?;
+            versions.sort_by_key(|v| std::cmp::Reverse(v.update_id));
+
+            let report = PluginReport {
+                dirty: database.get_dirty_plugins().await?.contains(xml_id),
+                versions: version_reports(&database, versions).await?,
+                xml_id: plugin.xml_id,
+                numeric_id: plugin.numeric_id,
+            };
+
+            match command.json {
+                true => serde_json::to_string_pretty(&report)? + "\n",
+                false => format_plugin(&report),
+            }
+        }
+        (None, None) => unreachable!("clap requires either a plugin or an update"),
+    };
+
+    print!("{}", output);
+    Ok(())
+}
+
+async fn version_reports(
+    database: &Database,
+    versions: Vec<CachedPluginVersion>,
+) -> Result<Vec<VersionReport>, IndexerError> {
+    let mut reports = Vec::with_capacity(versions.len());
+
+    for version in versions {
+        reports.push(VersionReport {
+            update: update_report(database, version.update_id).await?,
+            version: version.version,
+            channel: version.channel,
+        });
+    }
+
+    Ok(reports)
+}
+
+async fn update_report(database: &Database, update_id: u64) -> Result<UpdateReport, IndexerError> {
+    let update = database.get_update(update_id).await?;
+    let dependencies = database
+        .get_update_dependencies(update_id)
+        .await?
+        .into_iter()
+        .map(|v| DependencyReport {
+            xml_id: v.dependency_xml_id,
+            optional: v.optional,
+        })
+        .collect();
+
+    let sri_hash = match (&update.hash_algorithm, &update.hash) {
+        (Some(algorithm), Some(hash)) => crate::meta::sri_hash(algorithm, hash),
+        _ => None,
+    };
+
+    Ok(UpdateReport {
+        id: update.id,
+        stale: update.stale,
+        download_url: update.download_url,
+        file_name: update.file_name,
+        hash_algorithm: update.hash_algorithm,
+        hash: update.hash.map(crate::hex::encode),
+        sri_hash,
+        size: update.size,
+        since_build: update.since_build,
+        until_build: update.until_build,
+        dependencies,
+    })
+}
+
+fn format_plugin(report: &PluginReport) -> String {
+    let mut output = format!(
+        "{} (#{}){}\n",
+        report.xml_id,
+        report.numeric_id,
+        if report.dirty { ", dirty" } else { "" }
+    );
+
+    if report.versions.is_empty() {
+        output.push_str("  no versions\n");
+    }
+
+    for version in &report.versions {
+        output.push_str(&format!(
+            "  {} [{}] update {}\n",
+            version.version, version.channel, version.update.id
+        ));
+        output.push_str(&format_update(&version.update));
+    }
+
+    output
+}
+
+fn format_update_query(report: &UpdateQueryReport) -> String {
+    let mut output = format!("update {}\n", report.update.id);
+
+    if report.versions.is_empty() {
+        output.push_str("  not published as any version\n");
+    }
+
+    for version in &report.versions {
+        output.push_str(&format!(
+            "  published as {}@{} [{}]\n",
+            version.plugin_xml_id, version.version, version.channel
+        ));
+    }
+
+    output + &format_update(&report.update)
+}
+
+fn format_update(update: &UpdateReport) -> String {
+    let mut output = String::new();
+
+    let unknown = || "-".to_owned();
+    output.push_str(&format!(
+        "    url:   {}\n",
+        update.download_url.clone().unwrap_or_else(unknown)
+    ));
+    output.push_str(&format!(
+        "    file:  {}\n",
+        update.file_name.clone().unwrap_or_else(unknown)
+    ));
+    output.push_str(&format!(
+        "    hash:  {} {}\n",
+        update.hash_algorithm.clone().unwrap_or_else(unknown),
+        update.hash.clone().unwrap_or_else(unknown)
+    ));
+    output.push_str(&format!(
+        "    sri:   {}\n",
+        update.sri_hash.clone().unwrap_or_else(unknown)
+    ));
+    output.push_str(&format!(
+        "    size:  {}\n",
+        update.size.map(|v| v.to_string()).unwrap_or_else(unknown)
+    ));
+    output.push_str(&format!(
+        "    stale: {}\n",
+        if update.stale { "yes" } else { "no" }
+    ));
+    output.push_str(&format!(
+        "    builds: since {}, until {}\n",
+        update.since_build.clone().unwrap_or_else(unknown),
+        update.until_build.clone().unwrap_or_else(unknown)
+    ));
+
+    for dependency in &update.dependencies {
+        output.push_str(&format!(
+            "    depends on {}{}\n",
+            dependency.xml_id,
+            if dependency.optional { " (optional)" } else { "" }
+        ));
+    }
+
+    output
+}
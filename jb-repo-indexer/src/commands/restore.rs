@@ -0,0 +1,28 @@
+use crate::args::{IndexerArgs, RestoreArgs};
+use crate::commands::backup::{remove_file_if_exists, with_suffix};
+use crate::db::Database;
+use crate::error::IndexerError;
+
+pub async fn run(args: &IndexerArgs, command: &RestoreArgs) -> Result<(), IndexerError> {
+    if args.read_only {
+        return Err(IndexerError::ReadOnly("restore"));
+    }
+
+    Database::check_snapshot(&command.path).await?;
+
+    let staging = with_suffix(&args.database, ".restoring");
+    tokio::fs::copy(&command.path, &staging).await?;
+
+    // The journal of the replaced database must not be applied to the snapshot
+    remove_file_if_exists(&with_suffix(&args.database, "-wal")).await?;
+    remove_file_if_exists(&with_suffix(&args.database, "-shm")).await?;
+    tokio::fs::rename(&staging, &args.database).await?;
+
+    tracing::info!(
+        "Restored {} from {}",
+        args.database.display(),
+        command.path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use crate::args::{IndexerArgs, ServeArgs};
+use crate::error::IndexerError;
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::net::TcpListener;
+
+pub async fn run(_args: &IndexerArgs, command: &ServeArgs) -> Result<(), IndexerError> {
+    let listener = TcpListener::bind(command.listen).await?;
+    tracing::info!(
+        "Serving {} on http://{}",
+        command.directory.display(),
+        command.listen
+    );
+
+    let directory = Arc::new(command.directory.clone());
+    let cache_control = format!("public, max-age={}", command.max_age);
+
+    crate::server::accept_loop(listener, move |request| {
+        let directory = directory.clone();
+        let cache_control = cache_control.clone();
+
+        async move {
+            match handle(&directory, &cache_control, request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::error!("Failed to serve request: {}", err);
+                    empty(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+    })
+    .await;
+
+    Ok(())
+}
+
+async fn handle(
+    directory: &Path,
+    cache_control: &str,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, IndexerError> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(empty(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let Some(path) = resolve(directory, request.uri().path()) else {
+        return Ok(empty(StatusCode::NOT_FOUND));
+    };
+
+    let metadata = match tokio::fs::metadata(&path).await {
+        Ok(v) if v.is_file() => v,
+        Ok(_) => return Ok(empty(StatusCode::NOT_FOUND)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(empty(StatusCode::NOT_FOUND));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // Files are replaced rather than modified, so size and modification
+    // time identify the content well enough
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    // Already compressed files are served as they are
+    let gzip = !is_compressed(&path)
+        && request
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.split(',').any(|v| v.trim().starts_with("gzip")));
+
+    // Each encoding is a different representation and needs its own tag
+    let etag = match gzip {
+        true => format!("\"{:x}-{:x}-gzip\"", metadata.len(), modified),
+        false => format!("\"{:x}-{:x}\"", metadata.len(), modified),
+    };
+
+    let response = Response::builder()
+        .header(ETAG, &etag)
+        .header(CACHE_CONTROL, cache_control)
+        .header(VARY, ACCEPT_ENCODING.as_str());
+
+    let not_modified = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|v| v.trim() == etag || v.trim() == "*"));
+
+    if not_modified {
+        return Ok(response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Full::default())
+            .unwrap());
+    }
+
+    let mut data = tokio::fs::read(&path).await?;
+    let mut response = response.header(CONTENT_TYPE, content_type(&path));
+
+    if gzip {
+        data = tokio::task::spawn_blocking(move || {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            encoder.finish()
+        })
+        .await
+        .unwrap()?;
+
+        response = response.header(CONTENT_ENCODING, "gzip");
+    }
+
+    let body = match *request.method() {
+        Method::HEAD => Full::default(),
+        _ => Full::new(Bytes::from(data)),
+    };
+
+    Ok(response.body(body).unwrap())
+}
+
+/// Map a request path into the served directory, refusing anything which
+/// could escape it.
+fn resolve(directory: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = directory.to_path_buf();
+
+    for segment in request_path.split('/').filter(|v| !v.is_empty()) {
+        if segment == "." || segment == ".." || segment.contains(['\\', '%']) {
+            return None;
+        }
+
+        path.push(segment);
+    }
+
+    if path == directory {
+        path.push("index.json");
+    }
+
+    Some(path)
+}
+
+fn is_compressed(path: &Path) -> bool {
+    matches!(path.extension().and_then(|v| v.to_str()), Some("gz" | "zst"))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some("json") => "application/json",
+        Some("gz") => "application/gzip",
+        Some("zst") => "application/zstd",
+        _ => "application/octet-stream",
+    }
+}
+
+fn empty(status: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::default())
+        .unwrap()
+}
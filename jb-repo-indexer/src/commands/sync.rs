@@ -0,0 +1,94 @@
+use crate::args::{IndexerArgs, SyncArgs};
+use crate::db::DatabaseChanges;
+use crate::error::IndexerError;
+use crate::meta::MetadataProcessor;
+use crate::statistics::Statistics;
+
+pub async fn run(args: &IndexerArgs, command: &SyncArgs) -> Result<(), IndexerError> {
+    let (statistics, changes) = match sync(args, command).await {
+        Ok(v) => v,
+        Err(err) => {
+            if let Some(url) = &command.notify.webhook_url
+                && let Err(notify_err) = crate::notify::notify_error(&command.notify, &command.api, &err).await
+            {
+                tracing::error!("Failed to notify webhook {}: {}", url, notify_err);
+            }
+
+            return Err(err);
+        }
+    };
+
+    if let Some(url) = &command.notify.webhook_url
+        && let Err(err) = crate::notify::notify(&command.notify, &command.api, &statistics, changes.as_ref()).await
+    {
+        tracing::error!("Failed to notify webhook {}: {}", url, err);
+    }
+
+    if statistics.interrupted {
+        return Err(IndexerError::Interrupted);
+    }
+
+    match statistics.first_failure_in(&command.fail_on) {
+        Some(category) => Err(IndexerError::TasksFailed {
+            category,
+            count: statistics.failure_counts()[&category],
+        }),
+        None => Ok(()),
+    }
+}
+
+async fn sync(
+    args: &IndexerArgs,
+    command: &SyncArgs,
+) -> Result<(Statistics, Option<DatabaseChanges>), IndexerError> {
+    let processor = MetadataProcessor::new(args, command).await?;
+
+    if let Some(address) = command.metrics.metrics_listen {
+        processor.serve_metrics(address).await?;
+    }
+
+    tracing::info!("Starting to sync plugin metadata...");
+    let statistics = processor.sync_plugin_metadata().await?;
+    tracing::info!("Done.");
+
+    if !statistics.problems.is_empty() {
+        tracing::warn!("Problems encountered:");
+        for problem in &statistics.problems {
+            tracing::warn!("- {}: {}", problem.task_name, problem.error);
+        }
+    }
+
+    if !statistics.failures.is_empty() {
+        tracing::error!("Failed tasks:");
+        for failure in &statistics.failures {
+            tracing::error!("- {}: {}", failure.task_name, failure.error);
+        }
+    }
+
+    tracing::info!("Encountered problems: {}", statistics.problems.len());
+    tracing::info!("Failed tasks: {}", statistics.failures.len());
+    for (category, count) in statistics.failure_counts() {
+        tracing::info!("- {:?}: {}", category, count);
+    }
+    tracing::info!("Succeeded tasks: {}", statistics.successful_tasks);
+    if statistics.interrupted {
+        tracing::warn!("Cancelled tasks: {}", statistics.cancelled_tasks);
+    }
+
+    processor.http_statistics().log_summary();
+    statistics.log_timings();
+
+    if let Some(url) = &command.metrics.metrics_push_url
+        && let Err(err) = crate::metrics::push(url, processor.render_metrics(Some(&statistics))).await
+    {
+        tracing::error!("Failed to push metrics to {}: {}", url, err);
+    }
+
+    if let Some(path) = &command.report_out {
+        statistics.write_report(path).await?;
+        tracing::info!("Wrote sync report to {}", path.display());
+    }
+
+    let changes = processor.finish_dry_run().await?;
+    Ok((statistics, changes))
+}
@@ -0,0 +1,140 @@
+use crate::args::{IndexerArgs, ReportFormat, TrendsArgs};
+use crate::db::{CachedSyncRun, Database};
+use crate::error::IndexerError;
+use crate::meta::unix_timestamp;
+use serde::Serialize;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize)]
+struct TrendsReport {
+    since: i64,
+    runs: usize,
+    plugin_growth: i64,
+    version_growth: i64,
+    average_failure_rate: f64,
+    average_hash_changes: f64,
+    points: Vec<TrendPoint>,
+}
+
+/// The state after a single sync run.
+#[derive(Debug, Serialize)]
+struct TrendPoint {
+    started_at: i64,
+    duration: i64,
+    plugins: u64,
+    versions: u64,
+    plugin_growth: i64,
+    version_growth: i64,
+    average_artifact_size: Option<f64>,
+    hash_changes: u64,
+    problems: u64,
+    failure_rate: f64,
+}
+
+pub async fn run(args: &IndexerArgs, command: &TrendsArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+
+    let since = unix_timestamp() - i64::from(command.days) * SECONDS_PER_DAY;
+    let runs = database.get_sync_runs_since(since).await?;
+
+    let points = trend_points(&runs);
+
+    let output = match command.format {
+        ReportFormat::Json => {
+            let report = TrendsReport {
+                since,
+                runs: points.len(),
+                plugin_growth: points.iter().map(|p| p.plugin_growth).sum(),
+                version_growth: points.iter().map(|p| p.version_growth).sum(),
+                average_failure_rate: average(points.iter().map(|p| p.failure_rate)),
+                average_hash_changes: average(points.iter().map(|p| p.hash_changes as f64)),
+                points,
+            };
+
+            serde_json::to_string_pretty(&report)? + "\n"
+        }
+        ReportFormat::Csv => to_csv(&points),
+    };
+
+    match &command.out {
+        Some(path) => tokio::fs::write(path, output).await?,
+        None => print!("{}", output),
+    }
+
+    Ok(())
+}
+
+/// Growth is relative to the previous run in the window, so the first run
+/// never shows any.
+fn trend_points(runs: &[CachedSyncRun]) -> Vec<TrendPoint> {
+    let mut previous: Option<&CachedSyncRun> = None;
+    let mut points = Vec::with_capacity(runs.len());
+
+    for run in runs {
+        let (plugin_growth, version_growth) = match previous {
+            Some(previous) => (
+                run.plugins as i64 - previous.plugins as i64,
+                run.versions as i64 - previous.versions as i64,
+            ),
+            None => (0, 0),
+        };
+
+        let tasks = run.succeeded_tasks + run.failed_tasks;
+        let failure_rate = if tasks == 0 {
+            0.0
+        } else {
+            run.failed_tasks as f64 / tasks as f64
+        };
+
+        points.push(TrendPoint {
+            started_at: run.started_at,
+            duration: run.finished_at - run.started_at,
+            plugins: run.plugins,
+            versions: run.versions,
+            plugin_growth,
+            version_growth,
+            average_artifact_size: run.average_artifact_size,
+            hash_changes: run.hash_changes,
+            problems: run.problems,
+            failure_rate,
+        });
+
+        previous = Some(run);
+    }
+
+    points
+}
+
+fn average(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0usize), |(sum, count), v| (sum + v, count + 1));
+
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
+
+fn to_csv(points: &[TrendPoint]) -> String {
+    let mut csv = String::from(
+        "started_at,duration,plugins,versions,plugin_growth,version_growth,average_artifact_size,hash_changes,problems,failure_rate\n",
+    );
+
+    for point in points {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{:.4}\n",
+            point.started_at,
+            point.duration,
+            point.plugins,
+            point.versions,
+            point.plugin_growth,
+            point.version_growth,
+            point
+                .average_artifact_size
+                .map(|v| format!("{:.0}", v))
+                .unwrap_or_default(),
+            point.hash_changes,
+            point.problems,
+            point.failure_rate,
+        ));
+    }
+
+    csv
+}
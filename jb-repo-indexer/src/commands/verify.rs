@@ -0,0 +1,96 @@
+use crate::api::JetbrainsRepoApi;
+use crate::args::{IndexerArgs, VerifyArgs};
+use crate::error::IndexerError;
+use crate::meta::{PublishedVersion, read_latest_versions, read_published_plugins, sri_hash};
+use futures::StreamExt as _;
+use std::hash::{BuildHasher, RandomState};
+
+pub async fn run(args: &IndexerArgs, command: &VerifyArgs) -> Result<(), IndexerError> {
+    let repo = JetbrainsRepoApi::new(&command.api, args.chaos.clone())?;
+
+    let plugins = match command.plugins.is_empty() {
+        true => sample(read_published_plugins(&command.directory).await?, command.sample),
+        false => command.plugins.clone(),
+    };
+
+    let mut versions = Vec::new();
+    for xml_id in &plugins {
+        versions.extend(read_latest_versions(&command.directory, xml_id).await?);
+    }
+
+    tracing::info!(
+        "Verifying {} versions of {} plugins",
+        versions.len(),
+        plugins.len()
+    );
+
+    let issues = futures::stream::iter(versions)
+        .map(|version| {
+            let repo = &repo;
+            async move { verify_version(repo, &version).await }
+        })
+        .buffer_unordered(command.api.max_parallel_large_requests.get())
+        .filter_map(|v| async move { v })
+        .collect::<Vec<_>>()
+        .await;
+
+    if issues.is_empty() {
+        tracing::info!("All sampled artifacts match their published hashes");
+        return Ok(());
+    }
+
+    for issue in &issues {
+        tracing::error!("- {}", issue);
+    }
+
+    Err(IndexerError::OutputValidation(format!(
+        "{} published artifacts could not be verified",
+        issues.len()
+    )))
+}
+
+/// Pick up to `count` plugins at random.
+fn sample(plugins: Vec<String>, count: usize) -> Vec<String> {
+    let state = RandomState::new();
+
+    let mut plugins = plugins
+        .into_iter()
+        .map(|v| (state.hash_one(&v), v))
+        .collect::<Vec<_>>();
+    plugins.sort_unstable();
+
+    plugins.into_iter().take(count).map(|(_, v)| v).collect()
+}
+
+/// Download a published artifact, returning the issue if it does not match.
+async fn verify_version(repo: &JetbrainsRepoApi, version: &PublishedVersion) -> Option<String> {
+    let name = format!("{}@{}", version.xml_id, version.version);
+
+    if !version.hash.starts_with("sha256-") {
+        tracing::warn!("Skipping {}, only SHA-256 hashes can be verified", name);
+        return None;
+    }
+
+    let url = match repo.absolute_url(&version.download_url) {
+        Ok(v) => v,
+        Err(err) => return Some(format!("{} has an invalid download url: {}", name, err)),
+    };
+
+    let hash = match repo.download_hash(&url).await {
+        Ok(v) => v,
+        Err(err) => return Some(format!("{} could not be downloaded: {}", name, err)),
+    };
+
+    let actual = sri_hash(&hash.algorithm, &hash.value);
+    if actual.as_deref() != Some(version.hash.as_str()) {
+        return Some(format!(
+            "{} is published as {} but downloads as {}",
+            name,
+            version.hash,
+            actual.unwrap_or_default()
+        ));
+    }
+
+    tracing::debug!("{} matches its published hash", name);
+    None
+}
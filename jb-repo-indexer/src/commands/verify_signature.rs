@@ -0,0 +1,22 @@
+use crate::args::VerifySignatureArgs;
+use crate::error::IndexerError;
+
+pub async fn run(command: &VerifySignatureArgs) -> Result<(), IndexerError> {
+    let issues =
+        crate::meta::verify_tree_signatures(&command.directory, command.public_key.as_deref()).await?;
+
+    if issues.is_empty() {
+        tracing::info!("All signatures in {} are valid", command.directory.display());
+        return Ok(());
+    }
+
+    for issue in &issues {
+        tracing::error!("- {}", issue);
+    }
+
+    Err(IndexerError::OutputValidation(format!(
+        "found {} signature problems in {}",
+        issues.len(),
+        command.directory.display()
+    )))
+}
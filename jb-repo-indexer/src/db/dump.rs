@@ -0,0 +1,228 @@
+use crate::db::Database;
+use crate::error::IndexerError;
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use futures::{TryStreamExt as _, future};
+use libsql::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
+
+/// Bumped whenever dumps change in a way older versions can't import.
+const DUMP_FORMAT_VERSION: u32 = 1;
+
+/// Tables in the order their rows are dumped, so rows referenced by foreign
+/// keys are imported first.
+const DUMP_TABLES: &[&str] = &[
+    "plugins",
+    "updates",
+    "versions",
+    "update_dependencies",
+    "update_dependency_configs",
+    "update_verifications",
+    "hash_drift",
+    "archived_versions",
+    "plugin_screenshots",
+    "plugin_products",
+    "plugin_tags",
+    "sync_runs",
+    "sync_failures",
+    "sync_state",
+];
+
+/// A line of a dump, which is a header followed by one line per row.
+///
+/// Rows are keyed by column name, so dumps can be imported into databases
+/// with more or fewer columns.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DumpLine {
+    Header { format_version: u32 },
+    Row {
+        table: String,
+        values: BTreeMap<String, DumpValue>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum DumpValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob { base64: String },
+}
+
+impl From<Value> for DumpValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Integer(v) => Self::Integer(v),
+            Value::Real(v) => Self::Real(v),
+            Value::Text(v) => Self::Text(v),
+            Value::Blob(v) => Self::Blob {
+                base64: BASE64_STANDARD.encode(v),
+            },
+        }
+    }
+}
+
+impl TryFrom<DumpValue> for Value {
+    type Error = IndexerError;
+
+    fn try_from(value: DumpValue) -> Result<Self, Self::Error> {
+        Ok(match value {
+            DumpValue::Null => Value::Null,
+            DumpValue::Integer(v) => Value::Integer(v),
+            DumpValue::Real(v) => Value::Real(v),
+            DumpValue::Text(v) => Value::Text(v),
+            DumpValue::Blob { base64 } => Value::Blob(BASE64_STANDARD.decode(base64)?),
+        })
+    }
+}
+
+impl Database {
+    /// Write all rows as newline delimited JSON, returning how many rows
+    /// were written.
+    ///
+    /// All tables are read in one transaction, so the dump is consistent
+    /// even while another process syncs.
+    pub async fn export_dump(&self, writer: &mut impl Write) -> Result<usize, IndexerError> {
+        let connection = self.connection().await?;
+        let tx = connection.transaction().await?;
+        let mut count = 0;
+
+        write_line(writer, &DumpLine::Header {
+            format_version: DUMP_FORMAT_VERSION,
+        })?;
+
+        for table in DUMP_TABLES {
+            let mut rows = tx.query(&format!("SELECT * FROM {}", table), ()).await?;
+
+            while let Some(row) = rows.next().await? {
+                let mut values = BTreeMap::new();
+                for i in 0..rows.column_count() {
+                    let column = rows.column_name(i).unwrap_or_default().to_owned();
+                    values.insert(column, DumpValue::from(row.get_value(i)?));
+                }
+
+                write_line(writer, &DumpLine::Row {
+                    table: table.to_string(),
+                    values,
+                })?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Import a dump written by [`Database::export_dump`] into this database,
+    /// which must not contain any plugins yet.
+    ///
+    /// Columns the schema doesn't know are dropped, missing ones get their
+    /// default value.
+    pub async fn import_dump(&self, reader: impl BufRead) -> Result<usize, IndexerError> {
+        let connection = self.connection().await?;
+        let tx = connection.transaction().await?;
+
+        let existing = tx
+            .query("SELECT COUNT(*) FROM plugins", ())
+            .await?
+            .next()
+            .await?
+            .map(|r| r.get::<i64>(0))
+            .transpose()?
+            .unwrap_or_default();
+        if existing > 0 {
+            return Err(IndexerError::InvalidDump(format!(
+                "the database already contains {} plugins",
+                existing
+            )));
+        }
+
+        let mut columns = HashMap::<String, HashSet<String>>::new();
+        let mut ignored = HashSet::new();
+        let mut count = 0;
+
+        let mut lines = reader.lines();
+        match lines.next().transpose()?.map(|v| serde_json::from_str(&v)).transpose()? {
+            Some(DumpLine::Header { format_version }) if format_version <= DUMP_FORMAT_VERSION => {}
+            Some(DumpLine::Header { format_version }) => {
+                return Err(IndexerError::InvalidDump(format!(
+                    "format version {} is newer than the supported {}",
+                    format_version, DUMP_FORMAT_VERSION
+                )));
+            }
+            _ => return Err(IndexerError::InvalidDump("missing header".into())),
+        }
+
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let DumpLine::Row { table, values } = serde_json::from_str(&line)? else {
+                return Err(IndexerError::InvalidDump("header in the middle of the dump".into()));
+            };
+
+            if !DUMP_TABLES.contains(&table.as_str()) {
+                if ignored.insert(table.clone()) {
+                    tracing::warn!("Skipping rows of unknown table {}", table);
+                }
+                continue;
+            }
+
+            if !columns.contains_key(&table) {
+                let known = tx
+                    .query("SELECT name FROM pragma_table_info(?1)", [table.as_str()])
+                    .await?
+                    .into_stream()
+                    .and_then(|r| future::ready(r.get::<String>(0)))
+                    .try_collect()
+                    .await?;
+                columns.insert(table.clone(), known);
+            }
+
+            let mut names = Vec::new();
+            let mut params = Vec::new();
+            for (column, value) in values {
+                if !columns[&table].contains(&column) {
+                    if ignored.insert(format!("{}.{}", table, column)) {
+                        tracing::warn!("Skipping unknown column {}.{}", table, column);
+                    }
+                    continue;
+                }
+
+                names.push(column);
+                params.push(Value::try_from(value)?);
+            }
+
+            let placeholders = (1..=names.len())
+                .map(|i| format!("?{}", i))
+                .collect::<Vec<_>>();
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    table,
+                    names.join(", "),
+                    placeholders.join(", ")
+                ),
+                libsql::params_from_iter(params),
+            )
+            .await?;
+            count += 1;
+        }
+
+        tx.commit().await?;
+        Ok(count)
+    }
+}
+
+fn write_line(writer: &mut impl Write, line: &DumpLine) -> Result<(), IndexerError> {
+    serde_json::to_writer(&mut *writer, line)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
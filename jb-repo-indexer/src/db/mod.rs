@@ -1,16 +1,75 @@
+mod dump;
 mod models;
 pub use models::*;
 
 use crate::args::IndexerArgs;
+use crate::chaos::Chaos;
 use crate::error::IndexerError;
-use futures::{Stream, TryFutureExt, TryStreamExt, future, StreamExt};
+use futures::{TryFutureExt, TryStreamExt, future};
 use libsql::{Connection, Row};
 use serde::de::DeserializeOwned;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
 
 #[derive(Clone)]
 pub struct Database {
     connection: Connection,
+    chaos: Option<Arc<Chaos>>,
+    batch_lock: Arc<Mutex<()>>,
+    in_batch: bool,
+    read_only: bool,
+}
+
+/// Writes committed together, see [`Database::begin_batch`].
+///
+/// Statements of the batch are issued through it, the database it was begun
+/// on waits for the batch to end.
+pub struct WriteBatch {
+    database: Database,
+    transaction: libsql::Transaction,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl WriteBatch {
+    pub async fn commit(self) -> Result<(), IndexerError> {
+        self.transaction.commit().await?;
+        Ok(())
+    }
+}
+
+impl Deref for WriteBatch {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.database
+    }
+}
+
+/// The connection, kept from batches while a statement runs on it.
+struct HeldConnection<'a> {
+    connection: &'a Connection,
+    _guard: Option<MutexGuard<'a, ()>>,
+}
+
+impl Deref for HeldConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection
+    }
+}
+
+/// A URI opening a database file read-only, for attaching it.
+fn read_only_uri(path: &Path) -> Result<String, IndexerError> {
+    let path = std::path::absolute(path)?;
+    let mut uri = url::Url::from_file_path(&path)
+        .map_err(|()| IndexerError::IncompleteData(format!("invalid path {}", path.display())))?;
+    uri.set_query(Some("mode=ro"));
+
+    Ok(uri.to_string())
 }
 
 fn map_row_de<T: DeserializeOwned>(r: Row) -> impl Future<Output = Result<T, IndexerError>> {
@@ -32,6 +91,10 @@ impl Database {
     pub async fn setup(args: &IndexerArgs) -> Result<Self, IndexerError> {
         tracing::debug!("Setting up database at {}", args.database.display());
 
+        if args.read_only {
+            return Self::open_read_only(args).await;
+        }
+
         if let Some(parent) = args.database.parent() {
             tokio::fs::create_dir_all(parent).await.map_err(|e| {
                 tracing::error!("Failed to create database directory: {}", e);
@@ -52,7 +115,86 @@ impl Database {
         tracing::debug!("Connected to database");
         Self::ensure_db_structure(&connection).await?;
 
-        Ok(Self { connection })
+        Ok(Self {
+            connection,
+            chaos: args.chaos.clone().map(|v| Arc::new(Chaos::new(v))),
+            batch_lock: Arc::new(Mutex::new(())),
+            in_batch: false,
+            read_only: false,
+        })
+    }
+
+    /// Open an existing database without touching its schema or journal.
+    ///
+    /// SQLite rejects every write to it.
+    async fn open_read_only(args: &IndexerArgs) -> Result<Self, IndexerError> {
+        let db = libsql::Builder::new_local(&args.database)
+            .flags(libsql::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .build()
+            .await?;
+
+        let connection = db.connect()?;
+        tracing::debug!("Connected to database read-only");
+
+        Ok(Self {
+            connection,
+            chaos: args.chaos.clone().map(|v| Arc::new(Chaos::new(v))),
+            batch_lock: Arc::new(Mutex::new(())),
+            in_batch: false,
+            read_only: true,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail early if the database is read-only, instead of at the first write.
+    pub fn ensure_writable(&self, action: &'static str) -> Result<(), IndexerError> {
+        match self.read_only {
+            true => Err(IndexerError::ReadOnly(action)),
+            false => Ok(()),
+        }
+    }
+
+    /// Start a transaction, so the following writes are synced to disk once
+    /// on commit instead of once per statement.
+    ///
+    /// The connection is shared, so statements of other tasks wait until the
+    /// batch ends instead of becoming part of it. Only statements issued
+    /// through the batch belong to it, dropping it without committing rolls
+    /// them back.
+    pub async fn begin_batch(&self) -> Result<WriteBatch, IndexerError> {
+        let guard = self.batch_lock.clone().lock_owned().await;
+        let transaction = self.connection.transaction().await?;
+
+        Ok(WriteBatch {
+            database: Self {
+                in_batch: true,
+                ..self.clone()
+            },
+            transaction,
+            _guard: guard,
+        })
+    }
+
+    /// The connection to run a statement on, possibly failing by injection.
+    ///
+    /// Waits for an open batch to end, unless the statement belongs to it.
+    async fn connection(&self) -> Result<HeldConnection<'_>, IndexerError> {
+        if let Some(chaos) = &self.chaos {
+            chaos.inject_database_error()?;
+        }
+
+        let guard = match self.in_batch {
+            true => None,
+            false => Some(self.batch_lock.lock().await),
+        };
+
+        Ok(HeldConnection {
+            connection: &self.connection,
+            _guard: guard,
+        })
     }
 
     async fn ensure_db_structure(connection: &Connection) -> Result<(), IndexerError> {
@@ -120,6 +262,221 @@ impl Database {
         )
         .await?;
 
+        // Versions which disappeared upstream after we published them. The
+        // download information is copied, so it survives the update row.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS archived_versions (
+                version TEXT NOT NULL,
+                plugin_xml_id TEXT NOT NULL,
+                update_id INTEGER NOT NULL,
+                channel TEXT NOT NULL,
+                download_url TEXT NOT NULL,
+                file_name TEXT DEFAULT NULL,
+                hash_algorithm TEXT NOT NULL,
+                hash BLOB NOT NULL,
+                archived_at INTEGER NOT NULL,
+                PRIMARY KEY (version, plugin_xml_id),
+                FOREIGN KEY (plugin_xml_id) REFERENCES plugins(xml_id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS plugin_screenshots (
+                plugin_xml_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                PRIMARY KEY (plugin_xml_id, position),
+                FOREIGN KEY (plugin_xml_id) REFERENCES plugins(xml_id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // Config files of optional dependencies, only known for updates whose
+        // archive has been inspected. Kept apart from update_dependencies since
+        // both are synced independently.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS update_dependency_configs (
+                update_id INTEGER NOT NULL,
+                dependency_xml_id TEXT NOT NULL,
+                config_file TEXT NOT NULL,
+                PRIMARY KEY (update_id, dependency_xml_id),
+                FOREIGN KEY (update_id) REFERENCES updates(id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // Results of the marketplace plugin verifier, one row per IDE build
+        // an update was checked against.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS update_verifications (
+                update_id INTEGER NOT NULL,
+                build TEXT NOT NULL,
+                compatible BOOLEAN NOT NULL,
+                PRIMARY KEY (update_id, build),
+                FOREIGN KEY (update_id) REFERENCES updates(id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // Updates whose artifact no longer matches the hash they were synced
+        // with, as found by `drift-check`. The row stays until a check finds
+        // the artifact matching again.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS hash_drift (
+                update_id INTEGER PRIMARY KEY NOT NULL,
+                detected_at INTEGER NOT NULL,
+                hash_algorithm TEXT NOT NULL,
+                stored_hash BLOB NOT NULL,
+                current_hash BLOB NOT NULL,
+                FOREIGN KEY (update_id) REFERENCES updates(id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // One row per completed sync, used for reporting trends over time.
+        // The average artifact size stays empty until sizes are recorded.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS sync_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL,
+                plugins INTEGER NOT NULL,
+                versions INTEGER NOT NULL,
+                succeeded_tasks INTEGER NOT NULL,
+                failed_tasks INTEGER NOT NULL,
+                problems INTEGER NOT NULL,
+                hash_changes INTEGER NOT NULL,
+                average_artifact_size REAL DEFAULT NULL
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // One row per task which still failed at the end of a sync. The
+        // plugin is not a foreign key, so the history outlives the plugin.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS sync_failures (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                run_started_at INTEGER NOT NULL,
+                failed_at INTEGER NOT NULL,
+                task_name TEXT NOT NULL,
+                plugin_xml_id TEXT DEFAULT NULL,
+                category TEXT NOT NULL,
+                error TEXT NOT NULL
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        tx.execute(
+            "CREATE INDEX IF NOT EXISTS sync_failures_plugin ON sync_failures (plugin_xml_id, failed_at)",
+            (),
+        )
+        .await?;
+
+        // The marketplace only knows compatibility per plugin, not per update
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS plugin_products (
+                plugin_xml_id TEXT NOT NULL,
+                product TEXT NOT NULL,
+                PRIMARY KEY (plugin_xml_id, product),
+                FOREIGN KEY (plugin_xml_id) REFERENCES plugins(xml_id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS plugin_tags (
+                plugin_xml_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (plugin_xml_id, tag),
+                FOREIGN KEY (plugin_xml_id) REFERENCES plugins(xml_id) ON DELETE CASCADE
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // State kept across runs, such as when incremental syncs have to look
+        // back to.
+        tx.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS sync_state (
+                key TEXT PRIMARY KEY NOT NULL,
+                value INTEGER NOT NULL
+            )
+        "#,
+            (),
+        )
+        .await?;
+
+        // Columns added after the initial schema, existing databases need
+        // to be migrated.
+        Self::add_column_if_missing(&tx, "plugins", "pricing_model", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "product_code", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "trial_available", "BOOLEAN DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "trial_days", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "purchase_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "source_code_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "family", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "bugtracker_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "documentation_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "last_synced_at", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "last_seen_update_id", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "hash_changed_at", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "verified_at", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "since_build", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "until_build", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "fallback_download_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "dirty", "BOOLEAN NOT NULL DEFAULT TRUE").await?;
+        Self::add_column_if_missing(&tx, "plugins", "name", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "description", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "vendor_name", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "vendor_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "vendor_email", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "homepage_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "license_url", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "downloads", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "rating", "REAL DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "repository", "TEXT NOT NULL DEFAULT 'marketplace'").await?;
+        Self::add_column_if_missing(&tx, "updates", "repository", "TEXT NOT NULL DEFAULT 'marketplace'").await?;
+        Self::add_column_if_missing(&tx, "updates", "size", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "release_date", "INTEGER DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "updates", "change_notes", "TEXT DEFAULT NULL").await?;
+        Self::add_column_if_missing(&tx, "plugins", "change_counter", "INTEGER NOT NULL DEFAULT 0").await?;
+
+        tx.execute(
+            "CREATE INDEX IF NOT EXISTS plugins_change_counter ON plugins (change_counter)",
+            (),
+        )
+        .await?;
+
+        Self::ensure_unique_numeric_ids(&tx).await?;
+
         tx.commit().await?;
 
         tracing::trace!("Database structure created.");
@@ -127,9 +484,79 @@ impl Database {
         Ok(())
     }
 
+    /// Make sure no two plugins share a numeric id.
+    ///
+    /// Databases created before the constraint existed may contain collisions,
+    /// these are reported and left alone. The constraint is only added once
+    /// they have been resolved by hand.
+    async fn ensure_unique_numeric_ids(connection: &Connection) -> Result<(), IndexerError> {
+        let mut collisions = connection
+            .query(
+                r#"
+                SELECT numeric_id, GROUP_CONCAT(xml_id, ', ') FROM plugins
+                GROUP BY numeric_id HAVING COUNT(*) > 1
+            "#,
+                (),
+            )
+            .await?;
+
+        let mut collided = false;
+        while let Some(row) = collisions.next().await? {
+            tracing::warn!(
+                "Plugins {} share the numeric id {}, not enforcing unique numeric ids",
+                row.get_str(1)?,
+                row.get::<u64>(0)?
+            );
+            collided = true;
+        }
+
+        if collided {
+            return Ok(());
+        }
+
+        connection
+            .execute(
+                "CREATE UNIQUE INDEX IF NOT EXISTS plugins_numeric_id ON plugins (numeric_id)",
+                (),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_column_if_missing(
+        connection: &Connection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<(), IndexerError> {
+        let exists = connection
+            .query(
+                &format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table),
+                [column],
+            )
+            .await?
+            .next()
+            .await?
+            .is_some();
+
+        if !exists {
+            tracing::debug!("Adding column {}.{}", table, column);
+            connection
+                .execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                    (),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn known_plugin_xml_ids(&self) -> Result<HashSet<String>, IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .query("SELECT xml_id FROM plugins", ())
             .await?
             .into_stream()
@@ -139,20 +566,72 @@ impl Database {
             .await
     }
 
+    /// The plugins synced from a repository.
     #[tracing::instrument(skip(self))]
-    pub async fn stream_plugins(&self) -> impl Stream<Item = Result<CachedPlugin, IndexerError>> {
-        self.connection
-            .query("SELECT xml_id, numeric_id FROM plugins", ())
+    pub async fn repository_plugin_xml_ids(
+        &self,
+        repository: &str,
+    ) -> Result<HashSet<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query("SELECT xml_id FROM plugins WHERE repository = ?1", [repository])
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
             .await
-            .expect("Failed to query plugins")
+    }
+
+    /// Plugins of a repository which are of one of the families, and if any
+    /// products are given, compatible with one of them.
+    #[tracing::instrument(skip(self))]
+    pub async fn repository_plugin_xml_ids_in(
+        &self,
+        repository: &str,
+        families: &[&str],
+        products: &[String],
+    ) -> Result<HashSet<String>, IndexerError> {
+        let families = serde_json::to_string(families)?;
+        let products = serde_json::to_string(products)?;
+
+        self.connection()
+            .await?
+            .query(
+                "SELECT xml_id FROM plugins WHERE repository = ?1 AND family IN (SELECT value FROM json_each(?2)) AND (json_array_length(?3) = 0 OR EXISTS (SELECT 1 FROM plugin_products WHERE plugin_xml_id = xml_id AND product IN (SELECT value FROM json_each(?3))))",
+                libsql::params![repository, families, products],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_repository_plugins(
+        &self,
+        repository: &str,
+    ) -> Result<Vec<CachedPlugin>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT xml_id, numeric_id FROM plugins WHERE repository = ?1",
+                [repository],
+            )
+            .await?
             .into_stream()
             .map_err(IndexerError::from)
             .and_then(map_row_de)
+            .try_collect()
+            .await
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn get_all_plugins(&self) -> Result<Vec<CachedPlugin>, IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .query("SELECT xml_id, numeric_id FROM plugins", ())
             .await
             .expect("Failed to query plugins")
@@ -163,52 +642,440 @@ impl Database {
             .await
     }
 
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_popularity(
+        &self,
+    ) -> Result<HashMap<String, CachedPluginPopularity>, IndexerError> {
+        self.connection()
+            .await?
+            .query("SELECT xml_id, downloads, rating FROM plugins", ())
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de::<CachedPluginPopularity>)
+            .map_ok(|v| (v.xml_id.clone(), v))
+            .try_collect()
+            .await
+    }
+
+    /// The family of every plugin which has one.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_families(&self) -> Result<HashMap<String, String>, IndexerError> {
+        self.connection()
+            .await?
+            .query("SELECT xml_id, family FROM plugins WHERE family IS NOT NULL", ())
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get::<String>(0).and_then(|k| Ok((k, r.get::<String>(1)?)))))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
     #[tracing::instrument(skip_all, fields(plugin_xml_id = xml_id.as_ref()))]
     pub async fn delete_plugin_by_xml_id(
         &self,
         xml_id: impl AsRef<str>,
     ) -> Result<(), IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .execute("DELETE FROM plugins WHERE xml_id = ?1", [xml_id.as_ref()])
             .map_err(IndexerError::from)
             .await?;
 
-        Ok(())
+        self.mark_dependents_dirty(xml_id.as_ref()).await
     }
 
+    /// Add a new plugin synced from a repository.
+    ///
+    /// Returns `false` if a plugin with the same xml or numeric id already exists.
     #[tracing::instrument(skip(self))]
-    pub async fn add_plugin(&self, plugin: &CachedPlugin) -> Result<(), IndexerError> {
-        self.connection
+    pub async fn add_plugin(&self, plugin: &CachedPlugin, repository: &str) -> Result<bool, IndexerError> {
+        let affected = self
+            .connection()
+            .await?
             .execute(
-                "INSERT INTO plugins (xml_id, numeric_id) VALUES (?1, ?2)",
-                libsql::params![plugin.xml_id.as_str(), plugin.numeric_id],
+                r#"
+                INSERT INTO plugins (xml_id, numeric_id, repository, change_counter)
+                SELECT ?1, ?2, ?3, (SELECT COALESCE(MAX(change_counter), 0) + 1 FROM plugins) WHERE NOT EXISTS (SELECT 1 FROM plugins WHERE numeric_id = ?2)
+                ON CONFLICT DO NOTHING
+            "#,
+                libsql::params![plugin.xml_id.as_str(), plugin.numeric_id, repository],
             )
             .map_err(IndexerError::from)
             .await?;
 
-        Ok(())
+        if affected > 0 {
+            self.mark_dependents_dirty(&plugin.xml_id).await?;
+        }
+
+        Ok(affected > 0)
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn add_update(&self, update_id: u64) -> Result<(), IndexerError> {
-        self.connection
+    /// Mark plugins depending on a plugin dirty, so their dependencies are
+    /// resolved again.
+    async fn mark_dependents_dirty(&self, xml_id: &str) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
             .execute(
-                "INSERT OR IGNORE INTO updates (id) VALUES (?1)",
-                libsql::params![update_id],
+                r#"
+                UPDATE plugins SET dirty = TRUE, change_counter = (SELECT MAX(change_counter) FROM plugins) + 1 WHERE xml_id IN (
+                    SELECT versions.plugin_xml_id FROM versions
+                    JOIN update_dependencies ON update_dependencies.update_id = versions.update_id
+                    WHERE update_dependencies.dependency_xml_id = ?1
+                )
+            "#,
+                [xml_id],
             )
-            .map_err(IndexerError::from)
             .await?;
 
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
-    pub async fn add_plugin_version(
+    pub async fn get_plugin(&self, xml_id: &str) -> Result<Option<CachedPlugin>, IndexerError> {
+        let row = self
+            .connection()
+            .await?
+            .query(
+                "SELECT xml_id, numeric_id FROM plugins WHERE xml_id = ?1",
+                [xml_id],
+            )
+            .await?
+            .next()
+            .await?;
+
+        match row {
+            Some(row) => map_row_de(row).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_by_numeric_id(
+        &self,
+        numeric_id: u64,
+    ) -> Result<Option<CachedPlugin>, IndexerError> {
+        let row = self
+            .connection()
+            .await?
+            .query(
+                "SELECT xml_id, numeric_id FROM plugins WHERE numeric_id = ?1",
+                libsql::params![numeric_id],
+            )
+            .await?
+            .next()
+            .await?;
+
+        match row {
+            Some(row) => map_row_de(row).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_details(
+        &self,
+        xml_id: &str,
+    ) -> Result<CachedPluginDetails, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT pricing_model, product_code, trial_available, trial_days, purchase_url, source_code_url, bugtracker_url, documentation_url, name, description, vendor_name, vendor_url, vendor_email, homepage_url, license_url, downloads, rating, repository, family FROM plugins WHERE xml_id = ?1",
+                [xml_id],
+            )
+            .await?
+            .next()
+            .await?
+            .map(map_row_de)
+            .ok_or(IndexerError::NotFound)?
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn change_plugin_details(
+        &self,
+        xml_id: &str,
+        details: &CachedPluginDetails,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE plugins SET pricing_model = ?1, product_code = ?2, trial_available = ?3, trial_days = ?4, purchase_url = ?5, source_code_url = ?6, bugtracker_url = ?7, documentation_url = ?8, name = ?9, description = ?10, vendor_name = ?11, vendor_url = ?12, homepage_url = ?13, license_url = ?14, downloads = ?15, rating = ?16, family = ?17, vendor_email = ?18, dirty = TRUE, change_counter = (SELECT MAX(change_counter) FROM plugins) + 1 WHERE xml_id = ?19",
+                libsql::params![
+                    details.pricing_model.as_deref(),
+                    details.product_code.as_deref(),
+                    details.trial_available,
+                    details.trial_days,
+                    details.purchase_url.as_deref(),
+                    details.source_code_url.as_deref(),
+                    details.bugtracker_url.as_deref(),
+                    details.documentation_url.as_deref(),
+                    details.name.as_deref(),
+                    details.description.as_deref(),
+                    details.vendor_name.as_deref(),
+                    details.vendor_url.as_deref(),
+                    details.homepage_url.as_deref(),
+                    details.license_url.as_deref(),
+                    details.downloads.map(|v| v as i64),
+                    details.rating,
+                    details.family.as_deref(),
+                    details.vendor_email.as_deref(),
+                    xml_id
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_plugin_screenshots(
+        &self,
+        xml_id: &str,
+        urls: &[String],
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "DELETE FROM plugin_screenshots WHERE plugin_xml_id = ?1",
+                [xml_id],
+            )
+            .await?;
+
+        for (position, url) in urls.iter().enumerate() {
+            self.connection()
+                .await?
+                .execute(
+                    "INSERT INTO plugin_screenshots (plugin_xml_id, position, url) VALUES (?1, ?2, ?3)",
+                    libsql::params![xml_id, position as u64, url.as_str()],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_screenshots(&self, xml_id: &str) -> Result<Vec<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT url FROM plugin_screenshots WHERE plugin_xml_id = ?1 ORDER BY position",
+                [xml_id],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_plugin_products(
+        &self,
+        xml_id: &str,
+        products: &[String],
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute("DELETE FROM plugin_products WHERE plugin_xml_id = ?1", [xml_id])
+            .await?;
+
+        for product in products {
+            self.connection()
+                .await?
+                .execute(
+                    "INSERT OR IGNORE INTO plugin_products (plugin_xml_id, product) VALUES (?1, ?2)",
+                    [xml_id, product.as_str()],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_products(&self, xml_id: &str) -> Result<Vec<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT product FROM plugin_products WHERE plugin_xml_id = ?1 ORDER BY product",
+                [xml_id],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_plugin_tags(&self, xml_id: &str, tags: &[String]) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute("DELETE FROM plugin_tags WHERE plugin_xml_id = ?1", [xml_id])
+            .await?;
+
+        for tag in tags {
+            self.connection()
+                .await?
+                .execute(
+                    "INSERT OR IGNORE INTO plugin_tags (plugin_xml_id, tag) VALUES (?1, ?2)",
+                    [xml_id, tag.as_str()],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_tags(&self, xml_id: &str) -> Result<Vec<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT tag FROM plugin_tags WHERE plugin_xml_id = ?1 ORDER BY tag",
+                [xml_id],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    /// Record that all versions of a plugin have been synced.
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_plugin_synced(
+        &self,
+        xml_id: &str,
+        last_seen_update_id: Option<u64>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE plugins SET last_synced_at = unixepoch(), last_seen_update_id = ?1, dirty = TRUE, change_counter = (SELECT MAX(change_counter) FROM plugins) + 1 WHERE xml_id = ?2",
+                libsql::params![last_seen_update_id.map(|v| v as i64), xml_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark the plugins an update belongs to for regeneration.
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_update_plugins_dirty(&self, update_id: u64) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE plugins SET dirty = TRUE, change_counter = (SELECT MAX(change_counter) FROM plugins) + 1 WHERE xml_id IN (SELECT plugin_xml_id FROM versions WHERE update_id = ?1)",
+                [update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Plugins whose metadata has to be generated again, either because
+    /// they changed or because some of their versions are stale.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_dirty_plugins(&self) -> Result<HashSet<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT xml_id FROM plugins WHERE dirty = TRUE OR xml_id IN (SELECT versions.plugin_xml_id FROM versions JOIN updates ON updates.id = versions.update_id WHERE updates.stale = TRUE)",
+                (),
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get::<String>(0)))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    /// Counts every time a plugin is marked dirty, see
+    /// [`Database::mark_plugins_clean`].
+    #[tracing::instrument(skip(self))]
+    pub async fn get_change_counter(&self) -> Result<i64, IndexerError> {
+        let row = self
+            .connection()
+            .await?
+            .query("SELECT COALESCE(MAX(change_counter), 0) FROM plugins", ())
+            .await?
+            .next()
+            .await?;
+
+        Ok(row.map(|r| r.get::<i64>(0)).transpose()?.unwrap_or_default())
+    }
+
+    /// Mark plugins clean, unless they were marked dirty again after the
+    /// change counter was at `generated_at`.
+    #[tracing::instrument(skip_all)]
+    pub async fn mark_plugins_clean(
+        &self,
+        xml_ids: &[String],
+        generated_at: i64,
+    ) -> Result<(), IndexerError> {
+        let connection = self.connection().await?;
+        let tx = connection.transaction().await?;
+
+        for xml_id in xml_ids {
+            tx.execute(
+                "UPDATE plugins SET dirty = FALSE WHERE xml_id = ?1 AND change_counter <= ?2",
+                libsql::params![xml_id.as_str(), generated_at],
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Plugins which completed syncing after the last finished sync run, so
+    /// during an interrupted one.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugins_synced_since_last_run(&self) -> Result<HashSet<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT xml_id FROM plugins WHERE last_synced_at >= (SELECT COALESCE(MAX(finished_at), 0) FROM sync_runs)",
+                (),
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn add_update(&self, update_id: u64, repository: &str) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "INSERT OR IGNORE INTO updates (id, repository) VALUES (?1, ?2)",
+                libsql::params![update_id, repository],
+            )
+            .map_err(IndexerError::from)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn add_plugin_version(
         &self,
         version: &CachedPluginVersion,
     ) -> Result<u64, IndexerError> {
         let count = self
-            .connection
+            .connection()
+            .await?
             .execute(
                 r#"
                         INSERT INTO versions
@@ -237,7 +1104,8 @@ impl Database {
         &self,
         plugin_xml_id: impl AsRef<str>,
     ) -> Result<Vec<CachedPluginVersion>, IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .query("SELECT version, update_id, channel, plugin_xml_id FROM versions WHERE plugin_xml_id = ?1", libsql::params![plugin_xml_id.as_ref()])
             .await?
             .into_stream()
@@ -247,6 +1115,23 @@ impl Database {
             .await
     }
 
+    /// The versions of all plugins published as an update.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_versions_for_update(
+        &self,
+        update_id: u64,
+    ) -> Result<Vec<CachedPluginVersion>, IndexerError> {
+        self.connection()
+            .await?
+            .query("SELECT version, update_id, channel, plugin_xml_id FROM versions WHERE update_id = ?1", libsql::params![update_id])
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
     #[tracing::instrument(
         skip_all,
         fields(plugin_xml_id = plugin_xml_id.as_ref(), version = version.as_ref())
@@ -256,7 +1141,8 @@ impl Database {
         plugin_xml_id: impl AsRef<str>,
         version: impl AsRef<str>,
     ) -> Result<(), IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .execute(
                 "DELETE FROM versions WHERE plugin_xml_id = ?1 AND version = ?2",
                 libsql::params![plugin_xml_id.as_ref(), version.as_ref()],
@@ -267,15 +1153,30 @@ impl Database {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn add_update_dependency(
+    /// Copy a version and its download information into the archive.
+    ///
+    /// Versions which never had a complete download are not archived.
+    #[tracing::instrument(
+        skip_all,
+        fields(plugin_xml_id = plugin_xml_id.as_ref(), version = version.as_ref())
+    )]
+    pub async fn archive_plugin_version(
         &self,
-        dependency: &CachedUpdateDependency,
+        plugin_xml_id: impl AsRef<str>,
+        version: impl AsRef<str>,
     ) -> Result<(), IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .execute(
-                "INSERT INTO update_dependencies (update_id, dependency_xml_id, optional) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET dependency_xml_id = ?2, optional = ?3",
-                libsql::params![dependency.update_id, dependency.dependency_xml_id.as_str(), dependency.optional],
+                r#"
+                        INSERT OR REPLACE INTO archived_versions
+                            (version, plugin_xml_id, update_id, channel, download_url, file_name, hash_algorithm, hash, archived_at)
+                        SELECT v.version, v.plugin_xml_id, v.update_id, v.channel, u.download_url, u.file_name, u.hash_algorithm, u.hash, unixepoch()
+                        FROM versions v JOIN updates u ON u.id = v.update_id
+                        WHERE v.plugin_xml_id = ?1 AND v.version = ?2
+                            AND u.download_url IS NOT NULL AND u.hash_algorithm IS NOT NULL AND u.hash IS NOT NULL
+                     "#,
+                libsql::params![plugin_xml_id.as_ref(), version.as_ref()],
             )
             .map_err(IndexerError::from)
             .await?;
@@ -283,37 +1184,755 @@ impl Database {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn mark_all_updates_stale(&self) -> Result<(), IndexerError> {
-        self.connection
-            .execute("UPDATE updates SET stale = TRUE", ())
-            .await?;
-        Ok(())
-    }
-
-    #[tracing::instrument(skip(self))]
-    pub async fn mark_update_not_stale(&self, update_id: u64) -> Result<bool, IndexerError> {
-        let affected = self
-            .connection
+    /// Remove a version from the archive, used when it reappears upstream.
+    #[tracing::instrument(
+        skip_all,
+        fields(plugin_xml_id = plugin_xml_id.as_ref(), version = version.as_ref())
+    )]
+    pub async fn unarchive_plugin_version(
+        &self,
+        plugin_xml_id: impl AsRef<str>,
+        version: impl AsRef<str>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
             .execute(
-                "UPDATE updates SET stale = FALSE WHERE id = ?1",
-                libsql::params![update_id],
+                "DELETE FROM archived_versions WHERE plugin_xml_id = ?1 AND version = ?2",
+                libsql::params![plugin_xml_id.as_ref(), version.as_ref()],
             )
             .map_err(IndexerError::from)
             .await?;
 
-        Ok(affected > 0)
+        Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn get_update(&self, update_id: u64) -> Result<CachedUpdate, IndexerError> {
-        self.connection
-            .query(
-                "SELECT id, stale, etag, file_name, download_url, hash_algorithm, hash FROM updates WHERE id = ?1",
-                libsql::params![update_id],
-            )
-            .await?
-            .next()
+    #[tracing::instrument(
+        skip_all,
+        fields(plugin_xml_id = plugin_xml_id.as_ref())
+    )]
+    pub async fn get_archived_versions_for_plugin(
+        &self,
+        plugin_xml_id: impl AsRef<str>,
+    ) -> Result<Vec<CachedArchivedVersion>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT version, update_id, channel, download_url, file_name, hash_algorithm, hash, archived_at FROM archived_versions WHERE plugin_xml_id = ?1",
+                libsql::params![plugin_xml_id.as_ref()],
+            )
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
+    /// The artifacts of all versions which are up-to-date and hashed.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_published_artifacts(&self) -> Result<Vec<CachedArtifact>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT versions.plugin_xml_id, updates.id AS update_id, updates.download_url, updates.hash_algorithm, updates.hash FROM versions JOIN updates ON updates.id = versions.update_id WHERE updates.stale = FALSE AND updates.hash IS NOT NULL AND updates.download_url IS NOT NULL",
+                (),
+            )
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
+    /// The published artifacts of the given plugins, or a random sample of
+    /// `sample` published artifacts if no plugins are given.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_drift_check_artifacts(
+        &self,
+        plugins: &[String],
+        sample: u64,
+    ) -> Result<Vec<CachedArtifact>, IndexerError> {
+        const QUERY: &str = "SELECT versions.plugin_xml_id, updates.id AS update_id, updates.download_url, updates.hash_algorithm, updates.hash FROM versions JOIN updates ON updates.id = versions.update_id WHERE updates.stale = FALSE AND updates.hash IS NOT NULL AND updates.download_url IS NOT NULL";
+
+        if plugins.is_empty() {
+            return self
+                .connection()
+                .await?
+                .query(&format!("{} ORDER BY RANDOM() LIMIT ?1", QUERY), [sample])
+                .await?
+                .into_stream()
+                .map_err(IndexerError::from)
+                .and_then(map_row_de)
+                .try_collect()
+                .await;
+        }
+
+        let mut artifacts = Vec::new();
+        for xml_id in plugins {
+            let found: Vec<CachedArtifact> = self
+                .connection()
+                .await?
+                .query(&format!("{} AND versions.plugin_xml_id = ?1", QUERY), [xml_id.as_str()])
+                .await?
+                .into_stream()
+                .map_err(IndexerError::from)
+                .and_then(map_row_de)
+                .try_collect()
+                .await?;
+            artifacts.extend(found);
+        }
+
+        Ok(artifacts)
+    }
+
+    #[tracing::instrument(skip(self, stored_hash, current_hash))]
+    pub async fn record_hash_drift(
+        &self,
+        update_id: u64,
+        hash_algorithm: &str,
+        stored_hash: &[u8],
+        current_hash: &[u8],
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "INSERT INTO hash_drift (update_id, detected_at, hash_algorithm, stored_hash, current_hash) VALUES (?1, unixepoch(), ?2, ?3, ?4) ON CONFLICT DO UPDATE SET detected_at = unixepoch(), hash_algorithm = ?2, stored_hash = ?3, current_hash = ?4",
+                libsql::params![update_id, hash_algorithm, stored_hash, current_hash],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forget the drift of an update, returning whether there was any.
+    #[tracing::instrument(skip(self))]
+    pub async fn clear_hash_drift(&self, update_id: u64) -> Result<bool, IndexerError> {
+        let deleted = self
+            .connection()
+            .await?
+            .execute("DELETE FROM hash_drift WHERE update_id = ?1", [update_id])
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Whether the artifact of an update was found to no longer match the
+    /// hash the update still has.
+    #[tracing::instrument(skip(self))]
+    pub async fn is_update_known_bad(&self, update_id: u64) -> Result<bool, IndexerError> {
+        let row = self
+            .connection()
+            .await?
+            .query(
+                "SELECT 1 FROM hash_drift JOIN updates ON updates.id = hash_drift.update_id WHERE hash_drift.update_id = ?1 AND hash_drift.stored_hash = updates.hash",
+                [update_id],
+            )
+            .await?
+            .next()
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn add_update_dependency(
+        &self,
+        dependency: &CachedUpdateDependency,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "INSERT INTO update_dependencies (update_id, dependency_xml_id, optional) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET dependency_xml_id = ?2, optional = ?3",
+                libsql::params![dependency.update_id, dependency.dependency_xml_id.as_str(), dependency.optional],
+            )
+            .map_err(IndexerError::from)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_update_dependency_configs(
+        &self,
+        update_id: u64,
+        configs: &HashMap<String, String>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "DELETE FROM update_dependency_configs WHERE update_id = ?1",
+                libsql::params![update_id],
+            )
+            .await?;
+
+        for (dependency_xml_id, config_file) in configs {
+            self.connection()
+                .await?
+                .execute(
+                    "INSERT INTO update_dependency_configs (update_id, dependency_xml_id, config_file) VALUES (?1, ?2, ?3)",
+                    libsql::params![update_id, dependency_xml_id.as_str(), config_file.as_str()],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Config files of the optional dependencies of an update, by dependency.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_update_dependency_configs(
+        &self,
+        update_id: u64,
+    ) -> Result<BTreeMap<String, String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT dependency_xml_id, config_file FROM update_dependency_configs WHERE update_id = ?1",
+                libsql::params![update_id],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| {
+                future::ready(
+                    r.get_str(0)
+                        .and_then(|id| Ok((id.to_string(), r.get_str(1)?.to_string()))),
+                )
+            })
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_update_verifications(
+        &self,
+        update_id: u64,
+        verifications: &[(String, bool)],
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "DELETE FROM update_verifications WHERE update_id = ?1",
+                libsql::params![update_id],
+            )
+            .await?;
+
+        for (build, compatible) in verifications {
+            self.connection()
+                .await?
+                .execute(
+                    "INSERT INTO update_verifications (update_id, build, compatible) VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET compatible = ?3",
+                    libsql::params![update_id, build.as_str(), *compatible],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the verifier found an update to be compatible with.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_verified_compatible_builds(
+        &self,
+        update_id: u64,
+    ) -> Result<Vec<String>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT build FROM update_verifications WHERE update_id = ?1 AND compatible = TRUE ORDER BY build",
+                libsql::params![update_id],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get_str(0).map(|v| v.to_string())))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    /// Delete rows nothing refers to anymore, optionally dropping all but
+    /// the newest versions of each plugin first.
+    #[tracing::instrument(skip(self))]
+    pub async fn prune(&self, keep_versions: Option<usize>) -> Result<PruneCounts, IndexerError> {
+        let connection = self.connection().await?;
+        let tx = connection.transaction().await?;
+        let mut counts = PruneCounts::default();
+
+        if let Some(keep) = keep_versions {
+            tx.execute(
+                r#"
+                UPDATE plugins SET dirty = TRUE, change_counter = (SELECT MAX(change_counter) FROM plugins) + 1 WHERE xml_id IN (
+                    SELECT plugin_xml_id FROM versions GROUP BY plugin_xml_id HAVING COUNT(*) > ?1
+                )
+            "#,
+                libsql::params![keep as i64],
+            )
+            .await?;
+
+            counts.versions = tx
+                .execute(
+                    r#"
+                    DELETE FROM versions WHERE rowid IN (
+                        SELECT rowid FROM (
+                            SELECT rowid, ROW_NUMBER() OVER (
+                                PARTITION BY plugin_xml_id ORDER BY update_id DESC
+                            ) AS position FROM versions
+                        ) WHERE position > ?1
+                    )
+                "#,
+                    libsql::params![keep as i64],
+                )
+                .await?;
+        }
+
+        counts.updates = tx
+            .execute(
+                "DELETE FROM updates WHERE id NOT IN (SELECT update_id FROM versions)",
+                (),
+            )
+            .await?;
+
+        // Databases created without foreign keys enabled may still have rows
+        // of updates deleted long ago
+        for table in ["update_dependencies", "update_dependency_configs", "update_verifications"] {
+            counts.update_rows += tx
+                .execute(
+                    &format!("DELETE FROM {} WHERE update_id NOT IN (SELECT id FROM updates)", table),
+                    (),
+                )
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(counts)
+    }
+
+    /// Rebuild the database file to give back the space of deleted rows.
+    #[tracing::instrument(skip(self))]
+    pub async fn vacuum(&self) -> Result<(), IndexerError> {
+        self.connection().await?.execute("VACUUM", ()).await?;
+        Ok(())
+    }
+
+    /// Write a compacted copy of the database to a new file.
+    ///
+    /// This runs in a single read transaction, so the copy is consistent even
+    /// while other connections write.
+    pub async fn backup_into(&self, path: &Path) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute("VACUUM INTO ?1", [path.to_string_lossy().as_ref()])
+            .await?;
+        Ok(())
+    }
+
+    /// What changed in this database compared to another database file.
+    pub async fn changes_since(&self, original: &Path) -> Result<DatabaseChanges, IndexerError> {
+        let connection = self.connection().await?;
+        let connection = &*connection;
+        connection
+            .execute("ATTACH DATABASE ?1 AS original", [read_only_uri(original)?.as_str()])
+            .await?;
+
+        let query = |sql: &'static str| async move {
+            connection
+                .query(sql, ())
+                .await?
+                .into_stream()
+                .and_then(|r| future::ready(r.get::<String>(0)))
+                .map_err(IndexerError::from)
+                .try_collect::<Vec<_>>()
+                .await
+        };
+
+        let changes = async {
+            Ok::<_, IndexerError>(DatabaseChanges {
+                added_plugins: query(
+                    "SELECT xml_id FROM main.plugins WHERE xml_id NOT IN (SELECT xml_id FROM original.plugins) ORDER BY xml_id",
+                )
+                .await?,
+                removed_plugins: query(
+                    "SELECT xml_id FROM original.plugins WHERE xml_id NOT IN (SELECT xml_id FROM main.plugins) ORDER BY xml_id",
+                )
+                .await?,
+                added_versions: query(
+                    "SELECT v.plugin_xml_id || '@' || v.version AS id FROM main.versions v WHERE NOT EXISTS (SELECT 1 FROM original.versions o WHERE o.plugin_xml_id = v.plugin_xml_id AND o.version = v.version) ORDER BY id",
+                )
+                .await?,
+                removed_versions: query(
+                    "SELECT o.plugin_xml_id || '@' || o.version AS id FROM original.versions o WHERE NOT EXISTS (SELECT 1 FROM main.versions v WHERE v.plugin_xml_id = o.plugin_xml_id AND v.version = o.version) ORDER BY id",
+                )
+                .await?,
+                changed_artifacts: query(
+                    "SELECT v.plugin_xml_id || '@' || v.version AS id FROM main.versions v JOIN original.versions ov ON ov.plugin_xml_id = v.plugin_xml_id AND ov.version = v.version JOIN main.updates u ON u.id = v.update_id JOIN original.updates o ON o.id = ov.update_id WHERE o.hash IS NOT NULL AND (u.hash IS NOT o.hash OR u.download_url IS NOT o.download_url) ORDER BY id",
+                )
+                .await?,
+            })
+        }
+        .await;
+
+        connection.execute("DETACH DATABASE original", ()).await?;
+        changes
+    }
+
+    /// Check that a file is an intact database, without writing to it.
+    pub async fn check_snapshot(path: &Path) -> Result<(), IndexerError> {
+        let db = libsql::Builder::new_local(path)
+            .flags(libsql::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .build()
+            .await?;
+
+        let result = db
+            .connect()?
+            .query("PRAGMA integrity_check", ())
+            .await?
+            .next()
+            .await?
+            .map(|r| r.get::<String>(0))
+            .transpose()?;
+
+        match result.as_deref() {
+            Some("ok") => Ok(()),
+            Some(problem) => Err(IndexerError::InvalidSnapshot(format!(
+                "{}: {}",
+                path.display(),
+                problem
+            ))),
+            None => Err(IndexerError::InvalidSnapshot(format!(
+                "{} could not be checked",
+                path.display()
+            ))),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_all_updates_stale(&self, repository: &str) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute("UPDATE updates SET stale = TRUE WHERE repository = ?1", [repository])
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_plugin_updates_stale(&self, xml_id: &str) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET stale = TRUE WHERE id IN (SELECT update_id FROM versions WHERE plugin_xml_id = ?1)",
+                [xml_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_update_not_stale(&self, update_id: u64) -> Result<bool, IndexerError> {
+        let affected = self
+            .connection()
+            .await?
+            .execute(
+                "UPDATE updates SET stale = FALSE WHERE id = ?1 AND stale = TRUE",
+                libsql::params![update_id],
+            )
+            .map_err(IndexerError::from)
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    /// Remember that the artifact of an update was replaced upstream.
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_update_hash_changed(&self, update_id: u64) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET hash_changed_at = unixepoch() WHERE id = ?1",
+                libsql::params![update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Set when an update was uploaded, in seconds since the unix epoch.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_update_release_date(
+        &self,
+        update_id: u64,
+        release_date: Option<i64>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET release_date = ?1 WHERE id = ?2",
+                libsql::params![release_date, update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, change_notes))]
+    pub async fn set_update_change_notes(
+        &self,
+        update_id: u64,
+        change_notes: Option<&str>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET change_notes = ?1 WHERE id = ?2",
+                libsql::params![change_notes, update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_update_build_range(
+        &self,
+        update_id: u64,
+        since_build: Option<&str>,
+        until_build: Option<&str>,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET since_build = ?1, until_build = ?2 WHERE id = ?3",
+                libsql::params![since_build, until_build, update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn mark_update_verified(&self, update_id: u64) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "UPDATE updates SET verified_at = unixepoch() WHERE id = ?1",
+                libsql::params![update_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Hashed updates which have not been verified since the cutoff, the
+    /// least recently verified first.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_updates_to_reverify(
+        &self,
+        repository: &str,
+        verified_before: i64,
+        limit: Option<u64>,
+    ) -> Result<Vec<u64>, IndexerError> {
+        // A negative limit means no limit to SQLite
+        let limit = limit.map(|v| v as i64).unwrap_or(-1);
+
+        self.connection()
+            .await?
+            .query(
+                "SELECT id FROM updates WHERE repository = ?1 AND hash IS NOT NULL AND (verified_at IS NULL OR verified_at < ?2) ORDER BY verified_at IS NOT NULL, verified_at LIMIT ?3",
+                libsql::params![repository, verified_before, limit],
+            )
+            .await?
+            .into_stream()
+            .and_then(|r| future::ready(r.get::<u64>(0)))
+            .map_err(IndexerError::from)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn count_hashed_updates(&self) -> Result<u64, IndexerError> {
+        self.connection()
+            .await?
+            .query("SELECT COUNT(*) FROM updates WHERE hash IS NOT NULL", ())
+            .await?
+            .next()
+            .await?
+            .ok_or(IndexerError::NotFound)?
+            .get::<u64>(0)
+            .map_err(IndexerError::from)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_sync_state(&self, key: &str) -> Result<Option<i64>, IndexerError> {
+        let row = self
+            .connection()
+            .await?
+            .query("SELECT value FROM sync_state WHERE key = ?1", [key])
+            .await?
+            .next()
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(row.get::<i64>(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn set_sync_state(&self, key: &str, value: i64) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                "INSERT INTO sync_state (key, value) VALUES (?1, ?2) ON CONFLICT DO UPDATE SET value = ?2",
+                libsql::params![key, value],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record a finished sync, counting the current plugins and versions.
+    #[tracing::instrument(skip(self))]
+    pub async fn record_sync_run(
+        &self,
+        started_at: i64,
+        succeeded_tasks: usize,
+        failed_tasks: usize,
+        problems: usize,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                r#"
+                INSERT INTO sync_runs (started_at, finished_at, plugins, versions, succeeded_tasks, failed_tasks, problems, hash_changes, average_artifact_size)
+                SELECT
+                    ?1,
+                    unixepoch(),
+                    (SELECT COUNT(*) FROM plugins),
+                    (SELECT COUNT(*) FROM versions),
+                    ?2,
+                    ?3,
+                    ?4,
+                    (SELECT COUNT(*) FROM updates WHERE hash_changed_at >= ?1),
+                    (SELECT AVG(size) FROM updates WHERE size IS NOT NULL)
+                "#,
+                libsql::params![started_at, succeeded_tasks as u64, failed_tasks as u64, problems as u64],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record a task which failed in the sync started at the given time.
+    ///
+    /// Without a plugin, the plugin is looked up by the update.
+    #[tracing::instrument(skip(self, error))]
+    pub async fn record_sync_failure(
+        &self,
+        run_started_at: i64,
+        task_name: &str,
+        plugin_xml_id: Option<&str>,
+        update_id: Option<u64>,
+        category: &str,
+        error: &str,
+    ) -> Result<(), IndexerError> {
+        self.connection()
+            .await?
+            .execute(
+                r#"
+                INSERT INTO sync_failures (run_started_at, failed_at, task_name, plugin_xml_id, category, error)
+                VALUES (
+                    ?1,
+                    unixepoch(),
+                    ?2,
+                    COALESCE(?3, (SELECT plugin_xml_id FROM versions WHERE update_id = ?4 LIMIT 1)),
+                    ?5,
+                    ?6
+                )
+                "#,
+                libsql::params![
+                    run_started_at,
+                    task_name,
+                    plugin_xml_id,
+                    update_id.map(|v| v as i64),
+                    category,
+                    error
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Plugins which failed in at least `min_runs` syncs since the given
+    /// time, the most often failing first.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_repeated_failures(
+        &self,
+        since: i64,
+        min_runs: u32,
+    ) -> Result<Vec<CachedPluginFailures>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                r#"
+                SELECT
+                    plugin_xml_id,
+                    COUNT(DISTINCT run_started_at) AS runs,
+                    COUNT(*) AS failures,
+                    MAX(failed_at) AS last_failed_at,
+                    (
+                        SELECT category FROM sync_failures l
+                        WHERE l.plugin_xml_id = f.plugin_xml_id AND l.failed_at >= ?1
+                        ORDER BY failed_at DESC, id DESC LIMIT 1
+                    ) AS last_category,
+                    (
+                        SELECT error FROM sync_failures l
+                        WHERE l.plugin_xml_id = f.plugin_xml_id AND l.failed_at >= ?1
+                        ORDER BY failed_at DESC, id DESC LIMIT 1
+                    ) AS last_error
+                FROM sync_failures f
+                WHERE plugin_xml_id IS NOT NULL AND failed_at >= ?1
+                GROUP BY plugin_xml_id
+                HAVING runs >= ?2
+                ORDER BY runs DESC, failures DESC, plugin_xml_id
+                "#,
+                libsql::params![since, min_runs],
+            )
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
+    /// All sync runs started at or after the given time, oldest first.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_sync_runs_since(&self, since: i64) -> Result<Vec<CachedSyncRun>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT started_at, finished_at, plugins, versions, succeeded_tasks, failed_tasks, problems, hash_changes, average_artifact_size FROM sync_runs WHERE started_at >= ?1 ORDER BY started_at",
+                libsql::params![since],
+            )
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_update(&self, update_id: u64) -> Result<CachedUpdate, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                "SELECT id, stale, etag, file_name, download_url, fallback_download_url, hash_algorithm, hash, size, release_date, change_notes, since_build, until_build FROM updates WHERE id = ?1",
+                libsql::params![update_id],
+            )
+            .await?
+            .next()
             .await?
             .map(map_row_de)
             .ok_or(IndexerError::NotFound)?
@@ -325,7 +1944,8 @@ impl Database {
         &self,
         update_id: u64,
     ) -> Result<Vec<CachedUpdateDependency>, IndexerError> {
-        self.connection
+        self.connection()
+            .await?
             .query(
                 "SELECT update_id, dependency_xml_id, optional FROM update_dependencies WHERE update_id = ?1",
                 libsql::params![update_id],
@@ -338,10 +1958,34 @@ impl Database {
             .await
     }
 
+    /// Dependencies between plugins across all of their versions, required
+    /// ones take precedence over optional ones.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_plugin_dependencies(&self) -> Result<Vec<CachedPluginDependency>, IndexerError> {
+        self.connection()
+            .await?
+            .query(
+                r#"
+                SELECT versions.plugin_xml_id, update_dependencies.dependency_xml_id, MIN(update_dependencies.optional) AS optional
+                FROM versions
+                JOIN update_dependencies ON update_dependencies.update_id = versions.update_id
+                JOIN plugins ON plugins.xml_id = update_dependencies.dependency_xml_id
+                GROUP BY versions.plugin_xml_id, update_dependencies.dependency_xml_id
+            "#,
+                (),
+            )
+            .await?
+            .into_stream()
+            .map_err(IndexerError::from)
+            .and_then(map_row_de)
+            .try_collect()
+            .await
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn change_update_info(&self, update: &CachedUpdate) -> Result<(), IndexerError> {
-        self.connection.execute(
-            "UPDATE updates SET stale = ?1, etag = ?2, file_name = ?3, download_url = ?4, hash_algorithm = ?5, hash = ?6 WHERE id = ?7",
+        self.connection().await?.execute(
+            "UPDATE updates SET stale = ?1, etag = ?2, file_name = ?3, download_url = ?4, hash_algorithm = ?5, hash = ?6, size = ?7, fallback_download_url = ?8 WHERE id = ?9",
             libsql::params![
                 update.stale,
                 update.etag.as_deref(),
@@ -349,6 +1993,8 @@ impl Database {
                 update.download_url.as_deref(),
                 update.hash_algorithm.as_deref(),
                 update.hash.as_deref(),
+                update.size.map(|v| v as i64),
+                update.fallback_download_url.as_deref(),
                 update.id
             ],
         ).await?;
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct CachedPlugin {
@@ -6,6 +6,36 @@ pub struct CachedPlugin {
     pub numeric_id: u64,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CachedPluginDetails {
+    pub pricing_model: Option<String>,
+    pub product_code: Option<String>,
+    pub trial_available: Option<bool>,
+    pub trial_days: Option<u32>,
+    pub purchase_url: Option<String>,
+    pub source_code_url: Option<String>,
+    pub bugtracker_url: Option<String>,
+    pub documentation_url: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub vendor_name: Option<String>,
+    pub vendor_url: Option<String>,
+    pub vendor_email: Option<String>,
+    pub homepage_url: Option<String>,
+    pub license_url: Option<String>,
+    pub downloads: Option<u64>,
+    pub rating: Option<f64>,
+    pub repository: Option<String>,
+    pub family: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedPluginPopularity {
+    pub xml_id: String,
+    pub downloads: Option<u64>,
+    pub rating: Option<f64>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CachedPluginVersion {
     pub version: String,
@@ -14,6 +44,28 @@ pub struct CachedPluginVersion {
     pub plugin_xml_id: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedArchivedVersion {
+    pub version: String,
+    pub update_id: u64,
+    pub channel: String,
+    pub download_url: String,
+    pub file_name: Option<String>,
+    pub hash_algorithm: String,
+    pub hash: Vec<u8>,
+    pub archived_at: i64,
+}
+
+/// The artifact of a published version.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedArtifact {
+    pub plugin_xml_id: String,
+    pub update_id: u64,
+    pub download_url: String,
+    pub hash_algorithm: String,
+    pub hash: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CachedUpdateDependency {
     pub update_id: u64,
@@ -21,6 +73,14 @@ pub struct CachedUpdateDependency {
     pub optional: bool,
 }
 
+/// A plugin depending on another plugin with at least one of its versions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedPluginDependency {
+    pub plugin_xml_id: String,
+    pub dependency_xml_id: String,
+    pub optional: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CachedUpdate {
     pub id: u64,
@@ -28,6 +88,68 @@ pub struct CachedUpdate {
     pub etag: Option<String>,
     pub file_name: Option<String>,
     pub download_url: Option<String>,
+
+    /// The download URL in the other style, published as a fallback.
+    pub fallback_download_url: Option<String>,
+
     pub hash_algorithm: Option<String>,
     pub hash: Option<Vec<u8>>,
+
+    /// Size of the artifact in bytes.
+    pub size: Option<u64>,
+
+    /// Seconds since the unix epoch, synced from the update metadata.
+    pub release_date: Option<i64>,
+
+    /// Change notes as HTML, synced from the update metadata.
+    pub change_notes: Option<String>,
+
+    /// Synced from the update metadata, never written by `change_update_info`.
+    pub since_build: Option<String>,
+    pub until_build: Option<String>,
+}
+
+/// Number of rows deleted by pruning.
+#[derive(Debug, Clone, Default)]
+pub struct PruneCounts {
+    pub versions: u64,
+    pub updates: u64,
+    pub update_rows: u64,
+}
+
+/// Differences of a database to another one, versions as `xml_id@version`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DatabaseChanges {
+    pub added_plugins: Vec<String>,
+    pub removed_plugins: Vec<String>,
+    pub added_versions: Vec<String>,
+    pub removed_versions: Vec<String>,
+
+    /// Versions whose artifact was hashed before and has a different hash or
+    /// download URL now.
+    pub changed_artifacts: Vec<String>,
+}
+
+/// The failures of a plugin across sync runs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedPluginFailures {
+    pub plugin_xml_id: String,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_failed_at: i64,
+    pub last_category: String,
+    pub last_error: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachedSyncRun {
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub plugins: u64,
+    pub versions: u64,
+    pub succeeded_tasks: u64,
+    pub failed_tasks: u64,
+    pub problems: u64,
+    pub hash_changes: u64,
+    pub average_artifact_size: Option<f64>,
 }
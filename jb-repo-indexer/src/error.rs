@@ -1,3 +1,4 @@
+use crate::statistics::FailureCategory;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,6 +21,101 @@ pub enum IndexerError {
     #[error("bad base64 data: {0}")]
     InvalidBase64(#[from] base64::DecodeError),
 
+    #[error("bad archive: {0}")]
+    InvalidArchive(#[from] zip::result::ZipError),
+
+    #[error("bad xml: {0}")]
+    InvalidXml(#[from] quick_xml::Error),
+
+    #[error("background task failed: {0}")]
+    BackgroundTask(#[from] tokio::task::JoinError),
+
+    #[error("invalid signing key: {0}")]
+    InvalidSigningKey(String),
+
+    #[error("hash of update {0} changed since it was last verified")]
+    HashMismatch(u64),
+
+    #[error("{0} artifacts no longer match their stored hash")]
+    HashDrift(usize),
+
+    #[error("artifact changed while downloading: {0}")]
+    ArtifactChanged(String),
+
+    #[error("injected fault: {0}")]
+    InjectedFault(&'static str),
+
+    #[error("the database is opened read-only, refusing to {0}")]
+    ReadOnly(&'static str),
+
+    #[error("invalid overrides: {0}")]
+    InvalidOverrides(String),
+
+    #[error("invalid database snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("invalid database dump: {0}")]
+    InvalidDump(String),
+
+    #[error("{count} tasks failed with {category:?} errors")]
+    TasksFailed {
+        category: FailureCategory,
+        count: usize,
+    },
+
+    #[error("gave up on the task after {0:?}")]
+    TaskTimeout(std::time::Duration),
+
+    #[error("shutting down, no more requests are sent")]
+    ShuttingDown,
+
+    #[error("the sync was interrupted")]
+    Interrupted,
+
     #[error("not found")]
     NotFound,
-}
\ No newline at end of file
+
+    #[error("output validation failed: {0}")]
+    OutputValidation(String),
+
+    #[error("incomplete data: {0}")]
+    IncompleteData(String),
+
+    #[error("numeric id {numeric_id} of plugin {xml_id} is already used by plugin {existing_xml_id}")]
+    NumericIdCollision {
+        numeric_id: u64,
+        xml_id: String,
+        existing_xml_id: String,
+    },
+}
+
+impl IndexerError {
+    /// The code the process exits with because of this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::TasksFailed { category, .. } => category.exit_code(),
+            Self::Interrupted => 130,
+            _ => 1,
+        }
+    }
+}
+
+/// An error followed by all of its sources, leaving out those the message
+/// before already includes.
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut previous = err.to_string();
+    let mut chain = previous.clone();
+    let mut source = err.source();
+    while let Some(err) = source {
+        let message = err.to_string();
+        if !previous.contains(&message) {
+            chain.push_str(": ");
+            chain.push_str(&message);
+        }
+
+        previous = message;
+        source = err.source();
+    }
+
+    chain
+}
@@ -0,0 +1,11 @@
+/// Encode bytes as lowercase hex.
+pub fn encode(bytes: impl AsRef<[u8]>) -> String {
+    bytes
+        .as_ref()
+        .iter()
+        .fold(String::with_capacity(bytes.as_ref().len() * 2), |mut acc, byte| {
+            acc.push(std::char::from_digit((byte >> 4) as u32, 16).unwrap());
+            acc.push(std::char::from_digit((byte & 0xF) as u32, 16).unwrap());
+            acc
+        })
+}
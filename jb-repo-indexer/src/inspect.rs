@@ -0,0 +1,107 @@
+use crate::error::IndexerError;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+const PLUGIN_XML: &str = "META-INF/plugin.xml";
+
+/// Find the optional dependencies of a plugin archive which are gated
+/// behind a `config-file`, mapped to that file.
+///
+/// Plugins are either distributed as a single jar or as a zip with the
+/// jars in `<name>/lib/`, in which case the jar containing the
+/// `plugin.xml` is searched.
+pub fn optional_dependency_configs(path: &Path) -> Result<HashMap<String, String>, IndexerError> {
+    let mut archive = ZipArchive::new(std::fs::File::open(path)?)?;
+
+    if let Some(data) = read_plugin_xml(&mut archive)? {
+        return parse_optional_dependency_configs(&data);
+    }
+
+    let jars = archive
+        .file_names()
+        .filter(|name| name.ends_with(".jar") && name.contains("/lib/"))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    for jar in jars {
+        let mut data = Vec::new();
+        archive.by_name(&jar)?.read_to_end(&mut data)?;
+
+        let mut jar_archive = ZipArchive::new(Cursor::new(data))?;
+        if let Some(data) = read_plugin_xml(&mut jar_archive)? {
+            return parse_optional_dependency_configs(&data);
+        }
+    }
+
+    tracing::debug!("No {} found in {}", PLUGIN_XML, path.display());
+    Ok(HashMap::new())
+}
+
+fn read_plugin_xml<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<Option<Vec<u8>>, IndexerError> {
+    let mut file = match archive.by_name(PLUGIN_XML) {
+        Ok(v) => v,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    Ok(Some(data))
+}
+
+fn parse_optional_dependency_configs(data: &[u8]) -> Result<HashMap<String, String>, IndexerError> {
+    let mut reader = Reader::from_reader(data);
+    let mut buffer = Vec::new();
+
+    let mut configs = HashMap::new();
+    let mut current_config = None;
+
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(e) if e.name().as_ref() == b"depends" => {
+                current_config = optional_config_file(&e)?;
+            }
+            Event::Text(text) => {
+                if let Some(config) = current_config.take() {
+                    let xml_id = text.unescape()?.trim().to_owned();
+                    if !xml_id.is_empty() {
+                        configs.insert(xml_id, config);
+                    }
+                }
+            }
+            Event::End(_) => current_config = None,
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buffer.clear();
+    }
+
+    Ok(configs)
+}
+
+/// The `config-file` of a `<depends>` element, if it is optional.
+fn optional_config_file(element: &BytesStart) -> Result<Option<String>, IndexerError> {
+    let mut optional = false;
+    let mut config_file = None;
+
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let value = attribute.unescape_value()?;
+
+        match attribute.key.as_ref() {
+            b"optional" => optional = value.trim() == "true",
+            b"config-file" => config_file = Some(value.trim().to_owned()),
+            _ => {}
+        }
+    }
+
+    Ok(config_file.filter(|_| optional))
+}
@@ -4,26 +4,30 @@ mod args;
 mod api;
 mod meta;
 mod statistics;
+mod overrides;
+mod artifacts;
+mod hex;
+mod inspect;
+mod commands;
+mod chaos;
+mod metrics;
+mod server;
+mod s3;
+mod signing;
+mod progress;
+mod notify;
 
 use clap::Parser as _;
+use tracing::level_filters::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
-use crate::args::IndexerArgs;
-use crate::error::IndexerError;
-use crate::meta::MetadataProcessor;
+use crate::args::{IndexerArgs, IndexerCommand, LogFormat};
+use crate::error::{IndexerError, error_chain};
 
 fn main() {
-    let indicatif_layer = tracing_indicatif::IndicatifLayer::new();
-
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::from_env(
-            "JB_REPO_INDEXER_LOG",
-        ))
-        .with(tracing_subscriber::fmt::layer().with_writer(indicatif_layer.get_stdout_writer()))
-        .with(indicatif_layer)
-        .init();
-
     let args = args::IndexerArgs::parse();
+    init_logging(&args);
+    let log_format = args.log_format;
 
     let result = match tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -36,45 +40,84 @@ fn main() {
     };
 
     if let Err(err) = result {
-        tracing::error!("Error: {:?}", err);
-        std::process::exit(1);
+        match log_format {
+            LogFormat::Human => tracing::error!("Error: {:?}", err),
+            LogFormat::Json => tracing::error!(error_chain = %error_chain(&err), "Error: {}", err),
+        }
+        std::process::exit(err.exit_code());
     }
 }
 
-async fn async_main(args: IndexerArgs) -> Result<(), IndexerError> {
-    tracing::trace!("args = {:#?}", args);
-
-    let processor = MetadataProcessor::new(&args).await?;
+fn init_logging(args: &IndexerArgs) {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => Some(LevelFilter::ERROR),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::INFO),
+        (false, 2) => Some(LevelFilter::DEBUG),
+        (false, _) => Some(LevelFilter::TRACE),
+    };
 
-    if !args.no_sync {
-        tracing::info!("Starting to sync plugin metadata...");
-        let statistics = processor.sync_plugin_metadata().await?;
-        tracing::info!("Done.");
+    let filter = match level {
+        Some(level) => tracing_subscriber::EnvFilter::builder()
+            .with_default_directive(level.into())
+            .with_env_var("JB_REPO_INDEXER_LOG")
+            .from_env_lossy(),
+        None => tracing_subscriber::EnvFilter::from_env("JB_REPO_INDEXER_LOG"),
+    };
 
-        if !statistics.problems.is_empty() {
-            tracing::warn!("Problems encountered:");
-            for problem in &statistics.problems {
-                tracing::warn!("- {}: {}", problem.task_name, problem.error);
-            }
+    match args.log_format {
+        LogFormat::Human if args.quiet => {
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(tracing_subscriber::fmt::layer())
+                .init();
         }
+        LogFormat::Human => {
+            let indicatif_layer = tracing_indicatif::IndicatifLayer::new();
 
-        if !statistics.failures.is_empty() {
-            tracing::error!("Failed tasks:");
-            for failure in &statistics.failures {
-                tracing::error!("- {}: {}", failure.task_name, failure.error);
-            }
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(tracing_subscriber::fmt::layer().with_writer(indicatif_layer.get_stdout_writer()))
+                .with(indicatif_layer)
+                .init();
+        }
+        LogFormat::Json => {
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_current_span(true)
+                        .with_span_list(true)
+                        .with_writer(std::io::stderr),
+                )
+                .init();
         }
-
-        tracing::info!("Encountered problems: {}", statistics.problems.len());
-        tracing::info!("Failed tasks: {}", statistics.failures.len());
-        tracing::info!("Succeeded tasks: {}", statistics.successful_tasks);
     }
+}
 
-    if !args.no_generate {
-        tracing::info!("Starting to generate metadata...");
-        processor.generate_metadata().await?;
-        tracing::info!("Done.");
-    }
+async fn async_main(args: IndexerArgs) -> Result<(), IndexerError> {
+    tracing::trace!("args = {:#?}", args);
 
-    Ok(())
+    match &args.command {
+        IndexerCommand::Sync(command) => commands::sync::run(&args, command).await,
+        IndexerCommand::Generate(command) => commands::generate::run(&args, command).await,
+        IndexerCommand::ImportConfig(command) => commands::import_config::run(&args, command).await,
+        IndexerCommand::Trends(command) => commands::trends::run(&args, command).await,
+        IndexerCommand::Failures(command) => commands::failures::run(&args, command).await,
+        IndexerCommand::CheckOutput(command) => commands::check_output::run(&args, command).await,
+        IndexerCommand::VerifySignature(command) => commands::verify_signature::run(command).await,
+        IndexerCommand::Verify(command) => commands::verify::run(&args, command).await,
+        IndexerCommand::DriftCheck(command) => commands::drift_check::run(&args, command).await,
+        IndexerCommand::Serve(command) => commands::serve::run(&args, command).await,
+        IndexerCommand::Mirror(command) => commands::mirror::run(&args, command).await,
+        IndexerCommand::Query(command) => commands::query::run(&args, command).await,
+        IndexerCommand::Prune(command) => commands::prune::run(&args, command).await,
+        IndexerCommand::Closure(command) => commands::closure::run(&args, command).await,
+        IndexerCommand::Backup(command) => commands::backup::run(&args, command).await,
+        IndexerCommand::Restore(command) => commands::restore::run(&args, command).await,
+        IndexerCommand::ExportDb(command) => commands::export_db::run(&args, command).await,
+        IndexerCommand::ImportDb(command) => commands::import_db::run(&args, command).await,
+        IndexerCommand::ImportMeta(command) => commands::import_meta::run(&args, command).await,
+    }
 }
@@ -1,23 +1,57 @@
 mod sync;
 mod output;
+mod nix;
+mod version;
 
-use crate::api::JetbrainsRepoApi;
-use crate::args::IndexerArgs;
-use crate::db::Database;
+use crate::api::{HttpStatisticsSnapshot, JetbrainsRepoApi, RepoSearchPlugin};
+use crate::args::{
+    ClosureArgs, GenerateArgs, ImportMetaArgs, IndexerArgs, PluginFamily, RepositoryKind, SyncArgs,
+};
+use crate::db::{Database, DatabaseChanges};
 use crate::error::IndexerError;
-use crate::meta::sync::{sync_new_plugin, sync_plugin};
-use crate::statistics::{Statistics, StatisticsCollector, StatisticsSender};
+use crate::meta::output::{GenerateOptions, PublishedPlugin};
+pub use crate::meta::output::{
+    PublishedVersion, TreeLocation, check_tree, parse_sri_hash, read_latest_versions,
+    read_published_plugins, sri_hash, verify_tree_signatures,
+};
+use crate::overrides::Overrides;
+use crate::meta::sync::{RetryableTask, SyncOptions};
+use crate::metrics::SyncCounters;
+use crate::progress::{SyncPhase, SyncProgress};
+use crate::statistics::{
+    Statistics, StatisticsCollector, StatisticsSender, TaskSubject, TimingCategory,
+};
 use futures::StreamExt;
-use std::collections::HashSet;
-use std::path::{PathBuf};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
+/// Key in the sync state of when the last complete sync started.
+const LAST_SYNC_STARTED_AT: &str = "last_sync_started_at";
+
+/// Seconds an incremental sync looks back further than strictly needed.
+const INCREMENTAL_SLACK: i64 = 60 * 60;
+
+const FEED_PAGE_SIZE: usize = 100;
+
 #[derive(Clone)]
 pub struct TaskAttachment {
     database: Database,
     repo: JetbrainsRepoApi,
     tracker: TaskTracker,
+
+    /// Cancels the tasks still running after the shutdown timeout.
+    abandoned: CancellationToken,
     statistics_sender: StatisticsSender,
+    sync_options: Arc<SyncOptions>,
+    counters: Arc<SyncCounters>,
+    progress: Arc<SyncProgress>,
+    failed_tasks: Arc<std::sync::Mutex<Vec<RetryableTask>>>,
 }
 
 impl TaskAttachment {
@@ -27,8 +61,96 @@ impl TaskAttachment {
         F: Future<Output = Result<(), E>> + Send + 'static,
         E: std::error::Error + Send + 'static,
     {
-        let new_fut = self.statistics_sender.guard_future(name.into(), future);
-        self.tracker.spawn(new_fut);
+        self.dispatch_about(name, TaskSubject::default(), future);
+    }
+
+    /// Dispatch a new future working on a plugin or update.
+    fn dispatch_about<F, E>(&self, name: impl Into<String>, subject: TaskSubject, future: F)
+    where
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::error::Error + Send + 'static,
+    {
+        let new_fut = self.statistics_sender.guard_future(name.into(), subject, future);
+        let abandoned = self.abandoned.clone();
+
+        // Dropping an abandoned task rolls back its open write batch
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = new_fut => {},
+                _ = abandoned.cancelled() => {},
+            }
+        });
+    }
+
+    /// Dispatch a new future counted in the progress of a phase.
+    pub fn dispatch_phase<F, E>(
+        &self,
+        phase: SyncPhase,
+        name: impl Into<String>,
+        subject: TaskSubject,
+        future: F,
+    ) where
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::error::Error + Send + 'static,
+    {
+        let progress = self.progress.clone();
+        progress.add_task(phase);
+
+        self.dispatch_about(name, subject, async move {
+            let result = future.await;
+            progress.finish_task(phase);
+            result
+        });
+    }
+
+    /// Dispatch a task, remembering it to be retried if it fails.
+    ///
+    /// Tasks running longer than the task timeout are given up on, reported
+    /// as a problem and retried.
+    fn dispatch_task(&self, task: RetryableTask) {
+        let name = task.name();
+        let phase = task.phase();
+        let subject = TaskSubject {
+            plugin: task.plugin_xml_id().map(str::to_owned),
+            update_id: task.update_id(),
+        };
+
+        let future = {
+            let attachment = self.clone();
+            let name = name.clone();
+
+            async move {
+                let started = Arc::new(OnceLock::new());
+                let run = crate::api::track_task_start(
+                    started.clone(),
+                    task.clone().run(attachment.clone()),
+                );
+
+                let result = match attachment.sync_options.task_timeout {
+                    Some(timeout) => run_with_timeout(run, &started, timeout).await,
+                    None => run.await,
+                };
+
+                match result {
+                    Err(err @ IndexerError::TaskTimeout(_)) => {
+                        attachment.send_problem(name, err);
+                        attachment.failed_tasks.lock().unwrap().push(task);
+                        Ok(())
+                    }
+                    Err(IndexerError::ShuttingDown) => Err(IndexerError::ShuttingDown),
+                    Err(err) => {
+                        attachment.failed_tasks.lock().unwrap().push(task);
+                        Err(err)
+                    }
+                    Ok(()) => Ok(()),
+                }
+            }
+        };
+
+        match phase {
+            Some(phase) => self.dispatch_phase(phase, name, subject, future),
+            None => self.dispatch_about(name, subject, future),
+        }
     }
 
     pub fn send_problem(
@@ -38,63 +160,259 @@ impl TaskAttachment {
     ) {
         self.statistics_sender.send_problem(name, Box::new(error));
     }
+
+    /// Record how long an operation took in the statistics.
+    pub fn send_timing(&self, category: TimingCategory, started: Instant) {
+        self.statistics_sender.send_timing(category, started.elapsed());
+    }
+
+    /// Run a future, recording how long it took in the statistics.
+    pub async fn timed<F: Future>(&self, category: TimingCategory, future: F) -> F::Output {
+        self.statistics_sender.timed(category, future).await
+    }
+}
+
+/// Run a task until it finishes or the timeout passed.
+///
+/// The timeout counts from when the task first got a permit to send a
+/// request, tasks dispatched at once may wait for one for a long time.
+async fn run_with_timeout<F>(
+    run: F,
+    started: &OnceLock<Instant>,
+    timeout: Duration,
+) -> Result<(), IndexerError>
+where
+    F: Future<Output = Result<(), IndexerError>>,
+{
+    tokio::pin!(run);
+
+    loop {
+        let deadline = started.get().copied().unwrap_or_else(Instant::now) + timeout;
+
+        if let Ok(result) = tokio::time::timeout_at(deadline.into(), &mut run).await {
+            return result;
+        }
+
+        if let Some(started) = started.get()
+            && started.elapsed() >= timeout
+        {
+            return Err(IndexerError::TaskTimeout(started.elapsed()));
+        }
+    }
 }
 
 pub struct MetadataProcessor {
     database: Database,
     repo: JetbrainsRepoApi,
-    output_directory: PathBuf,
+    sync_options: Arc<SyncOptions>,
+    counters: Arc<SyncCounters>,
+    dry_run: Option<DryRun>,
+}
+
+/// A sync into a copy of the database, which is discarded afterward.
+///
+/// The copy is removed once the dry run is dropped, also when the sync failed.
+struct DryRun {
+    original: PathBuf,
+    copy: PathBuf,
+}
+
+impl Drop for DryRun {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = self.copy.as_os_str().to_owned();
+            file.push(suffix);
+
+            match std::fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => tracing::warn!("Failed to remove dry run copy {:?}: {}", file, err),
+            }
+        }
+    }
 }
 
 impl MetadataProcessor {
     /// Prepare the metadata processor.
-    pub async fn new(args: &IndexerArgs) -> Result<Self, IndexerError> {
-        let database = Database::setup(args).await?;
-        let repo = JetbrainsRepoApi::new(args)?;
-        let output_directory = args.output_directory.clone();
+    pub async fn new(args: &IndexerArgs, sync_args: &SyncArgs) -> Result<Self, IndexerError> {
+        let (database, dry_run) = match sync_args.dry_run {
+            true => {
+                // Kept out of the database directory, which may be read-only
+                let dry_run = DryRun {
+                    original: args.database.clone(),
+                    copy: std::env::temp_dir()
+                        .join(format!("jb-repo-indexer-dry-run-{}.db", std::process::id())),
+                };
+                remove_database_files(&dry_run.copy).await?;
+
+                // The schema of the original is left as it is, the copy is
+                // brought up to date instead
+                tracing::info!("Dry run, syncing into a copy at {}", dry_run.copy.display());
+                let original_args = IndexerArgs {
+                    read_only: true,
+                    ..args.clone()
+                };
+                Database::setup(&original_args).await?.backup_into(&dry_run.copy).await?;
 
-        Ok(Self { database, repo, output_directory })
+                let copy_args = IndexerArgs {
+                    database: dry_run.copy.clone(),
+                    read_only: false,
+                    ..args.clone()
+                };
+
+                (Database::setup(&copy_args).await?, Some(dry_run))
+            }
+            false => {
+                let database = Database::setup(args).await?;
+                database.ensure_writable("sync")?;
+                (database, None)
+            }
+        };
+
+        let repo = JetbrainsRepoApi::new(&sync_args.api, args.chaos.clone())?;
+        let mut sync_options = SyncOptions::new(sync_args).await?;
+
+        if !sync_args.filter_tags.is_empty() {
+            sync_options.restrict_to(Self::plugins_with_tags(&repo, &sync_args.filter_tags).await?);
+        }
+
+        // Custom repositories can't be searched, filtering would remove everything
+        if sync_args.api.repository_kind == RepositoryKind::Custom
+            && (!sync_options.families.is_empty() || !sync_options.products.is_empty())
+        {
+            tracing::warn!("Custom repositories can't be filtered by family or product, indexing all plugins");
+            sync_options.families.clear();
+            sync_options.products.clear();
+        }
+
+        let sync_options = Arc::new(sync_options);
+
+        Ok(Self {
+            database,
+            repo,
+            sync_options,
+            counters: Arc::new(SyncCounters::default()),
+            dry_run,
+        })
+    }
+
+    /// Report what a dry run changed.
+    pub async fn finish_dry_run(&self) -> Result<Option<DatabaseChanges>, IndexerError> {
+        let Some(dry_run) = &self.dry_run else {
+            return Ok(None);
+        };
+
+        let changes = self.database.changes_since(&dry_run.original).await?;
+        let sections = [
+            ("New plugins", &changes.added_plugins),
+            ("Removed plugins", &changes.removed_plugins),
+            ("New versions", &changes.added_versions),
+            ("Removed versions", &changes.removed_versions),
+            ("Changed artifacts", &changes.changed_artifacts),
+        ];
+
+        tracing::info!("Dry run, the database was not changed. The sync would have made these changes:");
+        for (title, items) in sections {
+            tracing::info!("{}: {}", title, items.len());
+            for item in items {
+                tracing::info!("- {}", item);
+            }
+        }
+
+        Ok(Some(changes))
     }
 
     pub async fn sync_plugin_metadata(&self) -> Result<Statistics, IndexerError> {
-        let (local, remote, _) = futures::try_join!(
+        let started_at = unix_timestamp();
+
+        // Selected before anything is hashed in this run
+        let reverify = self.updates_to_reverify(started_at).await?;
+        let changed = self.recently_changed_plugins().await?;
+
+        let repository = &self.sync_options.repository;
+        let (all_local, local, mut remote, _) = futures::try_join!(
             self.database.known_plugin_xml_ids(),
-            self.repo.fetch_all_xml_ids(),
-            self.database.mark_all_updates_stale()
+            self.database.repository_plugin_xml_ids(repository),
+            self.remote_xml_ids(),
+            self.mark_updates_stale(changed.as_ref())
         )?;
 
+        // Excluded plugins are purged below like plugins which disappeared
+        for excluded in &self.sync_options.excluded {
+            if remote.remove(excluded) {
+                tracing::debug!("Excluding plugin {}", excluded);
+            }
+        }
+
+        // The first repository a plugin was synced from keeps it
+        for foreign in all_local.difference(&local) {
+            if remote.remove(foreign) {
+                tracing::warn!("Plugin {} is already synced from another repository", foreign);
+            }
+        }
+
         self.purge_unknown_plugins(&local, &remote).await?;
 
+        let completed = match self.sync_options.resume {
+            true => {
+                let completed = self.database.get_plugins_synced_since_last_run().await?;
+                tracing::info!("Resuming, {} plugins are already done", completed.len());
+                completed
+            }
+            false => HashSet::new(),
+        };
+
+        if let Some(plugins) = &self.sync_options.plugins {
+            for missing in plugins.difference(&remote) {
+                tracing::warn!("Requested plugin {} does not exist", missing);
+            }
+        }
+
         let mut statistics = StatisticsCollector::new();
 
         let attachment = self.attachment(statistics.sender());
+        let remote = Arc::new(remote);
+        let expected_plugins = remote
+            .iter()
+            .filter(|v| {
+                self.sync_options.includes(v)
+                    && !completed.contains(*v)
+                    && (!local.contains(*v) || changed.as_ref().is_none_or(|c| c.contains(*v)))
+            })
+            .count();
+        attachment.progress.expect_tasks(SyncPhase::Plugins, expected_plugins as u64);
+
+        let finished = CancellationToken::new();
+        tokio::spawn(shut_down_on_signal(self.repo.clone(), finished.clone()));
 
         // Dispatch the initial tasks for syncing all plugins
         attachment.dispatch("dispatch plugin sync", {
             let attachment = attachment.clone();
+            let changed = changed.clone();
+            let remote = remote.clone();
 
             async move {
-                let plugins_stream = attachment.database.stream_plugins().await;
-                tokio::pin!(plugins_stream);
-
-                while let Some(next) = plugins_stream.next().await {
-                    let plugin = match next {
-                        Ok(v) => v,
-                        Err(err) => {
-                            attachment.send_problem("dispatch plugin sync", err);
-                            continue;
-                        }
-                    };
-
-                    attachment.dispatch(
-                        format!("sync plugin {}", plugin.xml_id),
-                        sync_plugin(attachment.clone(), plugin),
-                    );
+                let plugins = attachment
+                    .database
+                    .get_repository_plugins(&attachment.sync_options.repository)
+                    .await?;
+
+                for plugin in plugins {
+                    if !remote.contains(&plugin.xml_id)
+                        || !attachment.sync_options.includes(&plugin.xml_id)
+                        || completed.contains(&plugin.xml_id)
+                        || changed.as_ref().is_some_and(|v| !v.contains(&plugin.xml_id))
+                    {
+                        continue;
+                    }
+
+                    attachment.dispatch_task(RetryableTask::PluginDetails(plugin.clone()));
+                    attachment.dispatch_task(RetryableTask::Plugin(plugin));
                 }
 
                 tracing::trace!("Dispatched all known plugins");
 
-                Ok::<(), std::convert::Infallible>(())
+                Ok::<(), IndexerError>(())
             }
         });
 
@@ -106,19 +424,258 @@ impl MetadataProcessor {
             }
         });
 
-        // Wait for everything to finish
-        attachment.tracker.close();
-        let tracker_wait_fut = attachment.tracker.wait();
-        let statistics_wait_fut = statistics.run();
+        if !reverify.is_empty() {
+            tracing::info!("Reverifying {} updates", reverify.len());
+        }
 
-        // The statistics future never finishes either way, so we effectively wait for the tracker
-        // in this select, but also poll the statistics future.
-        tokio::select! {
-            _ = tracker_wait_fut => {},
-            _ = statistics_wait_fut => {},
+        for update_id in reverify {
+            attachment.dispatch_task(RetryableTask::UpdateMeta {
+                update_id,
+                reverify: true,
+            });
+        }
+
+        let mut interrupted = self.wait_for_tasks(&attachment, &mut statistics).await;
+
+        for round in 1..=self.sync_options.retry_rounds {
+            if interrupted {
+                break;
+            }
+
+            let failed = std::mem::take(&mut *attachment.failed_tasks.lock().unwrap());
+            if failed.is_empty() {
+                break;
+            }
+
+            tracing::info!("Retrying {} failed tasks (round {})", failed.len(), round);
+
+            // Only the outcome of the last attempt counts
+            let names = failed.iter().map(RetryableTask::name).collect::<HashSet<_>>();
+            statistics.forget_failures(|v| names.contains(&v.task_name));
+
+            attachment.tracker.reopen();
+            for task in failed {
+                attachment.dispatch_task(task);
+            }
+
+            interrupted = self.wait_for_tasks(&attachment, &mut statistics).await;
+        }
+
+        // The signal handlers stay installed, later signals have to exit
+        finished.cancel();
+
+        let mut statistics = statistics.reset();
+        statistics.interrupted = interrupted;
+
+        self.database
+            .record_sync_run(
+                started_at,
+                statistics.successful_tasks,
+                statistics.failures.len(),
+                statistics.problems.len(),
+            )
+            .await?;
+
+        let failed_tasks = std::mem::take(&mut *attachment.failed_tasks.lock().unwrap())
+            .into_iter()
+            .map(|v| (v.name(), v))
+            .collect::<HashMap<_, _>>();
+        for failure in &statistics.failures {
+            let task = failed_tasks.get(&failure.task_name);
+            self.database
+                .record_sync_failure(
+                    started_at,
+                    &failure.task_name,
+                    task.and_then(RetryableTask::plugin_xml_id),
+                    task.and_then(RetryableTask::update_id),
+                    failure.category.name(),
+                    &failure.error.to_string(),
+                )
+                .await?;
         }
 
-        Ok(statistics.reset())
+        // Only a complete run can be looked back to, plugins which failed
+        // or timed out would be missed by the next incremental sync otherwise
+        if self.sync_options.plugins.is_none()
+            && statistics.failures.is_empty()
+            && failed_tasks.is_empty()
+            && !interrupted
+        {
+            self.database
+                .set_sync_state(&self.sync_options.state_key(LAST_SYNC_STARTED_AT), started_at)
+                .await?;
+        }
+
+        Ok(statistics)
+    }
+
+    /// All plugins which have any of the tags.
+    async fn plugins_with_tags(
+        repo: &JetbrainsRepoApi,
+        tags: &[String],
+    ) -> Result<HashSet<String>, IndexerError> {
+        let mut plugins = HashSet::new();
+
+        for tag in tags {
+            let tagged = Self::search_all(|offset| {
+                repo.fetch_plugins_with_tag(tag, offset, FEED_PAGE_SIZE)
+            })
+            .await?;
+            plugins.extend(tagged);
+        }
+
+        tracing::info!("{} plugins have the tags {:?}", plugins.len(), tags);
+        Ok(plugins)
+    }
+
+    /// All plugins of the repository, or only those of the selected families
+    /// and products.
+    async fn remote_xml_ids(&self) -> Result<HashSet<String>, IndexerError> {
+        let options = &self.sync_options;
+        if options.families.is_empty() && options.products.is_empty() {
+            return self.repo.fetch_all_xml_ids().await;
+        }
+
+        let families = options.filtered_families();
+        let products = match options.products.is_empty() {
+            true => vec![None],
+            false => options.products.iter().map(|v| Some(v.as_str())).collect(),
+        };
+
+        let mut plugins = HashSet::new();
+        for family in &families {
+            for product in &products {
+                let found = Self::search_all(|offset| {
+                    self.repo
+                        .fetch_plugins_in(family.name(), *product, offset, FEED_PAGE_SIZE)
+                })
+                .await?;
+                plugins.extend(found);
+            }
+        }
+
+        tracing::info!(
+            "{} plugins are of the families {:?} and products {:?}",
+            plugins.len(),
+            families,
+            options.products
+        );
+        Ok(plugins)
+    }
+
+    /// Page through search results until they are exhausted.
+    async fn search_all<F, Fut>(mut fetch_page: F) -> Result<HashSet<String>, IndexerError>
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<Vec<RepoSearchPlugin>, IndexerError>>,
+    {
+        let mut plugins = HashSet::new();
+        let mut offset = 0;
+
+        loop {
+            let page = fetch_page(offset).await?;
+            let exhausted = page.len() < FEED_PAGE_SIZE;
+
+            offset += page.len();
+            plugins.extend(page.into_iter().map(|v| v.xml_id));
+
+            if exhausted {
+                return Ok(plugins);
+            }
+        }
+    }
+
+    /// The plugins updated since the last complete sync, if syncing incrementally.
+    async fn recently_changed_plugins(&self) -> Result<Option<HashSet<String>>, IndexerError> {
+        if !self.sync_options.incremental {
+            return Ok(None);
+        }
+
+        let state_key = self.sync_options.state_key(LAST_SYNC_STARTED_AT);
+        let Some(since) = self.database.get_sync_state(&state_key).await? else {
+            tracing::warn!("No complete sync to look back to, syncing everything");
+            return Ok(None);
+        };
+
+        // Timestamps of the feed are in milliseconds
+        let since = (since - INCREMENTAL_SLACK) * 1000;
+        let mut changed = HashSet::new();
+
+        for page in 0..self.sync_options.incremental_max_pages {
+            let plugins = self
+                .repo
+                .fetch_recently_updated_plugins(page * FEED_PAGE_SIZE, FEED_PAGE_SIZE)
+                .await?;
+
+            let exhausted = plugins.len() < FEED_PAGE_SIZE
+                || plugins
+                    .iter()
+                    .any(|v| v.update_date.is_some_and(|date| date < since));
+
+            changed.extend(
+                plugins
+                    .into_iter()
+                    .filter(|v| v.update_date.is_none_or(|date| date >= since))
+                    .map(|v| v.xml_id),
+            );
+
+            if exhausted {
+                tracing::info!("{} plugins changed since the last sync", changed.len());
+                return Ok(Some(changed));
+            }
+        }
+
+        tracing::warn!(
+            "Feed did not reach the last sync within {} pages, syncing everything",
+            self.sync_options.incremental_max_pages
+        );
+
+        Ok(None)
+    }
+
+    async fn updates_to_reverify(&self, now: i64) -> Result<Vec<u64>, IndexerError> {
+        let options = &self.sync_options;
+        if options.reverify_fraction.is_none() && options.reverify_max_age.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let verified_before = options
+            .reverify_max_age
+            .map(|v| now - v)
+            .unwrap_or(i64::MAX);
+
+        let limit = match options.reverify_fraction {
+            Some(fraction) => {
+                let hashed = self.database.count_hashed_updates().await?;
+                Some((hashed as f64 * fraction).ceil() as u64)
+            }
+            None => None,
+        };
+
+        self.database
+            .get_updates_to_reverify(&options.repository, verified_before, limit)
+            .await
+    }
+
+    /// Mark the updates about to be synced as stale.
+    async fn mark_updates_stale(&self, changed: Option<&HashSet<String>>) -> Result<(), IndexerError> {
+        // The interrupted run already did, everything it synced is up-to-date
+        if self.sync_options.resume {
+            return Ok(());
+        }
+
+        let Some(plugins) = self.sync_options.plugins.as_ref().or(changed) else {
+            return self
+                .database
+                .mark_all_updates_stale(&self.sync_options.repository)
+                .await;
+        };
+
+        for xml_id in plugins {
+            self.database.mark_plugin_updates_stale(xml_id).await?;
+        }
+
+        Ok(())
     }
 
     async fn purge_unknown_plugins(
@@ -126,10 +683,36 @@ impl MetadataProcessor {
         local: &HashSet<String>,
         remote: &HashSet<String>,
     ) -> Result<(), IndexerError> {
-        let all_disappeared = local.difference(remote);
+        let options = &self.sync_options;
+
+        // Search results only cover the filtered families and products, plugins
+        // outside of them are neither synced nor purged
+        let filtered = match options.families.is_empty() && options.products.is_empty() {
+            true => None,
+            false => {
+                let families = options
+                    .filtered_families()
+                    .into_iter()
+                    .map(PluginFamily::name)
+                    .collect::<Vec<_>>();
+
+                let filtered = self
+                    .database
+                    .repository_plugin_xml_ids_in(&options.repository, &families, &options.products)
+                    .await?;
+                Some(filtered)
+            }
+        };
+
+        let all_disappeared = local.difference(remote).filter(|v| {
+            options.excluded.contains(*v) || filtered.as_ref().is_none_or(|f| f.contains(*v))
+        });
 
         for disappeared in all_disappeared {
-            tracing::info!("Plugin disappeared: {}", disappeared);
+            match self.sync_options.excluded.contains(disappeared) {
+                true => tracing::info!("Removing excluded plugin {}", disappeared),
+                false => tracing::info!("Plugin disappeared: {}", disappeared),
+            }
             self.database.delete_plugin_by_xml_id(disappeared).await?;
         }
 
@@ -141,28 +724,401 @@ impl MetadataProcessor {
         remote: &HashSet<String>,
         attachment: TaskAttachment,
     ) -> Result<(), IndexerError> {
-        let all_new = remote.difference(local);
+        let all_new = remote
+            .difference(local)
+            .filter(|v| attachment.sync_options.includes(v));
 
         for new in all_new {
-            attachment.dispatch(
-                format!("sync new plugin {}", new),
-                sync_new_plugin(attachment.clone(), new.clone()),
-            );
+            attachment.dispatch_task(RetryableTask::NewPlugin(new.clone()));
         }
 
         Ok(())
     }
 
+    /// Wait for all dispatched tasks to finish, returning whether the sync
+    /// was shut down in the meantime.
+    ///
+    /// Once shut down, tasks which already sent their requests may finish
+    /// until the shutdown timeout, all others are cancelled.
+    async fn wait_for_tasks(
+        &self,
+        attachment: &TaskAttachment,
+        statistics: &mut StatisticsCollector,
+    ) -> bool {
+        attachment.tracker.close();
+
+        // The statistics future never finishes either way, so we effectively wait for the tracker
+        // in this select, but also poll the statistics future.
+        tokio::select! {
+            _ = attachment.tracker.wait() => return false,
+            _ = statistics.run() => {},
+            _ = self.repo.shutdown_requested() => {},
+        }
+
+        let timeout = self.sync_options.shutdown_timeout;
+        tracing::warn!("Shutting down, waiting up to {:?} for running tasks...", timeout);
+
+        tokio::select! {
+            _ = attachment.tracker.wait() => {},
+            _ = statistics.run() => {},
+            _ = tokio::time::sleep(timeout) => {
+                tracing::warn!("Abandoning {} tasks which are still running", attachment.tracker.len());
+                statistics.record_abandoned(attachment.tracker.len());
+                attachment.abandoned.cancel();
+                attachment.tracker.wait().await;
+            },
+        }
+
+        true
+    }
+
     fn attachment(&self, statistics_sender: StatisticsSender) -> TaskAttachment {
         TaskAttachment {
             database: self.database.clone(),
             repo: self.repo.clone(),
             tracker: TaskTracker::new(),
+            abandoned: CancellationToken::new(),
             statistics_sender,
+            sync_options: self.sync_options.clone(),
+            counters: self.counters.clone(),
+            progress: Arc::new(SyncProgress::new()),
+            failed_tasks: Default::default(),
+        }
+    }
+
+    pub fn http_statistics(&self) -> HttpStatisticsSnapshot {
+        self.repo.statistics()
+    }
+
+    /// Render the current metrics, including the outcome of a finished sync.
+    pub fn render_metrics(&self, statistics: Option<&Statistics>) -> String {
+        crate::metrics::render(&self.counters, &self.repo.statistics(), statistics)
+    }
+
+    /// Serve the metrics for scraping while the processor is running.
+    pub async fn serve_metrics(&self, address: SocketAddr) -> Result<(), IndexerError> {
+        let counters = self.counters.clone();
+        let repo = self.repo.clone();
+
+        crate::metrics::serve(address, move || {
+            crate::metrics::render(&counters, &repo.statistics(), None)
+        })
+        .await
+    }
+
+}
+
+/// Generate the metadata tree from the database.
+pub async fn generate_metadata(args: &IndexerArgs, generate_args: &GenerateArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    let overrides = Arc::new(Overrides::load(generate_args.overrides.as_deref()).await?);
+    let options = GenerateOptions::new(generate_args, overrides).await?;
+
+    generate_and_publish(generate_args, database, &options).await
+}
+
+/// Generate a tree of only some plugins and everything they require.
+pub async fn generate_closure(args: &IndexerArgs, closure_args: &ClosureArgs) -> Result<(), IndexerError> {
+    let generate_args = &closure_args.generate;
+
+    let database = Database::setup(args).await?;
+    let overrides = Arc::new(Overrides::load(generate_args.overrides.as_deref()).await?);
+    let mut options = GenerateOptions::new(generate_args, overrides).await?;
+
+    let closure = dependency_closure(&database, &closure_args.plugins).await?;
+    tracing::info!(
+        "{} plugins are required by {}",
+        closure.len(),
+        closure_args.plugins.join(", ")
+    );
+    options.plugins = Some(Arc::new(closure));
+
+    generate_and_publish(generate_args, database, &options).await
+}
+
+/// Remove a database file together with its journal.
+async fn remove_database_files(path: &Path) -> Result<(), IndexerError> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+
+        match tokio::fs::remove_file(&file).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Stop sending requests on SIGINT or SIGTERM, a second signal exits
+/// right away. Once the sync finished, the first signal exits already.
+async fn shut_down_on_signal(repo: JetbrainsRepoApi, finished: CancellationToken) {
+    let syncing = tokio::select! {
+        result = wait_for_signal() => {
+            if result.is_err() {
+                tracing::warn!("Failed to listen for signals, shutting down gracefully is not possible");
+                return;
+            }
+
+            true
+        }
+        _ = finished.cancelled() => false,
+    };
+
+    if syncing {
+        repo.shut_down();
+    }
+
+    if wait_for_signal().await.is_ok() {
+        match syncing {
+            true => tracing::error!("Received a second signal, exiting immediately"),
+            false => tracing::error!("Received a signal, exiting immediately"),
+        }
+        std::process::exit(130);
+    }
+}
+
+async fn wait_for_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Seed the database with the plugins of a generated tree.
+///
+/// Imported updates are not stale, so the next sync only fetches what is
+/// missing or changed since the tree was generated. Plugins already known to
+/// the database are left alone.
+pub async fn import_meta(args: &IndexerArgs, import_args: &ImportMetaArgs) -> Result<(), IndexerError> {
+    let database = Database::setup(args).await?;
+    database.ensure_writable("import metadata")?;
+
+    let client = crate::api::http_client(&import_args.api)?;
+    let source = &import_args.source;
+    let xml_ids = output::read_tree_index(source, &client).await?;
+    tracing::info!("Importing {} plugins", xml_ids.len());
+
+    let mut imported = 0usize;
+    let mut failed = 0usize;
+    let mut skipped_versions = 0usize;
+
+    let mut results = futures::stream::iter(&xml_ids)
+        .map(|xml_id| output::read_tree_plugin(source, &client, xml_id))
+        .buffer_unordered(import_args.jobs.get());
+
+    while let Some(result) = results.next().await {
+        let plugin = match result {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::warn!("Failed to read plugin from tree: {:?}", err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        skipped_versions += plugin.skipped_versions;
+        if store_published_plugin(&database, plugin).await? {
+            imported += 1;
         }
     }
 
-    pub async fn generate_metadata(&self) -> Result<(), IndexerError> {
-        output::generate_into(&self.output_directory, self.database.clone()).await
+    if skipped_versions > 0 {
+        tracing::warn!(
+            "Skipped {} versions without update id or supported hash, the next sync adds them",
+            skipped_versions
+        );
     }
+
+    tracing::info!(
+        "Imported {} plugins, {} were known already and {} failed",
+        imported,
+        xml_ids.len() - imported - failed,
+        failed
+    );
+
+    Ok(())
+}
+
+/// Store a plugin read from a tree, returning whether it was new.
+async fn store_published_plugin(
+    database: &Database,
+    published: PublishedPlugin,
+) -> Result<bool, IndexerError> {
+    let xml_id = &published.plugin.xml_id;
+    let batch = database.begin_batch().await?;
+
+    if !batch.add_plugin(&published.plugin, &published.repository).await? {
+        tracing::debug!("Plugin '{}' is known already", xml_id);
+        return Ok(false);
+    }
+
+    batch.change_plugin_details(xml_id, &published.details).await?;
+    batch.set_plugin_tags(xml_id, &published.tags).await?;
+    batch.set_plugin_screenshots(xml_id, &published.screenshots).await?;
+
+    for published_update in published.updates {
+        let update = &published_update.update;
+
+        batch.add_update(update.id, &published.repository).await?;
+        batch.add_plugin_version(&published_update.version).await?;
+        batch.change_update_info(update).await?;
+        batch
+            .set_update_build_range(
+                update.id,
+                update.since_build.as_deref(),
+                update.until_build.as_deref(),
+            )
+            .await?;
+        batch.set_update_release_date(update.id, update.release_date).await?;
+        batch.set_update_change_notes(update.id, update.change_notes.as_deref()).await?;
+
+        for dependency in &published_update.dependencies {
+            batch.add_update_dependency(dependency).await?;
+        }
+    }
+
+    batch.commit().await?;
+    Ok(true)
+}
+
+/// Generate and publish a tree, with `--watch` again whenever the plugins
+/// needing to be generated change.
+async fn generate_and_publish(
+    generate_args: &GenerateArgs,
+    database: Database,
+    options: &GenerateOptions,
+) -> Result<(), IndexerError> {
+    let directory = &generate_args.output_directory;
+
+    loop {
+        let generated_at = database.get_change_counter().await?;
+        let result = match output::generate_into(directory, database.clone(), options).await {
+            Ok(()) => output::publish_tree(directory, options).await,
+            Err(err) => Err(err),
+        };
+
+        if !generate_args.watch {
+            return result;
+        }
+
+        // A long running generator outlives single failed generations
+        if let Err(err) = result {
+            tracing::error!("Failed to generate metadata: {:?}", err);
+        }
+
+        // Plugins still dirty now could not be generated cleanly, only
+        // plugins marked dirty since the generation started or a difference
+        // means the database was changed
+        let generated = database.get_dirty_plugins().await?;
+        tracing::info!("Watching the database for changes...");
+
+        loop {
+            if database.get_change_counter().await? > generated_at {
+                tracing::info!("Plugins changed since generating, generating again");
+                break;
+            }
+
+            let dirty = database.get_dirty_plugins().await?;
+            if dirty != generated {
+                tracing::info!(
+                    "{} plugins changed, generating again",
+                    dirty.symmetric_difference(&generated).count()
+                );
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(generate_args.watch_interval_ms)).await;
+        }
+    }
+}
+
+/// The plugins and all plugins they transitively require.
+async fn dependency_closure(
+    database: &Database,
+    plugins: &[String],
+) -> Result<HashSet<String>, IndexerError> {
+    let known = database.known_plugin_xml_ids().await?;
+    if let Some(unknown) = plugins.iter().find(|v| !known.contains(*v)) {
+        return Err(IndexerError::IncompleteData(format!(
+            "plugin '{}' is not in the database",
+            unknown
+        )));
+    }
+
+    let mut required = HashMap::<String, Vec<String>>::new();
+    for dependency in database.get_plugin_dependencies().await? {
+        if !dependency.optional {
+            required
+                .entry(dependency.plugin_xml_id)
+                .or_default()
+                .push(dependency.dependency_xml_id);
+        }
+    }
+
+    let mut closure = HashSet::new();
+    let mut pending = plugins.to_vec();
+
+    while let Some(plugin) = pending.pop() {
+        if !closure.insert(plugin.clone()) {
+            continue;
+        }
+
+        if let Some(dependencies) = required.get(&plugin) {
+            pending.extend(dependencies.iter().cloned());
+        }
+    }
+
+    Ok(closure)
+}
+
+fn normalize_channel(channel: &str) -> String {
+    if channel.is_empty() {
+        "stable".to_string()
+    } else {
+        channel.to_lowercase()
+    }
+}
+
+/// The channels selected on the command line, `None` if all are.
+fn selected_channels(channels: &[String]) -> Option<BTreeSet<String>> {
+    (!channels.is_empty()).then(|| channels.iter().map(|v| normalize_channel(v)).collect())
+}
+
+/// Keep only the `count` most recently uploaded versions of every channel,
+/// newer uploads have higher update ids.
+fn newest_per_channel<T>(
+    mut versions: Vec<T>,
+    count: usize,
+    key: impl Fn(&T) -> (&str, u64),
+) -> Vec<T> {
+    versions.sort_by_key(|v| std::cmp::Reverse(key(v).1));
+
+    let mut kept = HashMap::<String, usize>::new();
+    versions.retain(|v| {
+        let seen = kept.entry(key(v).0.to_owned()).or_default();
+        *seen += 1;
+        *seen <= count
+    });
+
+    versions
+}
+
+/// Seconds since the unix epoch, as stored in the database.
+pub fn unix_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|v| v.as_secs() as i64)
+        .unwrap_or_default()
 }
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// The subset of Nix values the generated expressions are made of.
+#[derive(Debug)]
+pub enum NixValue {
+    String(String),
+    Bool(bool),
+    Import(String),
+    List(Vec<NixValue>),
+    Attrs(BTreeMap<String, NixValue>),
+}
+
+impl NixValue {
+    /// Render the value as a standalone `.nix` file.
+    pub fn to_file(&self) -> Vec<u8> {
+        let mut out = String::from("# Generated by jb-repo-indexer, do not edit.\n");
+        self.render(&mut out, 0);
+        out.push('\n');
+
+        out.into_bytes()
+    }
+
+    fn render(&self, out: &mut String, indent: usize) {
+        match self {
+            Self::String(v) => out.push_str(&string(v)),
+            Self::Bool(v) => out.push_str(if *v { "true" } else { "false" }),
+            Self::Import(v) => {
+                out.push_str("import ");
+                out.push_str(v);
+            }
+            Self::List(items) => {
+                out.push('[');
+                for item in items {
+                    out.push(' ');
+                    item.render(out, indent);
+                }
+                out.push_str(" ]");
+            }
+            Self::Attrs(attrs) if attrs.is_empty() => out.push_str("{ }"),
+            Self::Attrs(attrs) => {
+                out.push_str("{\n");
+
+                for (key, value) in attrs {
+                    let _ = write!(out, "{:width$}{} = ", "", string(key), width = indent + 2);
+                    value.render(out, indent + 2);
+                    out.push_str(";\n");
+                }
+
+                let _ = write!(out, "{:width$}}}", "", width = indent);
+            }
+        }
+    }
+}
+
+/// A double-quoted Nix string, escaping everything that would otherwise
+/// be interpreted.
+fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
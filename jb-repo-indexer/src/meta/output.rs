@@ -1,241 +1,3306 @@
-use crate::db::{CachedPlugin, CachedUpdateDependency, Database};
+use crate::args::{
+    BundleFormat, CompressionFormat, GenerateArgs, LatestStrategy, OutputFormat, SwapStrategy,
+};
+use crate::artifacts::ArtifactCache;
+use crate::db::{
+    CachedPlugin, CachedPluginDetails, CachedPluginVersion, CachedUpdate, CachedUpdateDependency,
+    Database,
+};
 use crate::error::IndexerError;
-use crate::meta::TaskAttachment;
+use crate::overrides::Overrides;
+use crate::meta::nix::NixValue;
+use crate::meta::version::compare_versions;
+use crate::s3::S3Bucket;
+use crate::signing::{KEYS_FILE, KeyManifest, SigningKey};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use futures::stream::FuturesUnordered;
-use futures::{FutureExt, StreamExt as _};
-use semver::Version;
-use serde::Serialize;
+use futures::StreamExt as _;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use sha2::Digest as _;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::cmp::Ordering;
 use std::collections::btree_map::Entry;
-use std::future;
+use std::io::{Read as _, Write as _};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
 
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Maximum allowed shrink of the plugin index compared to the previous
+    /// generation, in percent.
+    pub max_index_shrink: f64,
+
+    /// Size in bytes above which the versions of a plugin are split across
+    /// multiple chained files.
+    pub max_metadata_file_size: usize,
+
+    /// Default strategy for selecting the latest version per channel.
+    pub latest_strategy: LatestStrategy,
+
+    pub overrides: Arc<Overrides>,
+
+    /// Whether versions which vanished upstream are kept in an `archived` section.
+    pub retain_archived_versions: bool,
+
+    /// Seconds versions which vanished upstream are kept in a `removed`
+    /// section, if they are not retained.
+    pub tombstone_max_age: Option<i64>,
+
+    /// Maximum characters of the published change notes, if they are published.
+    pub change_notes_max_length: Option<usize>,
+
+    /// Only publish this many of the newest versions of every channel.
+    pub max_versions_per_channel: Option<usize>,
+
+    /// Only publish versions of these channels instead of all.
+    pub channels: Option<BTreeSet<String>>,
+
+    /// Whether incomplete data fails the generation instead of being skipped.
+    pub strict: bool,
+
+    /// Maximum number of stale versions tolerated before failing.
+    pub fail_on_stale: Option<usize>,
+
+    /// Number of plugins generated concurrently.
+    pub jobs: usize,
+
+    /// Base URL and directory of the artifact mirror, if published through one.
+    pub mirror: Option<(Url, ArtifactCache)>,
+
+    pub backend: OutputBackend,
+
+    /// How a new tree replaces the old one.
+    pub swap_strategy: SwapStrategy,
+
+    /// Whether plugins which did not change are generated again as well.
+    pub full: bool,
+
+    /// Whether a snapshot of the generated tree is added.
+    pub snapshot: bool,
+    pub max_snapshots: Option<usize>,
+
+    /// Only generate these plugins, for trees apart from the main one.
+    pub plugins: Option<Arc<HashSet<String>>>,
+
+    pub compression: OutputCompression,
+    pub output_format: OutputFormat,
+
+    /// Format of the file bundling all plugins, if one is written.
+    pub bundle: Option<BundleFormat>,
+
+    pub signing_key: Option<Arc<SigningKey>>,
+}
+
+/// Which variants of every generated file are written.
+#[derive(Debug, Clone)]
+pub struct OutputCompression {
+    pub formats: Vec<CompressionFormat>,
+    pub keep_uncompressed: bool,
+}
+
+/// Where the validated tree is published to, besides the output directory.
+#[derive(Debug, Clone)]
+pub enum OutputBackend {
+    /// The output directory is all there is.
+    Local,
+
+    /// S3 compatible storage, with a key prefix ending in a slash if not empty.
+    S3 { bucket: Box<S3Bucket>, prefix: String },
+}
+
+impl GenerateOptions {
+    pub async fn new(args: &GenerateArgs, overrides: Arc<Overrides>) -> Result<Self, IndexerError> {
+        let backend = match &args.s3.s3_bucket {
+            Some(bucket) => {
+                let mut prefix = args.s3.s3_prefix.trim_matches('/').to_owned();
+                if !prefix.is_empty() {
+                    prefix.push('/');
+                }
+
+                OutputBackend::S3 {
+                    bucket: Box::new(S3Bucket::new(&args.s3, bucket.clone())?),
+                    prefix,
+                }
+            }
+            None => OutputBackend::Local,
+        };
+
+        if args.bundle.is_some() && !args.output_format.writes_json() {
+            return Err(IndexerError::IncompleteData(
+                "bundles are built from the JSON output".into(),
+            ));
+        }
+
+        Ok(Self {
+            max_index_shrink: args.max_index_shrink,
+            max_metadata_file_size: args.max_metadata_file_size,
+            latest_strategy: args.latest_strategy,
+            overrides,
+            retain_archived_versions: args.retain_archived_versions,
+            tombstone_max_age: args.tombstone_days.map(|v| i64::from(v) * 24 * 60 * 60),
+            change_notes_max_length: args
+                .include_change_notes
+                .then_some(args.change_notes_max_length.get()),
+            max_versions_per_channel: args.max_versions_per_plugin.map(|v| v.get()),
+            channels: super::selected_channels(&args.channels),
+            strict: args.strict,
+            fail_on_stale: args.fail_on_stale,
+            jobs: args.generate_jobs.get(),
+            mirror: args
+                .mirror_url
+                .clone()
+                .zip(args.mirror_directory.as_ref().map(ArtifactCache::new)),
+            backend,
+            swap_strategy: args.swap,
+            full: args.full,
+            snapshot: args.snapshot,
+            max_snapshots: args.max_snapshots.map(|v| v.get()),
+            plugins: None,
+            compression: OutputCompression {
+                formats: args.compress.clone(),
+                keep_uncompressed: !args.no_uncompressed,
+            },
+            output_format: args.output_format,
+            bundle: args.bundle,
+            signing_key: match &args.signing_key {
+                Some(path) => Some(Arc::new(SigningKey::load(path).await?)),
+                None => None,
+            },
+        })
+    }
+
+    /// Identifies the options which influence the generated files, plugins
+    /// generated with different options can't be reused.
+    fn fingerprint(&self) -> i64 {
+        let options = format!(
+            "{}|{}|{}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            env!("CARGO_PKG_VERSION"),
+            OUTPUT_REVISION,
+            self.max_metadata_file_size,
+            self.latest_strategy,
+            self.retain_archived_versions,
+            self.tombstone_max_age,
+            self.change_notes_max_length,
+            self.mirror.as_ref().map(|(url, _)| url.as_str()),
+            self.overrides,
+            self.compression,
+            self.output_format,
+            self.max_versions_per_channel,
+            self.channels,
+            self.signing_key.as_ref().map(|v| v.public_key())
+        );
+
+        let digest = sha2::Sha256::digest(options.as_bytes());
+        i64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// Whether versions of a channel are published.
+    fn includes_channel(&self, channel: &str) -> bool {
+        self.channels
+            .as_ref()
+            .is_none_or(|v| v.contains(&super::normalize_channel(channel)))
+    }
+
+    /// Download URL of an artifact in the mirror, if it has been mirrored.
+    async fn mirrored_url(&self, algorithm: &str, hash: &[u8]) -> Result<Option<String>, IndexerError> {
+        let Some((base, mirror)) = &self.mirror else {
+            return Ok(None);
+        };
+
+        if !mirror.contains(algorithm, hash).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(format!(
+            "{}/{}",
+            base.as_str().trim_end_matches('/'),
+            ArtifactCache::key_for(algorithm, hash)
+        )))
+    }
+}
+
+/// Generate the metadata tree into the given directory.
+///
+/// The tree is first generated into a new directory next to the target,
+/// validated and only then swapped in place of the old tree. If validation
+/// fails, the old tree is kept untouched.
 pub async fn generate_into(
     directory: impl Into<PathBuf>,
     database: Database,
+    options: &GenerateOptions,
 ) -> Result<(), IndexerError> {
     let directory = directory.into();
+
+    // Plugins changing during the generation have to stay dirty
+    let generated_at = database.get_change_counter().await?;
+
+    let new_directory = match options.swap_strategy {
+        SwapStrategy::Rename => {
+            recover_interrupted_rename(&directory).await?;
+            sibling_directory(&directory, "staging")
+        }
+        SwapStrategy::Symlink => {
+            let generations = sibling_directory(&directory, "generations");
+            prune_generations(&generations, current_generation(&directory).await?.as_deref())
+                .await?;
+
+            generations.join(format!("{}-{}", super::unix_timestamp(), std::process::id()))
+        }
+    };
+
+    let reusable = reusable_tree(&directory, &database, options).await?;
+
+    let regenerated =
+        match finish_tree(&directory, &new_directory, &database, options, reusable).await {
+            Ok(v) => v,
+            Err(err) => {
+                remove_dir_if_exists(&new_directory).await?;
+                return Err(err);
+            }
+        };
+
+    if let Err(err) = validate_tree(&new_directory, &directory, &database, options).await {
+        tracing::error!(
+            "Generated tree failed validation, keeping old tree at {}",
+            directory.display()
+        );
+        remove_dir_if_exists(&new_directory).await?;
+        return Err(err);
+    }
+
+    match options.swap_strategy {
+        SwapStrategy::Rename => swap_by_rename(&directory, &new_directory).await?,
+        SwapStrategy::Symlink => swap_by_symlink(&directory, &new_directory).await?,
+    }
+
+    // Which plugins are dirty stays up to the process owning the database
+    if options.plugins.is_some() || database.is_read_only() {
+        return Ok(());
+    }
+
+    database.mark_plugins_clean(&regenerated, generated_at).await?;
+    database
+        .set_sync_state(GENERATE_OPTIONS_FINGERPRINT, options.fingerprint())
+        .await
+}
+
+/// Generate a tree, report how it differs from the current one and sign it.
+async fn finish_tree(
+    directory: &Path,
+    new_directory: &Path,
+    database: &Database,
+    options: &GenerateOptions,
+    reusable: Option<Arc<ReusableTree>>,
+) -> Result<Vec<String>, IndexerError> {
+    let regenerated = generate_tree(new_directory, database, options, reusable.clone()).await?;
+
+    if options.output_format.writes_json() {
+        write_changes(directory, new_directory, reusable.as_deref(), &options.compression).await?;
+    }
+
+    if let Some(key) = &options.signing_key {
+        sign_tree(new_directory, key.clone()).await?;
+    }
+
+    // Trees of only some plugins don't carry the history of the main tree,
+    // signed trees are snapshotted together with their signatures
+    if options.plugins.is_none() {
+        write_snapshots(directory, new_directory, options).await?;
+    }
+
+    Ok(regenerated)
+}
+
+/// Bumped whenever the same data results in different files, so trees of
+/// older revisions are not reused.
+const OUTPUT_REVISION: u32 = 18;
+
+/// Key in the sync state of the options the current tree was generated with.
+const GENERATE_OPTIONS_FINGERPRINT: &str = "generate_options_fingerprint";
+
+/// The current tree, if plugins which did not change can be taken from it.
+struct ReusableTree {
+    directory: PathBuf,
+    dirty: HashSet<String>,
+}
+
+async fn reusable_tree(
+    directory: &Path,
+    database: &Database,
+    options: &GenerateOptions,
+) -> Result<Option<Arc<ReusableTree>>, IndexerError> {
+    // Dirty plugins are only tracked for the main tree
+    if options.full
+        || options.plugins.is_some()
+        || !output_file_exists(&directory.join("index.json")).await?
+    {
+        return Ok(None);
+    }
+
+    let fingerprint = database.get_sync_state(GENERATE_OPTIONS_FINGERPRINT).await?;
+    if fingerprint != Some(options.fingerprint()) {
+        tracing::info!("Generation options changed, regenerating all plugins");
+        return Ok(None);
+    }
+
+    let dirty = database.get_dirty_plugins().await?;
+    tracing::info!("Regenerating {} changed plugins", dirty.len());
+
+    Ok(Some(Arc::new(ReusableTree {
+        directory: directory.to_path_buf(),
+        dirty,
+    })))
+}
+
+/// Hard link the files of a plugin from the previous tree, returning
+/// whether the plugin was part of it.
+async fn link_plugin_directory(previous: &Path, plugin_directory: &Path) -> Result<bool, IndexerError> {
+    let mut entries = match tokio::fs::read_dir(previous).await {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    tokio::fs::create_dir_all(plugin_directory).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let target = plugin_directory.join(entry.file_name());
+
+        if tokio::fs::hard_link(entry.path(), &target).await.is_err() {
+            tokio::fs::copy(entry.path(), &target).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Restore the old tree if a previous run crashed in the middle of swapping
+/// it out, then clean up the leftovers.
+async fn recover_interrupted_rename(directory: &Path) -> Result<(), IndexerError> {
+    let old_directory = sibling_directory(directory, "old");
+
+    if !tokio::fs::try_exists(directory).await? && tokio::fs::try_exists(&old_directory).await? {
+        tracing::warn!("Restoring tree of an interrupted swap at {}", directory.display());
+        tokio::fs::rename(&old_directory, directory).await?;
+    }
+
+    remove_dir_if_exists(&sibling_directory(directory, "staging")).await?;
+    remove_dir_if_exists(&old_directory).await
+}
+
+/// Rename the old tree aside and the new one into its place.
+///
+/// The directory briefly does not exist in between, the symlink strategy
+/// avoids that.
+async fn swap_by_rename(directory: &Path, new_directory: &Path) -> Result<(), IndexerError> {
+    let old_directory = sibling_directory(directory, "old");
+
+    if tokio::fs::try_exists(directory).await? {
+        tokio::fs::rename(directory, &old_directory).await?;
+    }
+
+    tokio::fs::rename(new_directory, directory).await?;
+    remove_dir_if_exists(&old_directory).await
+}
+
+/// Atomically point the directory, a symlink, at a new generation.
+async fn swap_by_symlink(directory: &Path, generation: &Path) -> Result<(), IndexerError> {
+    let generations = generation.parent().unwrap();
+    let link = sibling_directory(directory, "next");
+
+    // Relative, so the tree can be moved around as a whole
+    let target = Path::new(generations.file_name().unwrap()).join(generation.file_name().unwrap());
+
+    match tokio::fs::remove_file(&link).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    tokio::fs::symlink(&target, &link).await?;
+
+    // A real directory from the rename strategy can't be replaced atomically
+    let is_directory = match tokio::fs::symlink_metadata(directory).await {
+        Ok(v) => v.is_dir(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err.into()),
+    };
+
+    if is_directory {
+        tracing::warn!("Converting {} into a symlink to generations", directory.display());
+        swap_by_rename(directory, &link).await?;
+    } else {
+        tokio::fs::rename(&link, directory).await?;
+    }
+
+    let name = generation.file_name().unwrap().to_string_lossy();
+    prune_generations(generations, Some(&name)).await
+}
+
+/// Name of the generation a symlinked directory points to.
+async fn current_generation(directory: &Path) -> Result<Option<String>, IndexerError> {
+    match tokio::fs::read_link(directory).await {
+        Ok(target) => Ok(target.file_name().map(|v| v.to_string_lossy().into_owned())),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::InvalidInput
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Remove all generations but the current and the one before it, which
+/// readers may still be in the middle of.
+async fn prune_generations(generations: &Path, current: Option<&str>) -> Result<(), IndexerError> {
+    let mut names = Vec::new();
+
+    let mut entries = match tokio::fs::read_dir(generations).await {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+
+    // Names start with the timestamp of their generation
+    names.sort_by_key(|v| {
+        let timestamp = v.split('-').next().and_then(|v| v.parse::<i64>().ok());
+        (timestamp, v.clone())
+    });
+
+    let previous = current.and_then(|current| {
+        let position = names.iter().position(|v| v == current)?;
+        position.checked_sub(1).map(|v| names[v].clone())
+    });
+
+    for name in names {
+        if Some(name.as_str()) == current || Some(&name) == previous.as_ref() {
+            continue;
+        }
+
+        tracing::debug!("Removing generation {}", name);
+        remove_dir_if_exists(&generations.join(name)).await?;
+    }
+
+    Ok(())
+}
+
+/// Publish a validated tree to the output backend.
+///
+/// Plugin files are uploaded before the index referencing them, and objects
+/// which are gone are only deleted afterward, so consumers never observe an
+/// index pointing at missing files.
+pub async fn publish_tree(directory: &Path, options: &GenerateOptions) -> Result<(), IndexerError> {
+    let OutputBackend::S3 { bucket, prefix } = &options.backend else {
+        return Ok(());
+    };
+
+    let existing = bucket.list(prefix).await?;
+
+    let directory_owned = directory.to_path_buf();
+    let files = tokio::task::spawn_blocking(move || list_files(&directory_owned))
+        .await
+        .unwrap()?;
+
+    let key_for = |file: &Path| {
+        let segments = file
+            .components()
+            .map(|v| v.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+
+        format!("{}{}", prefix, segments.join("/"))
+    };
+
+    let (mut top_level, plugin_files): (Vec<_>, Vec<_>) =
+        files.iter().partition(|v| v.components().count() == 1);
+
+    // The index goes last, it makes everything else visible
+    top_level.sort_by_key(|v| uncompressed_path(v) == Path::new("index.json"));
+
+    let upload = |file: &PathBuf| {
+        let key = key_for(file);
+        let path = directory.join(file);
+        let existing_etag = existing.get(&key);
+
+        async move {
+            let data = tokio::fs::read(&path).await?;
+
+            // Etags of objects uploaded in a single part are their MD5 digest
+            if existing_etag.is_some_and(|v| *v == crate::hex::encode(md5::Md5::digest(&data))) {
+                return Ok::<_, IndexerError>(false);
+            }
+
+            let content_type = match path.extension().and_then(|v| v.to_str()) {
+                Some("gz") => "application/gzip",
+                Some("zst") => "application/zstd",
+                Some("xml") => "application/xml",
+                _ => "application/json",
+            };
+
+            bucket.put(&key, data, content_type).await?;
+            Ok(true)
+        }
+    };
+
+    let mut uploaded = 0usize;
+
+    let mut results = futures::stream::iter(plugin_files)
+        .map(upload)
+        .buffer_unordered(options.jobs);
+    while let Some(result) = results.next().await {
+        uploaded += usize::from(result?);
+    }
+
+    for file in top_level {
+        uploaded += usize::from(upload(file).await?);
+    }
+
+    let keys = files.iter().map(|v| key_for(v)).collect::<HashSet<_>>();
+    let removed = existing
+        .keys()
+        .filter(|v| !keys.contains(*v))
+        .collect::<Vec<_>>();
+
+    let mut results = futures::stream::iter(&removed)
+        .map(|key| bucket.delete(key))
+        .buffer_unordered(options.jobs);
+    while let Some(result) = results.next().await {
+        result?;
+    }
+
+    tracing::info!(
+        "Published tree to S3, uploaded {} and deleted {} objects",
+        uploaded,
+        removed.len()
+    );
+
+    Ok(())
+}
+
+/// Generate a complete tree, returning the plugins which have been
+/// regenerated without any problems.
+async fn generate_tree(
+    directory: &Path,
+    database: &Database,
+    options: &GenerateOptions,
+    reusable: Option<Arc<ReusableTree>>,
+) -> Result<Vec<String>, IndexerError> {
+    let directory = directory.to_path_buf();
     tokio::fs::create_dir_all(&directory).await?;
 
-    let plugin_index = database
-        .get_all_plugins()
-        .await?
-        .into_iter()
+    let mut failed_plugins = 0usize;
+    let mut stale_versions = BTreeMap::<String, usize>::new();
+    let mut plugin_index = BTreeMap::<String, String>::new();
+    let mut problems = BTreeMap::<String, String>::new();
+    let mut regenerated = Vec::new();
+
+    let mut plugins = database.get_all_plugins().await?;
+    if let Some(selected) = &options.plugins {
+        plugins.retain(|v| selected.contains(&v.xml_id));
+    }
+
+    let known_plugins = Arc::new(plugins.iter().map(|v| v.xml_id.clone()).collect::<HashSet<_>>());
+
+    let mut results = futures::stream::iter(plugins)
         .map(|plugin| {
+            let known_plugins = known_plugins.clone();
             let database = database.clone();
             let directory = directory.clone();
+            let options = options.clone();
+            let reusable = reusable.clone();
+
+            tokio::spawn(async move {
+                let hex_digest = plugin_digest(&plugin.xml_id);
+                let plugin_dir = plugin_directory(&directory, &hex_digest);
+
+                if let Some(reusable) = reusable
+                    && !reusable.dirty.contains(&plugin.xml_id)
+                {
+                    let previous_dir = plugin_directory(&reusable.directory, &hex_digest);
+
+                    match link_plugin_directory(&previous_dir, &plugin_dir).await {
+                        Ok(true) => {
+                            return GeneratedPlugin {
+                                xml_id: plugin.xml_id,
+                                hex_digest,
+                                stale_versions: Some(0),
+                                has_errors: false,
+                                has_tombstones: false,
+                                regenerated: false,
+                            };
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            tracing::warn!("Failed to reuse plugin '{}': {:?}", plugin.xml_id, err);
+                            remove_dir_if_exists(&plugin_dir).await.ok();
+                        }
+                    }
+                }
+
+                let outcome =
+                    generate_plugin(&plugin_dir, &plugin, &known_plugins, &database, &options).await;
+
+                let (stale, errors, has_tombstones) = match outcome {
+                    Ok(outcome) => {
+                        (Some(outcome.stale_versions), outcome.errors, outcome.has_tombstones)
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to generate plugin '{}': {:?}", plugin.xml_id, err);
+                        (None, vec![describe_error(&err)], false)
+                    }
+                };
+
+                let has_errors = !errors.is_empty();
+                if has_errors
+                    && let Err(err) =
+                        write_plugin_errors(&plugin_dir, &plugin.xml_id, errors, &options.compression)
+                            .await
+                {
+                    tracing::error!("Failed to write errors of plugin '{}': {:?}", plugin.xml_id, err);
+                }
+
+                GeneratedPlugin {
+                    xml_id: plugin.xml_id,
+                    hex_digest,
+                    stale_versions: stale,
+                    has_errors,
+                    has_tombstones,
+                    regenerated: true,
+                }
+            })
+        })
+        .buffer_unordered(options.jobs);
+
+    while let Some(result) = results.next().await {
+        let generated = match result {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!("Failed to process plugin: {:?}", err);
+                failed_plugins += 1;
+                continue;
+            }
+        };
+
+        if generated.has_errors {
+            problems.insert(
+                generated.xml_id.clone(),
+                format!(
+                    "{}/{}/{}/errors.json",
+                    &generated.hex_digest[0..2],
+                    &generated.hex_digest[2..4],
+                    &generated.hex_digest[4..]
+                ),
+            );
+        }
+
+        // Plugins with tombstones stay dirty, so expired ones are dropped
+        if generated.regenerated
+            && generated.stale_versions == Some(0)
+            && !generated.has_errors
+            && !generated.has_tombstones
+        {
+            regenerated.push(generated.xml_id.clone());
+        }
+
+        match generated.stale_versions {
+            Some(stale) => {
+                if stale > 0 {
+                    stale_versions.insert(generated.xml_id.clone(), stale);
+                }
+
+                plugin_index.insert(generated.xml_id, generated.hex_digest);
+            }
+            None => failed_plugins += 1,
+        }
+    }
+
+    if options.strict && failed_plugins > 0 {
+        return Err(IndexerError::IncompleteData(format!(
+            "{} plugins failed to generate",
+            failed_plugins
+        )));
+    }
+
+    let total_stale = stale_versions.values().sum::<usize>();
+    if total_stale > 0 {
+        report_stale_versions(&stale_versions, total_stale);
+    }
+
+    if let Some(max_stale) = options.fail_on_stale
+        && total_stale > max_stale
+    {
+        return Err(IndexerError::IncompleteData(format!(
+            "{} versions were skipped as stale, allowed are {}",
+            total_stale, max_stale
+        )));
+    }
+
+    if options.output_format.writes_nix() {
+        let index_nix = NixValue::Attrs(
+            plugin_index
+                .iter()
+                .map(|(xml_id, hex_digest)| {
+                    let path = plugin_directory(Path::new("."), hex_digest).join("default.nix");
+                    (xml_id.clone(), NixValue::Import(path.display().to_string()))
+                })
+                .collect(),
+        );
+
+        tokio::fs::write(directory.join("default.nix"), index_nix.to_file()).await?;
+    }
+
+    if let Some(format) = options.bundle {
+        write_bundle(&directory, &plugin_index, format, &options.compression).await?;
+    }
+
+    if options.output_format.writes_update_plugins_xml() {
+        write_update_plugins_xml(&directory, &plugin_index, &options.compression).await?;
+    }
+
+    let dependency_graph = dependency_graph(database, &plugin_index).await?;
+    let mut popularity = database.get_plugin_popularity().await?;
+    let mut families = database.get_plugin_families().await?;
+    let plugin_index = plugin_index
+        .into_iter()
+        .map(|(xml_id, digest)| {
+            let popularity = popularity.remove(&xml_id);
+            let entry = IndexEntry::Plugin {
+                digest,
+                downloads: popularity.as_ref().and_then(|v| v.downloads),
+                rating: popularity.and_then(|v| v.rating),
+                family: families.remove(&xml_id),
+            };
+
+            (xml_id, entry)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let index_path = directory.join("index.json");
+    let problems_path = directory.join("problems.json");
+    let dependencies_path = directory.join("dependencies.json");
+    let compression = options.compression.clone();
+    tokio::task::spawn_blocking(move || {
+        write_output_json(&dependencies_path, &dependency_graph, &compression)?;
+        write_output_json(&index_path, &plugin_index, &compression)?;
+        write_output_json(&problems_path, &Problems { plugins: problems }, &compression)?;
+
+        Ok::<_, IndexerError>(())
+    })
+    .await
+    .unwrap()?;
+
+    Ok(regenerated)
+}
+
+/// Write `changes.json`, describing how a new tree differs from the previous one.
+///
+/// Plugins which were taken over from the previous tree unchanged are not
+/// compared again.
+async fn write_changes(
+    previous: &Path,
+    directory: &Path,
+    reusable: Option<&ReusableTree>,
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    let new_index = read_index(&directory.join("index.json"))
+        .await?
+        .unwrap_or_default();
+    let old_index = read_index(&previous.join("index.json"))
+        .await?
+        .unwrap_or_default();
+
+    let mut changes = TreeChanges {
+        removed_plugins: old_index
+            .keys()
+            .filter(|v| !new_index.contains_key(*v))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+
+    for (xml_id, hex_digest) in &new_index {
+        let in_previous = old_index.contains_key(xml_id);
+        if !in_previous {
+            changes.added_plugins.push(xml_id.clone());
+        } else if reusable.is_some_and(|v| !v.dirty.contains(xml_id)) {
+            continue;
+        }
+
+        let plugin_dir = plugin_directory(directory, hex_digest);
+        let new_versions = match read_plugin_metadata(&plugin_dir).await {
+            Ok(v) => v.versions,
+            Err(err) => {
+                tracing::warn!("Failed to compare versions of plugin '{}': {:?}", xml_id, err);
+                continue;
+            }
+        };
+
+        let old_versions = match in_previous {
+            true => read_plugin_metadata(&plugin_directory(previous, hex_digest))
+                .await
+                .map(|v| v.versions)
+                .unwrap_or_default(),
+            false => BTreeMap::new(),
+        };
+
+        for (version, metadata) in &new_versions {
+            match old_versions.get(version) {
+                None => push_change(&mut changes.new_versions, xml_id, version),
+                Some(old) if old.hash != metadata.hash => {
+                    push_change(&mut changes.changed_hashes, xml_id, version)
+                }
+                Some(_) => {}
+            }
+        }
+
+        for version in old_versions.keys().filter(|v| !new_versions.contains_key(*v)) {
+            push_change(&mut changes.removed_versions, xml_id, version);
+        }
+    }
+
+    tracing::info!(
+        "Tree changes: {} plugins added, {} removed, {} new versions, {} changed hashes",
+        changes.added_plugins.len(),
+        changes.removed_plugins.len(),
+        changes.new_versions.values().map(Vec::len).sum::<usize>(),
+        changes.changed_hashes.values().map(Vec::len).sum::<usize>()
+    );
+
+    let path = directory.join(CHANGES_FILE);
+    let compression = compression.clone();
+    tokio::task::spawn_blocking(move || {
+        write_output_json(&path, &changes, &compression)
+    })
+    .await
+    .unwrap()
+}
+
+fn push_change(changes: &mut BTreeMap<String, Vec<String>>, xml_id: &str, version: &str) {
+    changes
+        .entry(xml_id.to_owned())
+        .or_default()
+        .push(version.to_owned());
+}
+
+/// Carry the snapshots of the current tree over into the new one, adding
+/// a snapshot of the new tree if enabled.
+///
+/// Snapshots are hard links of the files of their tree, which are never
+/// written again once generated.
+async fn write_snapshots(
+    directory: &Path,
+    new_directory: &Path,
+    options: &GenerateOptions,
+) -> Result<(), IndexerError> {
+    let mut snapshots = match output_file_exists(&directory.join(SNAPSHOTS_FILE)).await? {
+        true => read_output_file::<Snapshots>(directory.join(SNAPSHOTS_FILE)).await?.snapshots,
+        false => Vec::new(),
+    };
+
+    if options.snapshot {
+        let index = read_index(&new_directory.join("index.json")).await?.unwrap_or_default();
+        let id = super::unix_timestamp().to_string();
+
+        if !snapshots.iter().any(|v| v.id == id) {
+            let source = new_directory.to_path_buf();
+            let target = new_directory.join(SNAPSHOTS_DIRECTORY).join(&id);
+            tokio::task::spawn_blocking(move || link_tree(&source, &target))
+                .await
+                .unwrap()?;
+
+            tracing::info!("Added snapshot {} of {} plugins", id, index.len());
+            snapshots.push(Snapshot {
+                id,
+                created_at: super::unix_timestamp(),
+                plugins: index.len(),
+            });
+        }
+    }
+
+    if let Some(max) = options.max_snapshots
+        && snapshots.len() > max
+    {
+        let dropped = snapshots.drain(..snapshots.len() - max).collect::<Vec<_>>();
+        tracing::info!("Dropping {} old snapshots", dropped.len());
+    }
+
+    if snapshots.is_empty() {
+        return Ok(());
+    }
+
+    for snapshot in &snapshots {
+        let source = directory.join(SNAPSHOTS_DIRECTORY).join(&snapshot.id);
+        let target = new_directory.join(SNAPSHOTS_DIRECTORY).join(&snapshot.id);
+        if tokio::fs::try_exists(&target).await? {
+            continue;
+        }
+
+        tokio::task::spawn_blocking(move || link_tree(&source, &target))
+            .await
+            .unwrap()?;
+    }
+
+    let data = canonical_json(&Snapshots { snapshots })?;
+    let new_directory = new_directory.to_path_buf();
+    let compression = options.compression.clone();
+    let key = options.signing_key.clone();
+    tokio::task::spawn_blocking(move || {
+        write_output_file(&new_directory.join(SNAPSHOTS_FILE), &data, &compression)?;
+
+        // Written after the tree was signed
+        match key {
+            Some(key) => sign_file(&new_directory, Path::new(SNAPSHOTS_FILE), &key),
+            None => Ok(()),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Hard link all files of a tree into another directory, leaving out its
+/// snapshots.
+fn link_tree(source: &Path, target: &Path) -> Result<(), IndexerError> {
+    for file in list_files(source)? {
+        if file.starts_with(SNAPSHOTS_DIRECTORY) {
+            continue;
+        }
+
+        let target_file = target.join(&file);
+        if let Some(parent) = target_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if std::fs::hard_link(source.join(&file), &target_file).is_err() {
+            std::fs::copy(source.join(&file), &target_file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sign every generated JSON file, the signature is written next to it.
+async fn sign_tree(directory: &Path, key: Arc<SigningKey>) -> Result<(), IndexerError> {
+    let directory = directory.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        for file in signed_files(&directory)? {
+            sign_file(&directory, &file, &key)?;
+        }
+
+        std::fs::write(directory.join(KEYS_FILE), canonical_json(&key.manifest())?)?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Write the signature of a JSON file next to it.
+fn sign_file(directory: &Path, file: &Path, key: &SigningKey) -> Result<(), IndexerError> {
+    let data = read_output_bytes(&directory.join(file))?;
+    std::fs::write(directory.join(signature_path(file)), key.sign(&data) + "\n")?;
+    Ok(())
+}
+
+/// Check the signature of every JSON file in a tree.
+///
+/// The key is taken from `keys.json` unless one is pinned.
+pub async fn verify_tree_signatures(
+    directory: &Path,
+    pinned_key: Option<&str>,
+) -> Result<Vec<String>, IndexerError> {
+    let manifest: KeyManifest = read_output_file(directory.join(KEYS_FILE)).await?;
+    manifest.check_algorithm()?;
+
+    let mut issues = Vec::new();
+    if let Some(pinned_key) = pinned_key
+        && pinned_key.trim() != manifest.public_key
+    {
+        issues.push(format!("{} announces a different key", KEYS_FILE));
+    }
+
+    let public_key = pinned_key.unwrap_or(&manifest.public_key).to_owned();
+    let directory = directory.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        for file in signed_files(&directory)? {
+            let signature = match std::fs::read_to_string(directory.join(signature_path(&file))) {
+                Ok(v) => v,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    issues.push(format!("{} is not signed", file.display()));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let data = read_output_bytes(&directory.join(&file))?;
+            if !crate::signing::verify(&public_key, &data, &signature)? {
+                issues.push(format!("{} has an invalid signature", file.display()));
+            }
+        }
+
+        Ok(issues)
+    })
+    .await
+    .unwrap()
+}
+
+/// The JSON files of a tree, by the path of their uncompressed variant.
+fn signed_files(directory: &Path) -> Result<BTreeSet<PathBuf>, IndexerError> {
+    Ok(list_files(directory)?
+        .iter()
+        .map(|v| uncompressed_path(v))
+        .filter(|v| v.extension().is_some_and(|v| v == "json") && *v != Path::new(KEYS_FILE))
+        // Snapshots link the signatures of the tree they were taken of
+        .filter(|v| !v.starts_with(SNAPSHOTS_DIRECTORY))
+        .collect())
+}
+
+fn signature_path(path: &Path) -> PathBuf {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".sig");
+    PathBuf::from(signature)
+}
+
+/// Plugins mapped to the indexed plugins they depend on.
+async fn dependency_graph(
+    database: &Database,
+    plugin_index: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, PluginDependencies>, IndexerError> {
+    let mut graph = BTreeMap::<String, PluginDependencies>::new();
+
+    for dependency in database.get_plugin_dependencies().await? {
+        if !plugin_index.contains_key(&dependency.plugin_xml_id)
+            || !plugin_index.contains_key(&dependency.dependency_xml_id)
+        {
+            continue;
+        }
+
+        let entry = graph.entry(dependency.plugin_xml_id).or_default();
+        match dependency.optional {
+            true => entry.optional_dependencies.insert(dependency.dependency_xml_id),
+            false => entry.dependencies.insert(dependency.dependency_xml_id),
+        };
+    }
+
+    Ok(graph)
+}
+
+/// Write the complete metadata of all indexed plugins into a single file.
+async fn write_bundle(
+    directory: &Path,
+    plugin_index: &BTreeMap<String, String>,
+    format: BundleFormat,
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    let mut data = Vec::new();
+
+    match format {
+        BundleFormat::Json => data.push(b'{'),
+        BundleFormat::Ndjson => {}
+    }
+
+    for (index, (xml_id, hex_digest)) in plugin_index.iter().enumerate() {
+        let metadata = read_plugin_metadata(&plugin_directory(directory, hex_digest)).await?;
+
+        match format {
+            BundleFormat::Json => {
+                if index > 0 {
+                    data.push(b',');
+                }
+
+                serde_json::to_writer(&mut data, xml_id)?;
+                data.push(b':');
+                serde_json::to_writer(&mut data, &metadata)?;
+            }
+            BundleFormat::Ndjson => {
+                serde_json::to_writer(&mut data, &metadata)?;
+                data.push(b'\n');
+            }
+        }
+    }
+
+    let file_name = match format {
+        BundleFormat::Json => {
+            data.extend_from_slice(b"}\n");
+            "all-plugins.json"
+        }
+        BundleFormat::Ndjson => "all-plugins.ndjson",
+    };
+
+    let path = directory.join(file_name);
+    let compression = compression.clone();
+    tokio::task::spawn_blocking(move || write_output_file(&path, &data, &compression))
+        .await
+        .unwrap()
+}
+
+/// Write the custom repository format IDEs understand, one file per channel.
+///
+/// Every plugin is listed with its latest version and the latest version of
+/// each IDE branch, the IDE picks the newest compatible one.
+async fn write_update_plugins_xml(
+    directory: &Path,
+    plugin_index: &BTreeMap<String, String>,
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    let mut channels = BTreeMap::<String, String>::new();
+
+    for hex_digest in plugin_index.values() {
+        let metadata = read_plugin_metadata(&plugin_directory(directory, hex_digest)).await?;
+
+        let mut listed = BTreeMap::<&str, BTreeSet<&str>>::new();
+        for (channel, version) in &metadata.latest {
+            listed.entry(channel).or_default().insert(version);
+        }
+        for (channel, branches) in &metadata.latest_for_build {
+            listed
+                .entry(channel)
+                .or_default()
+                .extend(branches.values().map(String::as_str));
+        }
+
+        for (channel, versions) in listed {
+            let xml = channels.entry(channel.to_owned()).or_default();
+
+            for version in versions {
+                if let Some(version_metadata) = metadata.versions.get(version) {
+                    update_plugins_entry(xml, &metadata, version, version_metadata);
+                }
+            }
+        }
+    }
+
+    for (channel, entries) in channels {
+        let data = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plugins>\n{}</plugins>\n",
+            entries
+        );
+
+        let path = directory.join(update_plugins_file_name(&channel));
+        let compression = compression.clone();
+        tokio::task::spawn_blocking(move || write_output_file(&path, data.as_bytes(), &compression))
+            .await
+            .unwrap()?;
+    }
+
+    Ok(())
+}
+
+fn update_plugins_entry(
+    xml: &mut String,
+    metadata: &PluginMetadata,
+    version: &str,
+    version_metadata: &VersionMetadata,
+) {
+    use quick_xml::escape::escape;
+    use std::fmt::Write as _;
+
+    let _ = writeln!(
+        xml,
+        "  <plugin id=\"{}\" url=\"{}\" version=\"{}\">",
+        escape(&metadata.xml_id),
+        escape(&version_metadata.download_url),
+        escape(version)
+    );
+
+    let mut range = String::new();
+    if let Some(since) = &version_metadata.since {
+        let _ = write!(range, " since-build=\"{}\"", escape(since));
+    }
+    if let Some(until) = &version_metadata.until {
+        let _ = write!(range, " until-build=\"{}\"", escape(until));
+    }
+    if !range.is_empty() {
+        let _ = writeln!(xml, "    <idea-version{}/>", range);
+    }
+
+    if let Some(name) = &metadata.meta.name {
+        let _ = writeln!(xml, "    <name>{}</name>", escape(name));
+    }
+    if let Some(description) = &metadata.meta.description {
+        let _ = writeln!(xml, "    <description>{}</description>", escape(description));
+    }
+    if let Some(vendor) = &metadata.meta.vendor {
+        let attributes = [("url", &vendor.url), ("email", &vendor.email)]
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!(" {}=\"{}\"", key, escape(value.as_ref()?))))
+            .collect::<String>();
+        let _ = writeln!(
+            xml,
+            "    <vendor{}>{}</vendor>",
+            attributes,
+            escape(&vendor.name)
+        );
+    }
+    for dependency in &version_metadata.dependencies {
+        let _ = writeln!(xml, "    <depends>{}</depends>", escape(dependency));
+    }
+    for dependency in &version_metadata.optional_dependencies {
+        let _ = writeln!(xml, "    <depends optional=\"true\">{}</depends>", escape(dependency));
+    }
+
+    xml.push_str("  </plugin>\n");
+}
+
+/// The stable channel gets the name IDEs expect, the others a suffix.
+fn update_plugins_file_name(channel: &str) -> String {
+    match channel {
+        "stable" => "updatePlugins.xml".to_owned(),
+        channel => format!("updatePlugins-{}.xml", channel),
+    }
+}
+
+fn is_update_plugins_file(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|v| v.starts_with("updatePlugins") && v.ends_with(".xml") && !v.contains('/'))
+}
+
+struct GeneratedPlugin {
+    xml_id: String,
+    hex_digest: String,
+
+    /// Number of skipped stale versions, `None` if generation failed.
+    stale_versions: Option<usize>,
+    has_errors: bool,
+    has_tombstones: bool,
+
+    /// Whether the plugin was generated again instead of being reused.
+    regenerated: bool,
+}
+
+/// Record why a plugin is missing or incomplete next to its metadata.
+async fn write_plugin_errors(
+    plugin_directory: &Path,
+    xml_id: &str,
+    errors: Vec<String>,
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    tokio::fs::create_dir_all(plugin_directory).await?;
+
+    let mut errors = PluginErrors {
+        xml_id: xml_id.to_owned(),
+        errors,
+    };
+    errors.errors.sort();
+
+    let errors_path = plugin_directory.join("errors.json");
+    let compression = compression.clone();
+    tokio::task::spawn_blocking(move || {
+        write_output_json(&errors_path, &errors, &compression)?;
+        Ok::<_, IndexerError>(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Format an error together with its chain of causes.
+fn describe_error(err: &dyn std::error::Error) -> String {
+    let mut description = err.to_string();
+
+    let mut src = err.source();
+    while let Some(err) = src {
+        description.push_str(": ");
+        description.push_str(&err.to_string());
+        src = err.source();
+    }
+
+    description
+}
+
+async fn generate_plugin(
+    plugin_directory: impl AsRef<Path>,
+    plugin: &CachedPlugin,
+    known_plugins: &HashSet<String>,
+    database: &Database,
+    options: &GenerateOptions,
+) -> Result<PluginOutcome, IndexerError> {
+    let plugin_directory = plugin_directory.as_ref();
+    tokio::fs::create_dir_all(plugin_directory).await?;
+
+    let products = database.get_plugin_products(&plugin.xml_id).await?;
+    let products = &products;
+
+    let mut cached_versions = database.get_versions_for_plugin(&plugin.xml_id).await?;
+    cached_versions.retain(|v| options.includes_channel(&v.channel));
+    if let Some(max) = options.max_versions_per_channel {
+        cached_versions = super::newest_per_channel(cached_versions, max, |v| (&v.channel, v.update_id));
+    }
+
+    let version_results = cached_versions
+        .into_iter()
+        .map(|version| async move {
+            let label = format!("{} (update {})", version.version, version.update_id);
+
+            let result = generate_version(version, products, known_plugins, database, options).await;
+
+            (label, result)
+        })
+        .collect::<FuturesUnordered<_>>()
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut update_ids = BTreeMap::<String, u64>::new();
+    let mut versions = BTreeMap::<String, VersionMetadata>::new();
+    let mut stale_versions = 0;
+    let mut errors = Vec::new();
+
+    for (label, result) in version_results {
+        match result {
+            Ok(VersionOutcome::Generated(version, update_id, version_metadata)) => {
+                update_ids.insert(version.clone(), update_id);
+                versions.insert(version, *version_metadata);
+            }
+            Ok(VersionOutcome::Stale) => stale_versions += 1,
+            Ok(VersionOutcome::Skipped) => {}
+            Err(err) if options.strict => return Err(err),
+            Err(err) => {
+                tracing::error!("Failed to process version {}: {:?}", label, err);
+                errors.push(format!("{}: {}", label, describe_error(&err)));
+            }
+        }
+    }
+
+    let latest_strategy = options
+        .overrides
+        .plugin(&plugin.xml_id)
+        .and_then(|v| v.latest)
+        .unwrap_or(options.latest_strategy);
+
+    let latest = match latest_strategy {
+        LatestStrategy::Version => latest_by_version(&versions),
+        LatestStrategy::Upload => latest_by_upload(&versions, &update_ids),
+    };
+    let latest_for_build = latest_for_build(&versions);
+
+    let details = database.get_plugin_details(&plugin.xml_id).await?;
+
+    let mut archived = BTreeMap::new();
+    let mut removed = BTreeMap::new();
+    if let Some(max_age) = options.tombstone_max_age
+        && !options.retain_archived_versions
+    {
+        let removed_after = super::unix_timestamp() - max_age;
+
+        for archived_version in database.get_archived_versions_for_plugin(&plugin.xml_id).await? {
+            if archived_version.archived_at < removed_after
+                || !options.includes_channel(&archived_version.channel)
+            {
+                continue;
+            }
+
+            let Some(hash) = sri_hash(&archived_version.hash_algorithm, &archived_version.hash)
+            else {
+                continue;
+            };
+
+            removed.insert(
+                archived_version.version,
+                RemovedVersionMetadata {
+                    removed: true,
+                    update_id: archived_version.update_id,
+                    hash,
+                    channel: super::normalize_channel(&archived_version.channel),
+                    removed_at: archived_version.archived_at,
+                },
+            );
+        }
+    }
+
+    if options.retain_archived_versions {
+        for archived_version in database.get_archived_versions_for_plugin(&plugin.xml_id).await? {
+            if !options.includes_channel(&archived_version.channel) {
+                continue;
+            }
+
+            let Some(hash) = sri_hash(&archived_version.hash_algorithm, &archived_version.hash)
+            else {
+                continue;
+            };
+
+            let download_url = options
+                .mirrored_url(&archived_version.hash_algorithm, &archived_version.hash)
+                .await?
+                .unwrap_or(archived_version.download_url);
+
+            archived.insert(
+                archived_version.version,
+                ArchivedVersionMetadata {
+                    archived: true,
+                    update_id: archived_version.update_id,
+                    download_url,
+                    sha256: sha256_base64(&archived_version.hash_algorithm, &archived_version.hash),
+                    hash,
+                    channel: super::normalize_channel(&archived_version.channel),
+                    file_name: archived_version.file_name,
+                    archived_at: archived_version.archived_at,
+                },
+            );
+        }
+    }
+
+    let license = match (&details.pricing_model, &details.license_url) {
+        (None, None) => None,
+        (pricing_model, url) => {
+            let pricing_model = pricing_model.as_deref().map(str::to_lowercase);
+
+            Some(PluginLicense {
+                free: pricing_model.as_deref().is_none_or(|v| v == "free"),
+                pricing_model,
+                url: url.clone(),
+            })
+        }
+    };
+
+    // Free plugins don't need any licensing information
+    let purchase = match details.pricing_model.as_deref() {
+        None | Some("FREE") => None,
+        Some(pricing_model) => Some(PurchaseMetadata {
+            pricing_model: pricing_model.to_owned(),
+            product_code: details.product_code,
+            trial_available: details.trial_available,
+            trial_days: details.trial_days,
+            purchase_url: details.purchase_url,
+        }),
+    };
+
+    let tags = database.get_plugin_tags(&plugin.xml_id).await?;
+    let screenshots = database.get_plugin_screenshots(&plugin.xml_id).await?;
+    let media = (!screenshots.is_empty()).then_some(PluginMedia { screenshots });
+
+    let vendor = details.vendor_name.map(|name| PluginVendor {
+        name,
+        url: details.vendor_url,
+        email: details.vendor_email,
+    });
+
+    let meta = PluginMeta {
+        name: details.name,
+        description: details.description,
+        homepage: details.homepage_url,
+        vendor,
+        source_code_url: details.source_code_url,
+        bugtracker_url: details.bugtracker_url,
+        documentation_url: details.documentation_url,
+    };
+
+    let metadata = PluginMetadata {
+        xml_id: plugin.xml_id.clone(),
+        numeric_id: plugin.numeric_id,
+        repository: details.repository,
+        family: details.family,
+        meta,
+        tags,
+        media,
+        license,
+        purchase,
+        versions,
+        latest,
+        latest_for_build,
+        archived,
+        removed,
+        next: None,
+    };
+    let has_tombstones = !metadata.removed.is_empty();
+
+    write_plugin_metadata(
+        plugin_directory,
+        metadata,
+        options.max_metadata_file_size,
+        options.compression.clone(),
+        options.output_format,
+    )
+    .await?;
+
+    Ok(PluginOutcome {
+        stale_versions,
+        errors,
+        has_tombstones,
+    })
+}
+
+struct PluginOutcome {
+    stale_versions: usize,
+
+    /// Errors of versions which have been left out.
+    errors: Vec<String>,
+
+    /// Whether tombstones were published, which expire without the plugin
+    /// changing.
+    has_tombstones: bool,
+}
+
+async fn generate_version(
+    version: CachedPluginVersion,
+    products: &[String],
+    known_plugins: &HashSet<String>,
+    database: &Database,
+    options: &GenerateOptions,
+) -> Result<VersionOutcome, IndexerError> {
+    let pin = options.overrides.version(&version.plugin_xml_id, &version.version);
+    if pin.is_some_and(|v| v.exclude) {
+        tracing::info!(
+            "Excluding {}@{} as configured by the overrides",
+            version.plugin_xml_id,
+            version.version
+        );
+        return Ok(VersionOutcome::Skipped);
+    }
+
+    let (mut update_info, all_dependencies, mut dependency_configs, verified_compatible, mut known_bad) = tokio::try_join!(
+        database.get_update(version.update_id),
+        database.get_update_dependencies(version.update_id),
+        database.get_update_dependency_configs(version.update_id),
+        database.get_verified_compatible_builds(version.update_id),
+        database.is_update_known_bad(version.update_id)
+    )?;
+
+    if update_info.stale {
+        return skip_version(
+            options,
+            VersionOutcome::Stale,
+            format!("Update {} is stale", version.update_id),
+        );
+    }
+
+    if let Some(pin) = pin
+        && let Some((hash_algorithm, hash)) = pin.pinned_hash()?
+    {
+        update_info.hash_algorithm = Some(hash_algorithm);
+        update_info.hash = Some(hash);
+        if let Some(download_url) = &pin.download_url {
+            update_info.download_url = Some(download_url.clone());
+            update_info.fallback_download_url = None;
+        }
+
+        // Whatever drifted, the pinned artifact is known to match
+        known_bad = false;
+    }
+
+    let Some(download_url) = update_info.download_url else {
+        return skip_version(
+            options,
+            VersionOutcome::Skipped,
+            format!("No download URL for update {}", version.update_id),
+        );
+    };
+
+    let (Some(hash_algorithm), Some(hash)) = (update_info.hash_algorithm, update_info.hash) else {
+        return skip_version(
+            options,
+            VersionOutcome::Skipped,
+            format!("No hash for update {}", version.update_id),
+        );
+    };
+
+    let Some(sri) = sri_hash(&hash_algorithm, &hash) else {
+        return skip_version(
+            options,
+            VersionOutcome::Skipped,
+            format!(
+                "Unsupported hash algorithm {} for update {}",
+                hash_algorithm, version.update_id
+            ),
+        );
+    };
+    let sha256 = sha256_base64(&hash_algorithm, &hash);
+    // Fetchers fall back to the other URLs if the first one rots
+    let mut mirror_urls = Vec::new();
+    let download_url = match options.mirrored_url(&hash_algorithm, &hash).await? {
+        Some(mirrored) => {
+            mirror_urls.push(download_url);
+            mirrored
+        }
+        None => download_url,
+    };
+    mirror_urls.extend(update_info.fallback_download_url);
+
+    let channel = super::normalize_channel(&version.channel);
+
+    let dep_id = |d: CachedUpdateDependency| d.dependency_xml_id;
+
+    let (mut dependencies, mut optional_dependencies): (
+        Vec<CachedUpdateDependency>,
+        Vec<CachedUpdateDependency>,
+    ) = all_dependencies.into_iter().partition(|dep| !dep.optional);
+
+    // The database returns them in no particular order
+    dependencies.sort_by(|a, b| a.dependency_xml_id.cmp(&b.dependency_xml_id));
+    optional_dependencies.sort_by(|a, b| a.dependency_xml_id.cmp(&b.dependency_xml_id));
+
+    let resolved_dependencies = dependencies
+        .iter()
+        .map(|v| &v.dependency_xml_id)
+        .filter(|v| !is_ide_module(v) && known_plugins.contains(*v))
+        .cloned()
+        .collect();
+
+    Ok(VersionOutcome::Generated(
+        version.version,
+        version.update_id,
+        Box::new(VersionMetadata {
+            update_id: Some(version.update_id),
+            download_url,
+            mirror_urls,
+            sha256,
+            hash: sri,
+            channel,
+            dependencies: dependencies.into_iter().map(dep_id).collect(),
+            resolved_dependencies,
+            optional_dependency_configs: optional_dependencies
+                .iter()
+                .filter_map(|dep| {
+                    let config = dependency_configs.remove(&dep.dependency_xml_id)?;
+                    Some((dep.dependency_xml_id.clone(), config))
+                })
+                .collect(),
+            optional_dependencies: optional_dependencies.into_iter().map(dep_id).collect(),
+            file_name: update_info.file_name,
+            size: update_info.size,
+            release_date: update_info.release_date,
+            change_notes: options
+                .change_notes_max_length
+                .zip(update_info.change_notes.as_deref())
+                .map(|(max, notes)| change_notes_text(notes, max))
+                .filter(|v| !v.is_empty()),
+            since: update_info.since_build,
+            until: update_info.until_build,
+            products: products.to_vec(),
+            verified_compatible,
+            known_bad,
+        }),
+    ))
+}
+
+enum VersionOutcome {
+    Generated(String, u64, Box<VersionMetadata>),
+    Stale,
+    Skipped,
+}
+
+/// Plain text of HTML change notes, truncated to at most `max` characters.
+fn change_notes_text(html: &str, max: usize) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    let mut text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if let Some((end, _)) = text.char_indices().nth(max) {
+        // Too short for an ellipsis to fit
+        if max < 3 {
+            text.truncate(end);
+            return text;
+        }
+
+        let end = text[..end].char_indices().nth_back(2).map_or(0, |(i, _)| i);
+        text.truncate(end);
+        text.push_str("...");
+    }
+
+    text
+}
+
+/// Whether a dependency refers to a module bundled with the IDE instead of
+/// a plugin.
+fn is_ide_module(xml_id: &str) -> bool {
+    xml_id == "com.intellij" || xml_id.starts_with("com.intellij.modules.")
+}
+
+/// Skip a version with incomplete data, or fail in strict mode.
+fn skip_version(
+    options: &GenerateOptions,
+    outcome: VersionOutcome,
+    reason: String,
+) -> Result<VersionOutcome, IndexerError> {
+    if options.strict {
+        return Err(IndexerError::IncompleteData(reason));
+    }
+
+    tracing::warn!("{}", reason);
+    Ok(outcome)
+}
+
+/// Log a summary of the plugins with the most stale versions.
+fn report_stale_versions(stale_versions: &BTreeMap<String, usize>, total_stale: usize) {
+    const MAX_LISTED: usize = 25;
+
+    let mut by_count = stale_versions.iter().collect::<Vec<_>>();
+    by_count.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    tracing::warn!(
+        "Skipped {} stale versions across {} plugins:",
+        total_stale,
+        stale_versions.len()
+    );
+
+    for (xml_id, count) in by_count.iter().take(MAX_LISTED) {
+        tracing::warn!("- {}: {}", xml_id, count);
+    }
+
+    if by_count.len() > MAX_LISTED {
+        tracing::warn!("... and {} more plugins", by_count.len() - MAX_LISTED);
+    }
+}
+
+/// Format a hash as SRI string, if Nix knows the algorithm.
+pub fn sri_hash(algorithm: &str, hash: &[u8]) -> Option<String> {
+    let prefix = match normalize_hash_algorithm(algorithm).as_str() {
+        "SHA1" => "sha1",
+        "SHA256" => "sha256",
+        "SHA384" => "sha384",
+        "SHA512" => "sha512",
+        _ => return None,
+    };
+
+    Some(format!("{}-{}", prefix, BASE64_STANDARD.encode(hash)))
+}
+
+/// The raw base64 hash for the `sha256` field, only set for SHA-256 hashes.
+fn sha256_base64(algorithm: &str, hash: &[u8]) -> Option<String> {
+    (normalize_hash_algorithm(algorithm) == "SHA256").then(|| BASE64_STANDARD.encode(hash))
+}
+
+/// `.hash.json` spells algorithms like `SHA-256`, but don't rely on it.
+fn normalize_hash_algorithm(algorithm: &str) -> String {
+    algorithm.to_uppercase().replace(['-', '_'], "")
+}
+
+/// Select the highest version per channel.
+fn latest_by_version(versions: &BTreeMap<String, VersionMetadata>) -> BTreeMap<String, String> {
+    let mut latest = BTreeMap::<String, String>::new();
+
+    for (version, version_metadata) in versions {
+        let mut entry = match latest.entry(version_metadata.channel.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(version.clone());
+                continue;
+            }
+            Entry::Occupied(v) => v,
+        };
+
+        let newer = match compare_versions(version, entry.get()) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            // Differently spelled equal versions such as `1.01` and `1.1`, the most
+            // recent release wins
+            Ordering::Equal => version_metadata.release_date > versions[entry.get()].release_date,
+        };
+
+        if newer {
+            entry.insert(version.clone());
+        }
+    }
+
+    latest
+}
+
+/// Select the highest version per channel and IDE branch it is compatible with.
+///
+/// Only the branches at which the compatible versions change are listed, the
+/// first branch of every build range and the one after it. Other branches are
+/// answered by the highest listed branch below them, if that version's build
+/// range includes the branch.
+fn latest_for_build(
+    versions: &BTreeMap<String, VersionMetadata>,
+) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut branches = BTreeMap::<&str, BTreeSet<u32>>::new();
+
+    for version_metadata in versions.values() {
+        let channel_branches = branches.entry(&version_metadata.channel).or_default();
+
+        // Without a start the range starts at the first branch there is
+        let since = version_metadata.since.as_deref().and_then(build_branch).unwrap_or(0);
+        let after_until = version_metadata
+            .until
+            .as_deref()
+            .and_then(build_branch)
+            .map(|v| v + 1);
+        channel_branches.extend(std::iter::once(since).chain(after_until));
+    }
+
+    let mut latest = BTreeMap::new();
+
+    for (channel, channel_branches) in branches {
+        let mut channel_latest = BTreeMap::new();
+
+        for branch in channel_branches {
+            let newest = versions
+                .iter()
+                .filter(|(_, v)| v.channel == channel && supports_branch(v, branch))
+                .map(|(version, _)| version)
+                .max_by(|a, b| compare_versions(a, b));
+
+            if let Some(newest) = newest {
+                channel_latest.insert(branch.to_string(), newest.clone());
+            }
+        }
+
+        if !channel_latest.is_empty() {
+            latest.insert(channel.to_owned(), channel_latest);
+        }
+    }
+
+    latest
+}
+
+/// Whether the build range of a version includes any build of a branch.
+fn supports_branch(version: &VersionMetadata, branch: u32) -> bool {
+    let since = version.since.as_deref().and_then(build_branch);
+    let until = version.until.as_deref().and_then(build_branch);
+
+    since.is_none_or(|v| v <= branch) && until.is_none_or(|v| v >= branch)
+}
+
+/// The branch of a build number like `IU-233.11799.241` or `241.*`.
+fn build_branch(build: &str) -> Option<u32> {
+    let build = build.rsplit('-').next()?;
+    build.split('.').next()?.trim().parse().ok()
+}
+
+/// Select the most recently uploaded version per channel.
+///
+/// The marketplace hands out update ids in ascending order, so the highest
+/// update id is the most recent upload.
+fn latest_by_upload(
+    versions: &BTreeMap<String, VersionMetadata>,
+    update_ids: &BTreeMap<String, u64>,
+) -> BTreeMap<String, String> {
+    let mut latest = BTreeMap::<String, (u64, String)>::new();
+
+    for (version, version_metadata) in versions {
+        let update_id = update_ids[version];
+
+        match latest.entry(version_metadata.channel.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert((update_id, version.clone()));
+            }
+            Entry::Occupied(mut entry) => {
+                if update_id > entry.get().0 {
+                    entry.insert((update_id, version.clone()));
+                }
+            }
+        }
+    }
+
+    latest
+        .into_iter()
+        .map(|(channel, (_, version))| (channel, version))
+        .collect()
+}
+
+/// Write the metadata of a plugin, splitting the versions into chained
+/// `metadata-N.json` pages if the file would exceed the size limit.
+async fn write_plugin_metadata(
+    plugin_directory: &Path,
+    mut metadata: PluginMetadata,
+    max_file_size: usize,
+    compression: OutputCompression,
+    format: OutputFormat,
+) -> Result<(), IndexerError> {
+    let plugin_directory = plugin_directory.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        if format.writes_nix() {
+            std::fs::write(plugin_directory.join("default.nix"), plugin_nix(&metadata).to_file())?;
+        }
+
+        if !format.writes_json() {
+            return Ok(());
+        }
+
+        if metadata.versions.len() <= 1 || canonical_json_size(&metadata)? <= max_file_size {
+            write_output_json(&plugin_directory.join("metadata.json"), &metadata, &compression)?;
+            return Ok(());
+        }
+
+        let mut pages = Vec::<BTreeMap<String, VersionMetadata>>::new();
+        let mut current_page = BTreeMap::new();
+        let mut current_size = 0;
+
+        for (version, version_metadata) in std::mem::take(&mut metadata.versions) {
+            let entry_size = version.len() + canonical_json_size(&version_metadata)?;
+
+            if !current_page.is_empty() && current_size + entry_size > max_file_size {
+                pages.push(std::mem::take(&mut current_page));
+                current_size = 0;
+            }
+
+            current_size += entry_size;
+            current_page.insert(version, version_metadata);
+        }
+        pages.push(current_page);
+
+        tracing::debug!(
+            "Splitting metadata of {} into {} pages",
+            metadata.xml_id,
+            pages.len()
+        );
+
+        let page_count = pages.len();
+        let mut pages = pages.into_iter().enumerate();
+
+        let (_, first_page) = pages.next().unwrap();
+        metadata.versions = first_page;
+        metadata.next = (page_count > 1).then(|| page_file_name(1));
+        write_output_json(&plugin_directory.join("metadata.json"), &metadata, &compression)?;
+
+        for (index, versions) in pages {
+            let page = VersionsPage {
+                versions,
+                next: (index + 1 < page_count).then(|| page_file_name(index + 1)),
+            };
+
+            write_output_json(&plugin_directory.join(page_file_name(index)), &page, &compression)?;
+        }
+
+        Ok::<_, IndexerError>(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Serialize into canonical JSON, so identical data always results in
+/// identical bytes.
+///
+/// Object keys are sorted, going through `Value` does that as long as
+/// `serde_json` is built without `preserve_order`. Floats, such as ratings,
+/// are written in the shortest form `serde_json` finds to round-trip them,
+/// so their bytes only stay stable as long as that formatting does.
+fn canonical_json<T: Serialize>(value: &T) -> Result<Vec<u8>, IndexerError> {
+    let mut data = Vec::new();
+    write_canonical_json(&mut data, value)?;
+
+    Ok(data)
+}
+
+/// Serialize into canonical JSON, straight into a writer.
+fn write_canonical_json<T: Serialize>(
+    writer: &mut impl std::io::Write,
+    value: &T,
+) -> Result<(), IndexerError> {
+    serde_json::to_writer_pretty(&mut *writer, &serde_json::to_value(value)?)?;
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// The size of the canonical JSON of a value, without keeping it around.
+fn canonical_json_size<T: Serialize>(value: &T) -> Result<usize, IndexerError> {
+    let mut counter = ByteCounter(0);
+    write_canonical_json(&mut counter, value)?;
+
+    Ok(counter.0)
+}
+
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The attrset of a plugin for `default.nix`, with the stable version at
+/// the top level.
+fn plugin_nix(metadata: &PluginMetadata) -> NixValue {
+    let mut attrs = BTreeMap::from([(
+        "pname".to_owned(),
+        NixValue::String(metadata.xml_id.clone()),
+    )]);
+
+    if let Some(version) = metadata.latest.get("stable")
+        && let Some(version_metadata) = metadata.versions.get(version)
+    {
+        attrs.insert("version".into(), NixValue::String(version.clone()));
+        attrs.insert("url".into(), NixValue::String(version_metadata.download_url.clone()));
+        attrs.insert("hash".into(), NixValue::String(version_metadata.hash.clone()));
+    }
+
+    let meta = [
+        ("description", &metadata.meta.description),
+        ("homepage", &metadata.meta.homepage),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_owned(), NixValue::String(value.clone()?))))
+    .collect();
+    attrs.insert("meta".into(), NixValue::Attrs(meta));
+
+    if let Some(license) = &metadata.license {
+        attrs.insert("free".into(), NixValue::Bool(license.free));
+    }
+
+    let channels = metadata
+        .latest
+        .iter()
+        .map(|(channel, version)| (channel.clone(), NixValue::String(version.clone())))
+        .collect();
+    attrs.insert("channels".into(), NixValue::Attrs(channels));
+
+    let versions = metadata
+        .versions
+        .iter()
+        .map(|(version, version_metadata)| {
+            let mut version_attrs = BTreeMap::from([
+                ("version".to_owned(), NixValue::String(version.clone())),
+                ("url".to_owned(), NixValue::String(version_metadata.download_url.clone())),
+                ("hash".to_owned(), NixValue::String(version_metadata.hash.clone())),
+                ("channel".to_owned(), NixValue::String(version_metadata.channel.clone())),
+            ]);
+
+            if let Some(file_name) = &version_metadata.file_name {
+                version_attrs.insert("fileName".into(), NixValue::String(file_name.clone()));
+            }
+
+            if !version_metadata.mirror_urls.is_empty() {
+                let urls = version_metadata.mirror_urls.iter().cloned().map(NixValue::String).collect();
+                version_attrs.insert("mirrorUrls".into(), NixValue::List(urls));
+            }
+
+            if let Some(change_notes) = &version_metadata.change_notes {
+                version_attrs.insert("changeNotes".into(), NixValue::String(change_notes.clone()));
+            }
+
+            if version_metadata.known_bad {
+                version_attrs.insert("knownBad".into(), NixValue::Bool(true));
+            }
+
+            (version.clone(), NixValue::Attrs(version_attrs))
+        })
+        .collect();
+    attrs.insert("versions".into(), NixValue::Attrs(versions));
+
+    if !metadata.removed.is_empty() {
+        let removed = metadata
+            .removed
+            .iter()
+            .map(|(version, removed_metadata)| {
+                let removed_attrs = BTreeMap::from([
+                    ("removed".to_owned(), NixValue::Bool(true)),
+                    ("version".to_owned(), NixValue::String(version.clone())),
+                    ("hash".to_owned(), NixValue::String(removed_metadata.hash.clone())),
+                    ("channel".to_owned(), NixValue::String(removed_metadata.channel.clone())),
+                ]);
+
+                (version.clone(), NixValue::Attrs(removed_attrs))
+            })
+            .collect();
+        attrs.insert("removed".into(), NixValue::Attrs(removed));
+    }
+
+    NixValue::Attrs(attrs)
+}
+
+/// Write the canonical JSON of a value along with its compressed variants,
+/// streaming it into all of them at once instead of buffering each.
+fn write_output_json<T: Serialize>(
+    path: &Path,
+    value: &T,
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    let mut writer = OutputWriter::create(path, compression)?;
+    write_canonical_json(&mut writer, value)?;
+    writer.finish()
+}
+
+/// Writes a generated file and its compressed variants at the same time.
+struct OutputWriter {
+    sinks: Vec<OutputSink>,
+}
+
+enum OutputSink {
+    Plain(std::io::BufWriter<std::fs::File>),
+    Gzip(GzEncoder<std::io::BufWriter<std::fs::File>>),
+    Zstd(zstd::Encoder<'static, std::io::BufWriter<std::fs::File>>),
+}
+
+impl OutputWriter {
+    fn create(path: &Path, compression: &OutputCompression) -> Result<Self, IndexerError> {
+        let create = |path: &Path| std::fs::File::create(path).map(std::io::BufWriter::new);
+        let mut sinks = Vec::new();
+
+        if compression.keep_uncompressed {
+            sinks.push(OutputSink::Plain(create(path)?));
+        }
+
+        for format in &compression.formats {
+            let file = create(&compressed_path(path, *format))?;
+            sinks.push(match format {
+                CompressionFormat::Gzip => {
+                    OutputSink::Gzip(GzEncoder::new(file, flate2::Compression::best()))
+                }
+                CompressionFormat::Zstd => {
+                    OutputSink::Zstd(zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+                }
+            });
+        }
+
+        Ok(Self { sinks })
+    }
+
+    fn finish(self) -> Result<(), IndexerError> {
+        for sink in self.sinks {
+            let mut file = match sink {
+                OutputSink::Plain(file) => file,
+                OutputSink::Gzip(encoder) => encoder.finish()?,
+                OutputSink::Zstd(encoder) => encoder.finish()?,
+            };
+
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::io::Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for sink in &mut self.sinks {
+            match sink {
+                OutputSink::Plain(file) => file.write_all(buf)?,
+                OutputSink::Gzip(encoder) => encoder.write_all(buf)?,
+                OutputSink::Zstd(encoder) => encoder.write_all(buf)?,
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for sink in &mut self.sinks {
+            match sink {
+                OutputSink::Plain(file) => file.flush()?,
+                OutputSink::Gzip(encoder) => encoder.flush()?,
+                OutputSink::Zstd(encoder) => encoder.flush()?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a generated file along with its compressed variants.
+fn write_output_file(
+    path: &Path,
+    data: &[u8],
+    compression: &OutputCompression,
+) -> Result<(), IndexerError> {
+    if compression.keep_uncompressed {
+        std::fs::write(path, data)?;
+    }
+
+    for format in &compression.formats {
+        let compressed = match format {
+            CompressionFormat::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            CompressionFormat::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?,
+        };
+
+        std::fs::write(compressed_path(path, *format), compressed)?;
+    }
+
+    Ok(())
+}
+
+/// Read a generated file, falling back to its compressed variants.
+fn read_output_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
+    match std::fs::read(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+
+    for format in [CompressionFormat::Zstd, CompressionFormat::Gzip] {
+        let data = match std::fs::read(compressed_path(path, format)) {
+            Ok(v) => v,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        return decompress(&data, format);
+    }
+
+    Err(std::io::ErrorKind::NotFound.into())
+}
+
+fn decompress(data: &[u8], format: CompressionFormat) -> std::io::Result<Vec<u8>> {
+    match format {
+        CompressionFormat::Gzip => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        CompressionFormat::Zstd => zstd::decode_all(data),
+    }
+}
+
+async fn output_file_exists(path: &Path) -> Result<bool, IndexerError> {
+    for candidate in [
+        path.to_path_buf(),
+        compressed_path(path, CompressionFormat::Zstd),
+        compressed_path(path, CompressionFormat::Gzip),
+    ] {
+        if tokio::fs::try_exists(candidate).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn compressed_path(path: &Path, format: CompressionFormat) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(match format {
+        CompressionFormat::Gzip => ".gz",
+        CompressionFormat::Zstd => ".zst",
+    });
+
+    PathBuf::from(path)
+}
+
+/// The file a compressed variant was created from.
+fn uncompressed_path(path: &Path) -> PathBuf {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some("gz" | "zst") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn page_file_name(index: usize) -> String {
+    format!("metadata-{}.json", index)
+}
+
+/// Validate a freshly generated tree before it replaces the current one.
+async fn validate_tree(
+    staging_directory: &Path,
+    current_directory: &Path,
+    database: &Database,
+    options: &GenerateOptions,
+) -> Result<(), IndexerError> {
+    let new_index = read_index(&staging_directory.join("index.json"))
+        .await?
+        .ok_or_else(|| IndexerError::OutputValidation("index.json was not written".into()))?;
+
+    let mut known_plugins = database.known_plugin_xml_ids().await?;
+    if let Some(selected) = &options.plugins {
+        known_plugins.retain(|v| selected.contains(v));
+    }
+
+    if new_index.len() > known_plugins.len() {
+        return Err(IndexerError::OutputValidation(format!(
+            "index contains {} entries, but the database only knows {} plugins",
+            new_index.len(),
+            known_plugins.len()
+        )));
+    }
+
+    if new_index.len() < known_plugins.len() {
+        tracing::warn!(
+            "{} plugins known to the database are missing from the index",
+            known_plugins.len() - new_index.len()
+        );
+    }
+
+    for (xml_id, hex_digest) in &new_index {
+        if !known_plugins.contains(xml_id) {
+            return Err(IndexerError::OutputValidation(format!(
+                "index references plugin '{}' which is not in the database",
+                xml_id
+            )));
+        }
+
+        if *hex_digest != plugin_digest(xml_id) {
+            return Err(IndexerError::OutputValidation(format!(
+                "index entry of plugin '{}' has wrong digest {}",
+                xml_id, hex_digest
+            )));
+        }
+
+        let plugin_dir = plugin_directory(staging_directory, hex_digest);
+        if !options.output_format.writes_json() {
+            if !tokio::fs::try_exists(plugin_dir.join("default.nix")).await? {
+                return Err(IndexerError::OutputValidation(format!(
+                    "default.nix of plugin '{}' was not written",
+                    xml_id
+                )));
+            }
+
+            continue;
+        }
+
+        let metadata: PluginMetadata = read_output_file(plugin_dir.join("metadata.json")).await?;
+
+        if metadata.xml_id != *xml_id {
+            return Err(IndexerError::OutputValidation(format!(
+                "metadata of plugin '{}' claims to be for '{}'",
+                xml_id, metadata.xml_id
+            )));
+        }
+
+        let mut next = metadata.next;
+        while let Some(page_name) = next {
+            let page: VersionsPage = read_output_file(plugin_dir.join(page_name)).await?;
+            next = page.next;
+        }
+    }
+
+    let old_index = read_index(&current_directory.join("index.json")).await?;
+    if let Some(old_index) = old_index
+        && new_index.len() < old_index.len()
+    {
+        let shrink = (old_index.len() - new_index.len()) as f64 / old_index.len() as f64 * 100.0;
+
+        if shrink > options.max_index_shrink {
+            return Err(IndexerError::OutputValidation(format!(
+                "index shrunk by {:.2}% ({} -> {} plugins), allowed are {:.2}%",
+                shrink,
+                old_index.len(),
+                new_index.len(),
+                options.max_index_shrink
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Cross-check a published tree against the database.
+///
+/// Unlike the validation before swapping, this collects every inconsistency
+/// instead of stopping at the first one.
+pub async fn check_tree(
+    directory: &Path,
+    database: &Database,
+    overrides: &Overrides,
+) -> Result<Vec<String>, IndexerError> {
+    let index = read_index(&directory.join("index.json"))
+        .await?
+        .ok_or_else(|| IndexerError::OutputValidation("index.json does not exist".into()))?;
+
+    let problems = match output_file_exists(&directory.join("problems.json")).await? {
+        true => {
+            read_output_file::<Problems>(directory.join("problems.json"))
+                .await?
+                .plugins
+        }
+        false => BTreeMap::new(),
+    };
+
+    let known_plugins = database.known_plugin_xml_ids().await?;
+
+    let mut issues = Vec::new();
+    let mut expected_files = HashSet::from([
+        PathBuf::from("index.json"),
+        PathBuf::from("problems.json"),
+        PathBuf::from("dependencies.json"),
+        PathBuf::from("all-plugins.json"),
+        PathBuf::from("all-plugins.ndjson"),
+        PathBuf::from("default.nix"),
+        PathBuf::from(KEYS_FILE),
+        PathBuf::from(CHANGES_FILE),
+        PathBuf::from(SNAPSHOTS_FILE),
+    ]);
+    expected_files.extend(problems.values().map(PathBuf::from));
+
+    for (xml_id, hex_digest) in &index {
+        if !known_plugins.contains(xml_id) {
+            issues.push(format!("index references unknown plugin '{}'", xml_id));
+            continue;
+        }
+
+        if *hex_digest != plugin_digest(xml_id) {
+            issues.push(format!(
+                "index entry of plugin '{}' has wrong digest {}",
+                xml_id, hex_digest
+            ));
+            continue;
+        }
 
-            tokio::spawn(async move {
-                let mut sha_hasher = sha2::Sha256::new();
-                sha_hasher.update(plugin.xml_id.as_bytes());
-                let hash_bytes = sha_hasher.finalize_reset();
-
-                let hex_digest =
-                    hash_bytes
-                        .into_iter()
-                        .fold(String::with_capacity(64), |mut acc, byte| {
-                            let (high, low) = byte_to_hex(byte);
-
-                            acc.push(high);
-                            acc.push(low);
-                            acc
-                        });
-
-                let plugin_dir = directory
-                    .join(&hex_digest[0..2])
-                    .join(&hex_digest[2..4])
-                    .join(&hex_digest[4..]);
-
-                if let Err(err) = generate_plugin(plugin_dir, &plugin, &database).await {
-                    tracing::error!("Failed to generate plugin '{}': {:?}", plugin.xml_id, err);
-                    return None;
-                }
+        let relative_dir = plugin_directory(Path::new(""), hex_digest);
+        if let Err(err) = check_plugin(
+            directory,
+            &relative_dir,
+            xml_id,
+            database,
+            overrides,
+            &mut issues,
+            &mut expected_files,
+        )
+        .await
+        {
+            issues.push(format!("plugin '{}': {}", xml_id, describe_error(&err)));
+        }
+    }
 
-                Some((plugin.xml_id, hex_digest))
-            })
-        })
-        .collect::<FuturesUnordered<_>>()
-        .filter_map(|v| {
-            future::ready(match v {
-                Ok(Some(v)) => Some(v),
-                Ok(None) => None,
-                Err(err) => {
-                    tracing::error!("Failed to process plugin: {:?}", err);
-                    None
-                }
-            })
-        })
-        .collect::<BTreeMap<_, _>>()
-        .await;
+    let directory_owned = directory.to_path_buf();
+    let existing_files = tokio::task::spawn_blocking(move || list_files(&directory_owned))
+        .await
+        .unwrap()?;
 
-    let index_path = directory.join("index.json");
-    tokio::task::spawn_blocking(move || {
-        let index_file = std::fs::File::create(index_path)?;
-        serde_json::to_writer_pretty(index_file, &plugin_index)?;
-        Ok::<_, IndexerError>(())
-    })
-    .await
-    .unwrap()?;
+    for file in existing_files {
+        let file_path = uncompressed_path(&file);
+        let signed_file = (file_path.extension().is_some_and(|v| v == "sig"))
+            .then(|| file_path.with_extension(""));
 
-    Ok(())
+        if !expected_files.contains(&file_path)
+            && !file_path.starts_with(SNAPSHOTS_DIRECTORY)
+            && !is_update_plugins_file(&file_path)
+            && !signed_file.is_some_and(|v| expected_files.contains(&v))
+        {
+            issues.push(format!("orphan file {}", file.display()));
+        }
+    }
+
+    Ok(issues)
 }
 
-async fn generate_plugin(
-    plugin_directory: impl AsRef<Path>,
-    plugin: &CachedPlugin,
+async fn check_plugin(
+    directory: &Path,
+    relative_dir: &Path,
+    xml_id: &str,
     database: &Database,
+    overrides: &Overrides,
+    issues: &mut Vec<String>,
+    expected_files: &mut HashSet<PathBuf>,
 ) -> Result<(), IndexerError> {
-    let plugin_directory = plugin_directory.as_ref();
-    tokio::fs::create_dir_all(plugin_directory).await?;
+    // Trees generated as Nix only can't be compared in detail
+    expected_files.insert(relative_dir.join("default.nix"));
+    if !output_file_exists(&directory.join(relative_dir).join("metadata.json")).await?
+        && tokio::fs::try_exists(directory.join(relative_dir).join("default.nix")).await?
+    {
+        return Ok(());
+    }
+
+    let mut metadata: PluginMetadata =
+        read_output_file(directory.join(relative_dir).join("metadata.json")).await?;
+    expected_files.insert(relative_dir.join("metadata.json"));
+
+    if metadata.xml_id != xml_id {
+        issues.push(format!(
+            "metadata of plugin '{}' claims to be for '{}'",
+            xml_id, metadata.xml_id
+        ));
+    }
+
+    let mut versions = std::mem::take(&mut metadata.versions);
+    let mut next = metadata.next.take();
+    while let Some(page_name) = next {
+        let page: VersionsPage =
+            read_output_file(directory.join(relative_dir).join(&page_name)).await?;
+        expected_files.insert(relative_dir.join(page_name));
 
-    let versions = database
-        .get_versions_for_plugin(&plugin.xml_id)
+        versions.extend(page.versions);
+        next = page.next;
+    }
+
+    let cached_versions = database
+        .get_versions_for_plugin(xml_id)
         .await?
         .into_iter()
-        .map(|version| async move {
-            let (update_info, all_dependencies) = tokio::try_join!(
-                database.get_update(version.update_id),
-                database.get_update_dependencies(version.update_id)
-            )?;
+        .map(|v| (v.version.clone(), v))
+        .collect::<HashMap<_, _>>();
 
-            if update_info.stale {
-                tracing::warn!("Update {} is stale", version.update_id);
-                return Ok(None);
-            }
+    for (version, version_metadata) in &versions {
+        let Some(cached_version) = cached_versions.get(version) else {
+            issues.push(format!("{}@{} is not in the database", xml_id, version));
+            continue;
+        };
 
-            let Some(download_url) = update_info.download_url else {
-                tracing::warn!("No download URL for update {}", version.update_id);
-                return Ok(None);
-            };
+        let mut update = database.get_update(cached_version.update_id).await?;
 
-            if update_info
-                .hash_algorithm
-                .as_deref()
-                .map(|v| v != "SHA-256")
-                .unwrap_or(true)
-            {
-                tracing::warn!(
-                    "Unsupported hash algorithm for update {}",
-                    version.update_id
-                );
-                return Ok(None);
+        // Pinned versions are generated from the overrides instead
+        if let Some(pin) = overrides.version(xml_id, version)
+            && let Some((hash_algorithm, hash)) = pin.pinned_hash()?
+        {
+            update.hash_algorithm = Some(hash_algorithm);
+            update.hash = Some(hash);
+            if let Some(download_url) = &pin.download_url {
+                update.download_url = Some(download_url.clone());
             }
+        }
 
-            let hash = update_info
-                .hash
-                .expect("Hash algorith set but no hash provided");
-            let sha256 = BASE64_STANDARD.encode(&hash);
+        let hash = match (&update.hash_algorithm, &update.hash) {
+            (Some(algorithm), Some(hash)) => sri_hash(algorithm, hash),
+            _ => None,
+        };
 
-            let channel = if version.channel.is_empty() {
-                "stable".to_string()
-            } else {
-                version.channel.to_lowercase()
-            };
+        if hash.as_deref() != Some(version_metadata.hash.as_str()) {
+            issues.push(format!(
+                "{}@{} has a different hash in the database",
+                xml_id, version
+            ));
+        }
+
+        // Mirrored artifacts are published under their content address instead
+        let mirrored = match (&update.hash_algorithm, &update.hash) {
+            (Some(algorithm), Some(hash)) => version_metadata
+                .download_url
+                .ends_with(&format!("/{}", ArtifactCache::key_for(algorithm, hash))),
+            _ => false,
+        };
+
+        if !mirrored
+            && update.download_url.as_deref() != Some(version_metadata.download_url.as_str())
+        {
+            issues.push(format!(
+                "{}@{} has a different download url in the database",
+                xml_id, version
+            ));
+        }
+    }
 
-            let dep_id = |d: CachedUpdateDependency| d.dependency_xml_id;
+    let archived_versions = database
+        .get_archived_versions_for_plugin(xml_id)
+        .await?
+        .into_iter()
+        .map(|v| (v.version.clone(), v))
+        .collect::<HashMap<_, _>>();
 
-            let (dependencies, optional_dependencies): (
-                Vec<CachedUpdateDependency>,
-                Vec<CachedUpdateDependency>,
-            ) = all_dependencies.into_iter().partition(|dep| !dep.optional);
+    for (version, archived_metadata) in &metadata.archived {
+        match archived_versions.get(version) {
+            Some(archived)
+                if sri_hash(&archived.hash_algorithm, &archived.hash).as_deref()
+                    == Some(archived_metadata.hash.as_str()) => {}
+            Some(_) => issues.push(format!(
+                "archived {}@{} has a different hash in the database",
+                xml_id, version
+            )),
+            None => issues.push(format!(
+                "archived {}@{} is not in the database",
+                xml_id, version
+            )),
+        }
+    }
 
-            Ok::<_, IndexerError>(Some((
-                version.version,
-                VersionMetadata {
-                    download_url,
-                    sha256,
-                    channel,
-                    dependencies: dependencies.into_iter().map(dep_id).collect(),
-                    optional_dependencies: optional_dependencies.into_iter().map(dep_id).collect(),
-                    file_name: update_info.file_name,
-                },
-            )))
+    Ok(())
+}
+
+/// A version as published in a generated tree.
+#[derive(Debug)]
+pub struct PublishedVersion {
+    pub xml_id: String,
+    pub version: String,
+    pub download_url: String,
+    pub hash: String,
+}
+
+/// The plugins of a generated tree.
+pub async fn read_published_plugins(directory: &Path) -> Result<Vec<String>, IndexerError> {
+    let index = read_index(&directory.join("index.json"))
+        .await?
+        .ok_or_else(|| IndexerError::OutputValidation("index.json does not exist".into()))?;
+
+    Ok(index.into_keys().collect())
+}
+
+/// The latest version of each channel of a plugin in a generated tree.
+pub async fn read_latest_versions(
+    directory: &Path,
+    xml_id: &str,
+) -> Result<Vec<PublishedVersion>, IndexerError> {
+    let plugin_dir = plugin_directory(directory, &plugin_digest(xml_id));
+    let mut metadata = read_plugin_metadata(&plugin_dir).await?;
+
+    let mut versions = std::mem::take(&mut metadata.versions);
+    let latest = metadata.latest.into_values().collect::<BTreeSet<_>>();
+
+    Ok(latest
+        .into_iter()
+        .filter_map(|version| {
+            let metadata = versions.remove(&version)?;
+
+            Some(PublishedVersion {
+                xml_id: xml_id.to_owned(),
+                version,
+                download_url: metadata.download_url,
+                hash: metadata.hash,
+            })
         })
-        .collect::<FuturesUnordered<_>>()
-        .filter_map(|v| {
-            future::ready(match v {
-                Ok(Some(v)) => Some(v),
-                Ok(None) => None,
-                Err(err) => {
-                    tracing::error!("Failed to process version: {:?}", err);
-                    None
+        .collect())
+}
+
+/// Where a generated tree is read from, a directory or the URL it is served at.
+#[derive(Debug, Clone)]
+pub enum TreeLocation {
+    Directory(PathBuf),
+    Url(Url),
+}
+
+impl std::str::FromStr for TreeLocation {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match Url::parse(s) {
+            Ok(mut url) if matches!(url.scheme(), "http" | "https") => {
+                // Files are resolved relative to the tree, not its parent
+                if !url.path().ends_with('/') {
+                    url.set_path(&format!("{}/", url.path()));
                 }
-            })
+
+                Self::Url(url)
+            }
+            _ => Self::Directory(PathBuf::from(s)),
         })
-        .collect::<BTreeMap<String, VersionMetadata>>()
-        .await;
+    }
+}
 
-    let mut latest = BTreeMap::<String, String>::new();
+impl TreeLocation {
+    /// Read and parse a file of the tree, falling back to compressed variants.
+    async fn read<T>(&self, client: &reqwest::Client, path: &Path) -> Result<T, IndexerError>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let url = match self {
+            Self::Directory(directory) => return read_output_file(directory.join(path)).await,
+            Self::Url(url) => url,
+        };
 
-    for (version, version_metadata) in &versions {
-        let mut entry = match latest.entry(version_metadata.channel.clone()) {
-            Entry::Vacant(entry) => {
-                entry.insert(version.clone());
+        let url_path = |path: &Path| {
+            path.components()
+                .map(|v| v.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        let name = url_path(path);
+
+        let mut data = None;
+        for format in [None, Some(CompressionFormat::Zstd), Some(CompressionFormat::Gzip)] {
+            let file = match format {
+                None => path.to_path_buf(),
+                Some(format) => compressed_path(path, format),
+            };
+            let file_url = url.join(&url_path(&file)).map_err(|e| {
+                IndexerError::OutputValidation(format!("invalid path {}: {}", name, e))
+            })?;
+
+            let response = client.get(file_url).send().await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
                 continue;
             }
-            Entry::Occupied(v) => v,
+
+            let body = response.error_for_status()?.bytes().await?;
+            data = Some(match format {
+                None => body.to_vec(),
+                Some(format) => decompress(&body, format)?,
+            });
+            break;
+        }
+
+        let data = data.ok_or_else(|| {
+            IndexerError::OutputValidation(format!("{} does not exist in {}", name, url))
+        })?;
+
+        serde_json::from_slice(&data).map_err(|e| {
+            IndexerError::OutputValidation(format!(
+                "{} does not match the metadata schema: {}",
+                name, e
+            ))
+        })
+    }
+}
+
+/// A plugin as published in a generated tree, in the shape of the database.
+#[derive(Debug)]
+pub struct PublishedPlugin {
+    pub plugin: CachedPlugin,
+    pub repository: String,
+    pub details: CachedPluginDetails,
+    pub tags: Vec<String>,
+    pub screenshots: Vec<String>,
+    pub updates: Vec<PublishedUpdate>,
+
+    /// Versions which could not be imported, because the tree doesn't
+    /// contain their update id.
+    pub skipped_versions: usize,
+}
+
+#[derive(Debug)]
+pub struct PublishedUpdate {
+    pub version: CachedPluginVersion,
+    pub update: CachedUpdate,
+    pub dependencies: Vec<CachedUpdateDependency>,
+}
+
+/// The plugins listed in the index of a tree.
+pub async fn read_tree_index(
+    location: &TreeLocation,
+    client: &reqwest::Client,
+) -> Result<Vec<String>, IndexerError> {
+    let index: BTreeMap<String, IndexEntry> = location.read(client, Path::new("index.json")).await?;
+    Ok(index.into_keys().collect())
+}
+
+/// Read everything a tree publishes about a plugin.
+pub async fn read_tree_plugin(
+    location: &TreeLocation,
+    client: &reqwest::Client,
+    xml_id: &str,
+) -> Result<PublishedPlugin, IndexerError> {
+    let plugin_dir = plugin_directory(Path::new(""), &plugin_digest(xml_id));
+
+    let mut metadata: PluginMetadata = location.read(client, &plugin_dir.join("metadata.json")).await?;
+    let mut next = metadata.next.take();
+    while let Some(page_name) = next {
+        let page: VersionsPage = location.read(client, &plugin_dir.join(page_name)).await?;
+
+        metadata.versions.extend(page.versions);
+        next = page.next;
+    }
+
+    let purchase = metadata.purchase.as_ref();
+    let details = CachedPluginDetails {
+        pricing_model: purchase.map(|v| v.pricing_model.clone()).or_else(|| {
+            metadata
+                .license
+                .as_ref()
+                .and_then(|v| v.pricing_model.as_ref())
+                .map(|v| v.to_uppercase())
+        }),
+        product_code: purchase.and_then(|v| v.product_code.clone()),
+        trial_available: purchase.and_then(|v| v.trial_available),
+        trial_days: purchase.and_then(|v| v.trial_days),
+        purchase_url: purchase.and_then(|v| v.purchase_url.clone()),
+        source_code_url: metadata.meta.source_code_url,
+        bugtracker_url: metadata.meta.bugtracker_url,
+        documentation_url: metadata.meta.documentation_url,
+        name: metadata.meta.name,
+        description: metadata.meta.description,
+        vendor_name: metadata.meta.vendor.as_ref().map(|v| v.name.clone()),
+        vendor_url: metadata.meta.vendor.as_ref().and_then(|v| v.url.clone()),
+        vendor_email: metadata.meta.vendor.and_then(|v| v.email),
+        homepage_url: metadata.meta.homepage,
+        license_url: metadata.license.and_then(|v| v.url),
+        repository: metadata.repository.clone(),
+        family: metadata.family.clone(),
+        ..Default::default()
+    };
+
+    let mut updates = Vec::new();
+    let mut skipped_versions = 0;
+
+    for (version, version_metadata) in metadata.versions {
+        let Some(update_id) = version_metadata.update_id else {
+            skipped_versions += 1;
+            continue;
         };
 
-        let current_version = Version::parse(entry.get()).ok();
-        let new_version = Version::parse(version).ok();
+        let Some((hash_algorithm, hash)) = parse_sri_hash(&version_metadata.hash) else {
+            skipped_versions += 1;
+            continue;
+        };
 
-        match (current_version, new_version) {
-            (None, None) => { /* no change */ }
-            (Some(_), None) => { /* no change */ }
-            (None, Some(_)) => {
-                entry.insert(version.clone());
-            }
-            (Some(current), Some(new)) => {
-                if new > current {
-                    entry.insert(version.clone());
-                }
+        let dependency = |dependency_xml_id: String, optional: bool| CachedUpdateDependency {
+            dependency_xml_id,
+            update_id,
+            optional,
+        };
+
+        let mut dependencies = version_metadata
+            .dependencies
+            .into_iter()
+            .map(|v| dependency(v, false))
+            .collect::<Vec<_>>();
+        dependencies.extend(
+            version_metadata
+                .optional_dependencies
+                .into_iter()
+                .map(|v| dependency(v, true)),
+        );
+
+        updates.push(PublishedUpdate {
+            version: CachedPluginVersion {
+                version,
+                update_id,
+                // The database keeps the stable channel as the marketplace names it
+                channel: match version_metadata.channel.as_str() {
+                    "stable" => String::new(),
+                    channel => channel.to_owned(),
+                },
+                plugin_xml_id: metadata.xml_id.clone(),
+            },
+            update: CachedUpdate {
+                id: update_id,
+                stale: false,
+                etag: None,
+                file_name: version_metadata.file_name,
+                download_url: Some(version_metadata.download_url),
+                // Filled in by the next sync
+                fallback_download_url: None,
+                hash_algorithm: Some(hash_algorithm),
+                hash: Some(hash),
+                size: version_metadata.size,
+                release_date: version_metadata.release_date,
+                change_notes: version_metadata.change_notes,
+                since_build: version_metadata.since,
+                until_build: version_metadata.until,
+            },
+            dependencies,
+        });
+    }
+
+    Ok(PublishedPlugin {
+        plugin: CachedPlugin {
+            xml_id: metadata.xml_id,
+            numeric_id: metadata.numeric_id,
+        },
+        repository: metadata.repository.unwrap_or_else(|| "marketplace".to_owned()),
+        details,
+        tags: metadata.tags,
+        screenshots: metadata.media.map(|v| v.screenshots).unwrap_or_default(),
+        updates,
+        skipped_versions,
+    })
+}
+
+/// The algorithm, spelled like the marketplace does, and digest of an SRI hash.
+pub fn parse_sri_hash(sri: &str) -> Option<(String, Vec<u8>)> {
+    let (prefix, digest) = sri.split_once('-')?;
+    let algorithm = match prefix {
+        "sha1" => "SHA-1",
+        "sha256" => "SHA-256",
+        "sha384" => "SHA-384",
+        "sha512" => "SHA-512",
+        _ => return None,
+    };
+
+    Some((algorithm.to_owned(), BASE64_STANDARD.decode(digest).ok()?))
+}
+
+/// The metadata of a plugin with the versions of all pages merged.
+async fn read_plugin_metadata(plugin_dir: &Path) -> Result<PluginMetadata, IndexerError> {
+    let mut metadata: PluginMetadata = read_output_file(plugin_dir.join("metadata.json")).await?;
+
+    let mut next = metadata.next.take();
+    while let Some(page_name) = next {
+        let page: VersionsPage = read_output_file(plugin_dir.join(page_name)).await?;
+
+        metadata.versions.extend(page.versions);
+        next = page.next;
+    }
+
+    Ok(metadata)
+}
+
+/// All files below a directory, relative to it.
+fn list_files(directory: &Path) -> Result<Vec<PathBuf>, IndexerError> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        for entry in std::fs::read_dir(directory.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
             }
         }
     }
 
-    let metadata = PluginMetadata {
-        xml_id: plugin.xml_id.clone(),
-        numeric_id: plugin.numeric_id,
-        versions,
-        latest,
-    };
+    Ok(files)
+}
 
-    let metadata_path = plugin_directory.join("metadata.json");
+/// Read and parse a generated file, mapping all errors to validation errors.
+async fn read_output_file<T>(path: PathBuf) -> Result<T, IndexerError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
     tokio::task::spawn_blocking(move || {
-        let metadata_file = std::fs::File::create(metadata_path)?;
-        serde_json::to_writer_pretty(metadata_file, &metadata)?;
-        Ok::<_, IndexerError>(())
+        let data = read_output_bytes(&path).map_err(|e| {
+            IndexerError::OutputValidation(format!("failed to open {}: {}", path.display(), e))
+        })?;
+
+        serde_json::from_slice(&data).map_err(|e| {
+            IndexerError::OutputValidation(format!(
+                "{} does not match the metadata schema: {}",
+                path.display(),
+                e
+            ))
+        })
     })
     .await
-    .unwrap()?;
+    .unwrap()
+}
 
-    Ok(())
+async fn read_index(path: &Path) -> Result<Option<BTreeMap<String, String>>, IndexerError> {
+    let path_owned = path.to_path_buf();
+    let data = match tokio::task::spawn_blocking(move || read_output_bytes(&path_owned))
+        .await
+        .unwrap()
+    {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    serde_json::from_slice::<BTreeMap<String, IndexEntry>>(&data)
+        .map(|index| Some(index.into_iter().map(|(k, v)| (k, v.into_digest())).collect()))
+        .map_err(|e| IndexerError::OutputValidation(format!("failed to parse {}: {}", path.display(), e)))
 }
 
-fn byte_to_hex(byte: u8) -> (char, char) {
-    (
-        std::char::from_digit((byte >> 4) as u32, 16).unwrap(),
-        std::char::from_digit((byte & 0xF) as u32, 16).unwrap(),
-    )
+async fn remove_dir_if_exists(path: &Path) -> Result<(), IndexerError> {
+    match tokio::fs::remove_dir_all(path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
 }
 
-#[derive(Debug, Serialize)]
+fn sibling_directory(directory: &Path, suffix: &str) -> PathBuf {
+    let mut name = directory
+        .file_name()
+        .map(|v| v.to_os_string())
+        .unwrap_or_else(|| "meta".into());
+    name.push(".");
+    name.push(suffix);
+
+    directory.with_file_name(name)
+}
+
+fn plugin_digest(xml_id: &str) -> String {
+    let mut sha_hasher = sha2::Sha256::new();
+    sha_hasher.update(xml_id.as_bytes());
+
+    crate::hex::encode(sha_hasher.finalize())
+}
+
+fn plugin_directory(directory: &Path, hex_digest: &str) -> PathBuf {
+    directory
+        .join(&hex_digest[0..2])
+        .join(&hex_digest[2..4])
+        .join(&hex_digest[4..])
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct PluginMetadata {
     pub xml_id: String,
     pub numeric_id: u64,
+
+    /// Name of the repository the plugin was synced from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+
+    /// The kind of product the plugin extends, such as `intellij`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+
+    pub meta: PluginMeta,
+
+    /// Marketplace tags, such as `Theme`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<PluginMedia>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<PluginLicense>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purchase: Option<PurchaseMetadata>,
+
     pub versions: BTreeMap<String, VersionMetadata>,
     pub latest: BTreeMap<String, String>,
+
+    /// The highest version per channel compatible with each IDE branch,
+    /// such as `233` for 2023.3. Branches which aren't listed are answered
+    /// by the highest listed one below them, if its version supports them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub latest_for_build: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Versions which have been removed upstream after they were published.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub archived: BTreeMap<String, ArchivedVersionMetadata>,
+
+    /// Tombstones of versions which have been removed upstream recently, so
+    /// consumers pinning them can tell why they are gone.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub removed: BTreeMap<String, RemovedVersionMetadata>,
+
+    /// File name of the next page of versions, if the versions were split.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+/// Descriptive information about a plugin, intended for derivation `meta`
+/// attributes.
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Short plain text description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The plugin page on the marketplace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<PluginVendor>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_code_url: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bugtracker_url: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginVendor {
+    pub name: String,
+    pub url: Option<String>,
+
+    /// Contact address published by the vendor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginMedia {
+    pub screenshots: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginLicense {
+    /// `free`, `freemium` or `paid`.
+    pub pricing_model: Option<String>,
+
+    /// Whether the plugin can be used without paying for it.
+    pub free: bool,
+
+    /// Where the declared license can be read.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PurchaseMetadata {
+    pub pricing_model: String,
+    pub product_code: Option<String>,
+    pub trial_available: Option<bool>,
+    pub trial_days: Option<u32>,
+    pub purchase_url: Option<String>,
+}
+
+/// An entry of `dependencies.json`, across all versions of a plugin.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PluginDependencies {
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub dependencies: BTreeSet<String>,
+
+    /// Plugins which are only ever optional dependencies.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub optional_dependencies: BTreeSet<String>,
+}
+
+/// An entry of `index.json`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum IndexEntry {
+    /// Trees generated before popularity was tracked only contain the digest.
+    Digest(String),
+    Plugin {
+        digest: String,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        downloads: Option<u64>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rating: Option<f64>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        family: Option<String>,
+    },
+}
+
+impl IndexEntry {
+    fn into_digest(self) -> String {
+        match self {
+            Self::Digest(digest) | Self::Plugin { digest, .. } => digest,
+        }
+    }
+}
+
+/// Name of the report of changes since the previous tree.
+const CHANGES_FILE: &str = "changes.json";
+
+/// Differences of a tree to the one it replaced, versions are listed by plugin.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TreeChanges {
+    pub added_plugins: Vec<String>,
+    pub removed_plugins: Vec<String>,
+    pub new_versions: BTreeMap<String, Vec<String>>,
+    pub removed_versions: BTreeMap<String, Vec<String>>,
+    pub changed_hashes: BTreeMap<String, Vec<String>>,
+}
+
+/// Directory of the snapshots in a tree, one subdirectory per snapshot.
+const SNAPSHOTS_DIRECTORY: &str = "snapshots";
+
+/// Name of the list of snapshots kept in a tree.
+const SNAPSHOTS_FILE: &str = "snapshots.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshots {
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// A copy of a generated tree, below `snapshots/<id>`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    pub id: String,
+    pub created_at: i64,
+    pub plugins: usize,
+}
+
+/// Plugins which could not be generated completely, mapped to their error file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Problems {
+    pub plugins: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginErrors {
+    pub xml_id: String,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedVersionMetadata {
+    pub archived: bool,
+    pub update_id: u64,
+    pub download_url: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    pub hash: String,
+    pub channel: String,
+    pub file_name: Option<String>,
+    pub archived_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RemovedVersionMetadata {
+    pub removed: bool,
+    pub update_id: u64,
+
+    /// The hash the version was published with.
+    pub hash: String,
+    pub channel: String,
+    pub removed_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionsPage {
+    pub versions: BTreeMap<String, VersionMetadata>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct VersionMetadata {
+    /// Missing in trees generated before it was published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_id: Option<u64>,
+
     pub download_url: String,
-    pub sha256: String,
+
+    /// Other URLs serving the same artifact, such as the stable marketplace
+    /// URL of a CDN download.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirror_urls: Vec<String>,
+
+    /// Only present for artifacts hashed with SHA-256.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// The hash in SRI form, as expected by `fetchurl`.
+    pub hash: String,
     pub channel: String,
     pub dependencies: Vec<String>,
+
+    /// The dependencies which are plugins known to the index, the others
+    /// are modules of the IDE.
+    #[serde(default)]
+    pub resolved_dependencies: Vec<String>,
+
     pub optional_dependencies: Vec<String>,
+
+    /// Optional dependencies which only gate a feature of this version,
+    /// mapped to the config file loaded when they are present.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub optional_dependency_configs: BTreeMap<String, String>,
+
     pub file_name: Option<String>,
+
+    /// Size of the artifact in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// When the version was uploaded, in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<i64>,
+
+    /// What changed in this version as plain text, possibly truncated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_notes: Option<String>,
+
+    /// Range of IDE builds this version can be installed into.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+
+    /// Codes of the IDE products this version works with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub products: Vec<String>,
+
+    /// IDE builds the marketplace verifier found this version to work with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verified_compatible: Vec<String>,
+
+    /// The artifact no longer matches the hash, so fetching it fails.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub known_bad: bool,
 }
@@ -1,24 +1,298 @@
-use crate::db::{CachedPlugin, CachedPluginVersion, CachedUpdateDependency};
+use crate::api::{RepoDownloadInfo, RepoPluginDetails};
+use crate::args::{DownloadUrlStyle, PluginFamily, SyncArgs};
+use crate::db::{
+    CachedPlugin, CachedPluginDetails, CachedPluginVersion, CachedUpdate, CachedUpdateDependency,
+};
 use crate::error::IndexerError;
-use crate::meta::TaskAttachment;
+use crate::meta::{TaskAttachment, newest_per_channel, normalize_channel, selected_channels};
+use crate::progress::SyncPhase;
+use crate::statistics::TimingCategory;
+use futures::FutureExt as _;
+use futures::future::BoxFuture;
+use std::collections::{BTreeSet, HashSet};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use url::Url;
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Only sync the newest version per channel of plugins without any
+    /// known versions.
+    pub bootstrap_latest_only: bool,
+
+    /// Fetch plugin verifier results for every update.
+    pub sync_verification: bool,
+
+    /// Fraction of hashed updates to hash again on every sync.
+    pub reverify_fraction: Option<f64>,
+
+    /// Hash updates again once their hash is older than this many seconds.
+    pub reverify_max_age: Option<i64>,
+
+    /// Only sync these plugins instead of the whole marketplace.
+    pub plugins: Option<HashSet<String>>,
+
+    /// Plugins which are treated as if the marketplace didn't know them.
+    pub excluded: HashSet<String>,
+
+    /// Only index plugins of these families and products, enumerated with
+    /// the search API instead of listing all plugins.
+    pub families: Vec<PluginFamily>,
+    pub products: Vec<String>,
+
+    /// Continue an interrupted sync instead of starting over.
+    pub resume: bool,
+
+    /// Only sync plugins which changed since the last sync, according to
+    /// the marketplace.
+    pub incremental: bool,
+    pub incremental_max_pages: usize,
+
+    /// Only keep this many of the newest versions of every channel.
+    pub max_versions_per_channel: Option<usize>,
+
+    /// Only sync versions of these channels instead of all.
+    pub channels: Option<BTreeSet<String>>,
+
+    pub download_url_style: DownloadUrlStyle,
+    pub strip_download_url_params: HashSet<String>,
+
+    /// Name of the synced repository, other repositories are left alone.
+    pub repository: String,
+
+    /// How often failed tasks are dispatched again at the end of a sync.
+    pub retry_rounds: usize,
+
+    /// How long running tasks may take to finish once shut down.
+    pub shutdown_timeout: Duration,
+
+    /// Give up on a single plugin or update task after this long.
+    pub task_timeout: Option<Duration>,
+}
+
+impl SyncOptions {
+    pub async fn new(args: &SyncArgs) -> Result<Self, IndexerError> {
+        let mut plugins = args.plugins.iter().cloned().collect::<HashSet<_>>();
+        if let Some(path) = &args.plugin_file {
+            plugins.extend(read_plugin_list(path).await?);
+        }
+
+        let mut excluded = args.excluded.iter().cloned().collect::<HashSet<_>>();
+        if let Some(path) = &args.exclude_file {
+            excluded.extend(read_plugin_list(path).await?);
+        }
+
+        let filtered = !args.plugins.is_empty() || args.plugin_file.is_some();
+
+        Ok(Self {
+            bootstrap_latest_only: args.bootstrap_latest_only,
+            sync_verification: args.sync_verification,
+            reverify_fraction: args.reverify,
+            reverify_max_age: args
+                .reverify_older_than_days
+                .map(|v| i64::from(v) * 24 * 60 * 60),
+            plugins: filtered.then_some(plugins),
+            excluded,
+            families: args.families.clone(),
+            products: args.products.clone(),
+            resume: args.resume,
+            incremental: args.incremental,
+            incremental_max_pages: args.incremental_max_pages,
+            max_versions_per_channel: args.max_versions_per_plugin.map(NonZeroUsize::get),
+            channels: selected_channels(&args.channels),
+            download_url_style: args.download_url_style,
+            strip_download_url_params: args.strip_download_url_params.iter().cloned().collect(),
+            repository: args.repository_name.clone(),
+            retry_rounds: args.retry_rounds,
+            shutdown_timeout: Duration::from_secs(args.shutdown_timeout_secs),
+            task_timeout: args.task_timeout_secs.map(|v| Duration::from_secs(v.get())),
+        })
+    }
+
+    /// Only sync plugins which are also in this set.
+    pub fn restrict_to(&mut self, plugins: HashSet<String>) {
+        self.plugins = Some(match self.plugins.take() {
+            Some(existing) => existing.intersection(&plugins).cloned().collect(),
+            None => plugins,
+        });
+    }
+
+    /// Key of sync state kept per repository.
+    ///
+    /// The marketplace keeps the plain key of databases with only one repository.
+    pub fn state_key(&self, key: &str) -> String {
+        match self.repository.as_str() {
+            "marketplace" => key.to_owned(),
+            repository => format!("{}:{}", key, repository),
+        }
+    }
+
+    /// Whether versions of a channel should be synced.
+    pub fn includes_channel(&self, channel: &str) -> bool {
+        self.channels
+            .as_ref()
+            .is_none_or(|v| v.contains(&normalize_channel(channel)))
+    }
+
+    /// Whether a plugin should be synced.
+    pub fn includes(&self, xml_id: &str) -> bool {
+        self.plugins.as_ref().is_none_or(|v| v.contains(xml_id))
+    }
+
+    /// The families searched for plugins, products are IDEs so they imply
+    /// the IntelliJ family.
+    pub fn filtered_families(&self) -> Vec<PluginFamily> {
+        match self.families.is_empty() {
+            true => vec![PluginFamily::Intellij],
+            false => self.families.clone(),
+        }
+    }
+}
+
+/// Read a file of plugin xml ids, one per line, skipping `#` comments.
+async fn read_plugin_list(path: &Path) -> Result<Vec<String>, IndexerError> {
+    let content = tokio::fs::read_to_string(path).await?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && !v.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// A task which is dispatched again at the end of a sync if it failed.
+#[derive(Debug, Clone)]
+pub(super) enum RetryableTask {
+    NewPlugin(String),
+    Plugin(CachedPlugin),
+    PluginDetails(CachedPlugin),
+    UpdateDependencyMeta(CachedPlugin, CachedPluginVersion),
+    UpdateMeta { update_id: u64, reverify: bool },
+    UpdateVerification(u64),
+}
+
+impl RetryableTask {
+    pub fn name(&self) -> String {
+        match self {
+            Self::NewPlugin(xml_id) => format!("sync new plugin {}", xml_id),
+            Self::Plugin(plugin) => format!("sync plugin {}", plugin.xml_id),
+            Self::PluginDetails(plugin) => format!("sync plugin details {}", plugin.xml_id),
+            Self::UpdateDependencyMeta(plugin, version) => format!(
+                "sync update metadata for {}@{}",
+                plugin.xml_id, version.version
+            ),
+            Self::UpdateMeta {
+                update_id,
+                reverify: true,
+            } => format!("reverify update {}", update_id),
+            Self::UpdateMeta { update_id, .. } => {
+                format!("sync update metadata for {}", update_id)
+            }
+            Self::UpdateVerification(update_id) => {
+                format!("sync update verification for {}", update_id)
+            }
+        }
+    }
+
+    /// The plugin the task is about, if known without looking it up.
+    pub fn plugin_xml_id(&self) -> Option<&str> {
+        match self {
+            Self::NewPlugin(xml_id) => Some(xml_id),
+            Self::Plugin(plugin)
+            | Self::PluginDetails(plugin)
+            | Self::UpdateDependencyMeta(plugin, _) => Some(&plugin.xml_id),
+            _ => None,
+        }
+    }
+
+    pub fn update_id(&self) -> Option<u64> {
+        match self {
+            Self::UpdateDependencyMeta(_, version) => Some(version.update_id),
+            Self::UpdateMeta { update_id, .. } | Self::UpdateVerification(update_id) => {
+                Some(*update_id)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn phase(&self) -> Option<SyncPhase> {
+        match self {
+            Self::Plugin(_) => Some(SyncPhase::Plugins),
+            Self::UpdateDependencyMeta(..) => Some(SyncPhase::Versions),
+            Self::UpdateMeta { .. } => Some(SyncPhase::Hashing),
+            _ => None,
+        }
+    }
+
+    pub fn run(self, attachment: TaskAttachment) -> BoxFuture<'static, Result<(), IndexerError>> {
+        match self {
+            Self::NewPlugin(xml_id) => sync_new_plugin(attachment, xml_id).boxed(),
+            Self::Plugin(plugin) => sync_plugin(attachment, plugin).boxed(),
+            Self::PluginDetails(plugin) => sync_plugin_details(attachment, plugin).boxed(),
+            Self::UpdateDependencyMeta(plugin, version) => {
+                sync_update_dependency_meta(attachment, plugin, version).boxed()
+            }
+            Self::UpdateMeta {
+                update_id,
+                reverify,
+            } => sync_update_meta(attachment, update_id, reverify).boxed(),
+            Self::UpdateVerification(update_id) => {
+                sync_update_verification(attachment, update_id).boxed()
+            }
+        }
+    }
+}
 
 #[tracing::instrument(skip(attachment))]
-pub(super) async fn sync_new_plugin(
+async fn sync_new_plugin(
     attachment: TaskAttachment,
     xml_id: String,
 ) -> Result<(), IndexerError> {
-    let details = attachment.repo.fetch_plugin_details(&xml_id).await?;
+    let details = attachment
+        .timed(TimingCategory::PluginDetails, attachment.repo.fetch_plugin_details(&xml_id))
+        .await?;
 
     let known = CachedPlugin {
         xml_id,
         numeric_id: details.id,
     };
-    attachment.database.add_plugin(&known).await?;
+    if !attachment
+        .database
+        .add_plugin(&known, &attachment.sync_options.repository)
+        .await?
+    {
+        let existing = attachment
+            .database
+            .get_plugin_by_numeric_id(known.numeric_id)
+            .await?;
 
-    attachment.dispatch(
-        format!("sync plugin {}", known.xml_id),
-        sync_plugin(attachment.clone(), known),
-    );
+        match existing {
+            // A retry finds the plugin added by the failed attempt
+            Some(existing) if existing.xml_id == known.xml_id => {}
+            Some(existing) => {
+                attachment.send_problem(
+                    format!("sync new plugin {}", known.xml_id),
+                    IndexerError::NumericIdCollision {
+                        numeric_id: known.numeric_id,
+                        xml_id: known.xml_id,
+                        existing_xml_id: existing.xml_id,
+                    },
+                );
+
+                return Ok(());
+            }
+            None => {
+                tracing::debug!("Plugin '{}' has been added meanwhile", known.xml_id);
+                return Ok(());
+            }
+        }
+    }
+
+    store_plugin_details(&attachment, &known, details).await?;
+
+    attachment.dispatch_task(RetryableTask::Plugin(known));
 
     Ok(())
 }
@@ -27,19 +301,43 @@ pub(super) async fn sync_new_plugin(
     skip(attachment, known_plugin),
     fields(plugin_id = known_plugin.xml_id.as_str())
 )]
-pub(super) async fn sync_plugin(
+async fn sync_plugin(
     attachment: TaskAttachment,
     known_plugin: CachedPlugin,
 ) -> Result<(), IndexerError> {
-    let (repo_versions, cached_versions) = tokio::try_join!(
-        attachment
-            .repo
-            .fetch_plugin_versions(known_plugin.numeric_id),
+    let (mut repo_versions, cached_versions) = tokio::try_join!(
+        attachment.timed(
+            TimingCategory::VersionListing,
+            attachment.repo.fetch_plugin_versions(known_plugin.numeric_id),
+        ),
         attachment
             .database
             .get_versions_for_plugin(&known_plugin.xml_id)
     )?;
 
+    // Versions left out below still exist upstream and must not be archived
+    let upstream = repo_versions
+        .iter()
+        .map(|v| (v.id, v.version.clone()))
+        .collect::<Vec<_>>();
+
+    repo_versions.retain(|v| attachment.sync_options.includes_channel(&v.channel));
+
+    if attachment.sync_options.bootstrap_latest_only && cached_versions.is_empty() {
+        // The full history is filled in by the next sync
+        repo_versions = newest_per_channel(repo_versions, 1, |v| (&v.channel, v.id));
+        tracing::debug!("Bootstrapping with {} versions", repo_versions.len());
+    }
+
+    if let Some(max) = attachment.sync_options.max_versions_per_channel {
+        repo_versions = newest_per_channel(repo_versions, max, |v| (&v.channel, v.id));
+    }
+
+    // Follow-up tasks are only dispatched once the batch is committed
+    let mut added_versions = Vec::new();
+    let batch = attachment.database.begin_batch().await?;
+    let started = Instant::now();
+
     for version in &repo_versions {
         let version = CachedPluginVersion {
             update_id: version.id,
@@ -48,48 +346,171 @@ pub(super) async fn sync_plugin(
             plugin_xml_id: known_plugin.xml_id.clone(),
         };
 
-        attachment.database.add_update(version.update_id).await?;
-        attachment.database.add_plugin_version(&version).await?;
+        batch.add_update(version.update_id, &attachment.sync_options.repository).await?;
+        batch.add_plugin_version(&version).await?;
+        batch.unarchive_plugin_version(&version.plugin_xml_id, &version.version).await?;
 
-        // We only do this for added versions since we don't expect a version
-        // that has been released to ever change its metadata.
-        attachment.dispatch(
-            format!(
-                "sync update metadata for {}@{}",
-                known_plugin.xml_id, version.version
-            ),
-            sync_update_dependency_meta(attachment.clone(), known_plugin.clone(), version.clone()),
-        );
+        // We were the ones marking it as not stale, so we need to sync it
+        let was_stale = batch.mark_update_not_stale(version.update_id).await?;
 
-        if attachment
-            .database
-            .mark_update_not_stale(version.update_id)
-            .await?
-        {
-            // We were the ones marking it as not stale, so we need to sync it
-            attachment.dispatch(
-                format!("sync update metadata for {}", version.update_id),
-                sync_update_meta(attachment.clone(), version.update_id),
-            );
-        }
+        added_versions.push((version, was_stale));
     }
 
     for cached_version in &cached_versions {
+        // A version whose update id changed has already been updated above
         if !repo_versions
             .iter()
-            .any(|v| v.id == cached_version.update_id)
+            .any(|v| v.id == cached_version.update_id || v.version == cached_version.version)
         {
             tracing::trace!("Removing cached version: {:?}", cached_version);
-            attachment
-                .database
+
+            if !upstream
+                .iter()
+                .any(|(id, version)| *id == cached_version.update_id || *version == cached_version.version)
+            {
+                batch
+                    .archive_plugin_version(&cached_version.plugin_xml_id, &cached_version.version)
+                    .await?;
+            }
+
+            batch
                 .remove_plugin_version(&cached_version.plugin_xml_id, &cached_version.version)
                 .await?
         }
     }
 
+    batch
+        .mark_plugin_synced(
+            &known_plugin.xml_id,
+            repo_versions.iter().map(|v| v.id).max(),
+        )
+        .await?;
+    batch.commit().await?;
+    attachment.send_timing(TimingCategory::DatabaseWrites, started);
+    attachment.counters.record_plugin_synced();
+
+    for (version, was_stale) in added_versions {
+        // We only do this for added versions since we don't expect a version
+        // that has been released to ever change its metadata.
+        attachment.dispatch_task(RetryableTask::UpdateDependencyMeta(
+            known_plugin.clone(),
+            version.clone(),
+        ));
+
+        if was_stale {
+            attachment.dispatch_task(RetryableTask::UpdateMeta {
+                update_id: version.update_id,
+                reverify: false,
+            });
+
+            if attachment.sync_options.sync_verification {
+                attachment.dispatch_task(RetryableTask::UpdateVerification(version.update_id));
+            }
+        }
+    }
+
     Ok(())
 }
 
+#[tracing::instrument(
+    skip(attachment, plugin),
+    fields(plugin_id = plugin.xml_id.as_str())
+)]
+async fn sync_plugin_details(
+    attachment: TaskAttachment,
+    plugin: CachedPlugin,
+) -> Result<(), IndexerError> {
+    let details = attachment
+        .timed(TimingCategory::PluginDetails, attachment.repo.fetch_plugin_details(&plugin.xml_id))
+        .await?;
+    store_plugin_details(&attachment, &plugin, details).await
+}
+
+async fn store_plugin_details(
+    attachment: &TaskAttachment,
+    plugin: &CachedPlugin,
+    details: RepoPluginDetails,
+) -> Result<(), IndexerError> {
+    let mut cached_details = CachedPluginDetails {
+        pricing_model: details.pricing_model,
+        source_code_url: details.urls.source_code_url,
+        bugtracker_url: details.urls.bugtracker_url,
+        documentation_url: details.urls.doc_url,
+        license_url: details.urls.license_url,
+        downloads: details.downloads,
+        rating: details.rating,
+        name: details.name,
+        description: details.preview.map(|v| v.trim().to_owned()),
+        vendor_name: details.vendor.as_ref().and_then(|v| v.name.clone()),
+        vendor_url: details.vendor.as_ref().and_then(|v| v.url.clone()),
+        vendor_email: details.vendor.and_then(|v| v.email).filter(|v| !v.is_empty()),
+        homepage_url: details
+            .link
+            .and_then(|v| attachment.repo.absolute_url(&v).ok())
+            .map(String::from),
+        family: details.family,
+        ..Default::default()
+    };
+
+    if let Some(purchase_info) = details.purchase_info {
+        cached_details.trial_days = purchase_info.trial_period;
+        cached_details.purchase_url = purchase_info.buy_url;
+
+        // The products endpoint has the authoritative licensing details, the
+        // purchase info embedded in the plugin details only serves as a fallback.
+        if let Some(product_code) = purchase_info.product_code {
+            if let Some(product) = attachment.repo.fetch_product(&product_code).await? {
+                cached_details.trial_available = product.trial_available;
+                cached_details.trial_days = product.trial_days.or(cached_details.trial_days);
+                cached_details.purchase_url = product.buy_url.or(cached_details.purchase_url);
+            }
+
+            cached_details.product_code = Some(product_code);
+        }
+    }
+
+    attachment
+        .database
+        .change_plugin_details(&plugin.xml_id, &cached_details)
+        .await?;
+
+    // Keep the previously stored products instead of failing the whole task
+    match attachment.repo.fetch_compatible_products(plugin.numeric_id).await {
+        Ok(products) => {
+            attachment
+                .database
+                .set_plugin_products(&plugin.xml_id, &products)
+                .await?;
+        }
+        Err(err) => {
+            attachment.send_problem(format!("sync plugin details {}", plugin.xml_id), err);
+        }
+    }
+
+    let tags = details.tags.iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+    attachment
+        .database
+        .set_plugin_tags(&plugin.xml_id, &tags)
+        .await?;
+
+    let screenshots = details
+        .screens
+        .iter()
+        .filter_map(|screen| match attachment.repo.absolute_url(&screen.url) {
+            Ok(v) => Some(v.to_string()),
+            Err(err) => {
+                tracing::warn!("Invalid screenshot URL {}: {}", screen.url, err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    attachment
+        .database
+        .set_plugin_screenshots(&plugin.xml_id, &screenshots)
+        .await
+}
+
 #[tracing::instrument(
     skip(attachment, plugin, version),
     fields(plugin_id = plugin.xml_id.as_str(), version = version.version.as_str())
@@ -104,6 +525,25 @@ async fn sync_update_dependency_meta(
         .fetch_update_metadata(plugin.numeric_id, version.update_id)
         .await?;
 
+    let batch = attachment.database.begin_batch().await?;
+    let started = Instant::now();
+    batch
+        .set_update_build_range(
+            version.update_id,
+            metadata.since.as_deref().filter(|v| !v.is_empty()),
+            metadata.until.as_deref().filter(|v| !v.is_empty()),
+        )
+        .await?;
+
+    batch.set_update_release_date(version.update_id, metadata.cdate.map(|v| v / 1000)).await?;
+
+    batch
+        .set_update_change_notes(
+            version.update_id,
+            metadata.notes.as_deref().filter(|v| !v.trim().is_empty()),
+        )
+        .await?;
+
     for dependency in metadata.dependencies {
         let dependency = CachedUpdateDependency {
             dependency_xml_id: dependency,
@@ -111,10 +551,7 @@ async fn sync_update_dependency_meta(
             optional: false,
         };
 
-        attachment
-            .database
-            .add_update_dependency(&dependency)
-            .await?;
+        batch.add_update_dependency(&dependency).await?;
     }
 
     for optional_dependency in metadata.optional_dependencies {
@@ -124,42 +561,236 @@ async fn sync_update_dependency_meta(
             optional: true,
         };
 
-        attachment
-            .database
-            .add_update_dependency(&dependency)
-            .await?;
+        batch.add_update_dependency(&dependency).await?;
     }
 
+    batch.commit().await?;
+    attachment.send_timing(TimingCategory::DatabaseWrites, started);
+
     Ok(())
 }
 
 #[tracing::instrument(skip(attachment))]
-async fn sync_update_meta(attachment: TaskAttachment, update_id: u64) -> Result<(), IndexerError> {
-    let (download_info, mut cached_update) = tokio::try_join!(
+async fn sync_update_verification(
+    attachment: TaskAttachment,
+    update_id: u64,
+) -> Result<(), IndexerError> {
+    let verifications = attachment
+        .repo
+        .fetch_update_verification(update_id)
+        .await?
+        .into_iter()
+        .map(|v| {
+            let compatible = v.is_compatible();
+            (v.ide_version, compatible)
+        })
+        .collect::<Vec<_>>();
+
+    attachment
+        .database
+        .set_update_verifications(update_id, &verifications)
+        .await
+}
+
+/// Whether the artifact behind an update may have been replaced.
+///
+/// The etag is authoritative if the marketplace sent one and it is known,
+/// otherwise the resolved download location is compared. Updates imported
+/// from a tree have no etag yet.
+fn download_changed(
+    cached_update: &CachedUpdate,
+    download_info: &RepoDownloadInfo,
+    download_url: &str,
+) -> bool {
+    if cached_update.hash.is_none() {
+        return true;
+    }
+
+    match (&cached_update.etag, &download_info.etag) {
+        (Some(cached), Some(current)) => cached != current,
+        (None, _) => {
+            cached_update.download_url.as_deref() != Some(download_url)
+                || cached_update.file_name != download_info.file_name
+        }
+        _ => true,
+    }
+}
+
+/// The download URL of an update in the configured style and the one in the
+/// other style if there is one, so the stored URLs don't change between runs
+/// unless the artifact does.
+fn stored_download_urls(
+    attachment: &TaskAttachment,
+    update_id: u64,
+    resolved: &Url,
+) -> (String, Option<String>) {
+    let options = &attachment.sync_options;
+    let resolved = normalize_resolved_url(options, resolved);
+    let stable = attachment
+        .repo
+        .stable_download_url(update_id)
+        .map(String::from)
+        .filter(|v| *v != resolved);
+
+    match (options.download_url_style, stable) {
+        (DownloadUrlStyle::Stable, Some(stable)) => (stable, Some(resolved)),
+        (_, stable) => (resolved, stable),
+    }
+}
+
+/// Strip the volatile parts of a resolved download URL.
+fn normalize_resolved_url(options: &SyncOptions, resolved: &Url) -> String {
+    let mut url = resolved.clone();
+    url.set_fragment(None);
+
+    let pairs = url
+        .query_pairs()
+        .filter(|(name, _)| !options.strip_download_url_params.contains(name.as_ref()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    // Leave the query alone unless something was stripped, re-encoding it
+    // could change the URL as well
+    if pairs.len() != url.query_pairs().count() {
+        match pairs.is_empty() {
+            true => url.set_query(None),
+            false => {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+    }
+
+    url.to_string()
+}
+
+/// Resolve and hash the artifact of an update.
+///
+/// Reverifying hashes the artifact even if its etag did not change.
+async fn sync_update_meta(
+    attachment: TaskAttachment,
+    update_id: u64,
+    reverify: bool,
+) -> Result<(), IndexerError> {
+    let (mut download_info, mut cached_update) = tokio::try_join!(
         attachment.repo.resolve_update_download_info(update_id),
         attachment.database.get_update(update_id)
     )?;
 
-    if cached_update.etag.as_deref() == download_info.etag.as_deref() {
-        // Up-to-date
+    let (download_url, fallback_url) = stored_download_urls(&attachment, update_id, &download_info.url);
+    if !reverify && !download_changed(&cached_update, &download_info, &download_url) {
+        let mut changed = false;
+
+        // Up-to-date, but imported without an etag
+        if cached_update.etag.is_none() && download_info.etag.is_some() {
+            cached_update.etag = download_info.etag.clone();
+            changed = true;
+        }
+
+        // Up-to-date, but hashed before sizes were recorded
+        if cached_update.size.is_none() && download_info.size.is_some() {
+            cached_update.size = download_info.size;
+            changed = true;
+        }
+
+        // Up-to-date, but stored in another style of URL
+        if cached_update.download_url.as_deref() != Some(download_url.as_str())
+            || cached_update.fallback_download_url != fallback_url
+        {
+            cached_update.download_url = Some(download_url);
+            cached_update.fallback_download_url = fallback_url;
+            attachment.database.mark_update_plugins_dirty(update_id).await?;
+            changed = true;
+        }
+
+        if changed {
+            attachment.database.change_update_info(&cached_update).await?;
+        }
+
         return Ok(());
     }
 
-    let hash_info = attachment
-        .repo
-        .hash_download_url(&download_info.url)
-        .await?;
+    if cached_update.hash.is_some() {
+        tracing::info!("Download of update {} changed, hashing again", update_id);
+    }
+
+    // The artifact may be swapped between resolving and downloading it
+    let started = Instant::now();
+    let hash_info = match attachment.repo.hash_download_url(&download_info).await {
+        Err(IndexerError::ArtifactChanged(reason)) => {
+            tracing::warn!("Artifact of update {} {}, resolving it again", update_id, reason);
+
+            download_info = attachment.repo.resolve_update_download_info(update_id).await?;
+            attachment.repo.hash_download_url(&download_info).await?
+        }
+        result => result?,
+    };
+    attachment.send_timing(TimingCategory::Hashing, started);
+    attachment.counters.record_update_hashed();
+
+    if cached_update.hash_algorithm.as_deref() == Some(hash_info.algorithm.as_str())
+        && cached_update
+            .hash
+            .as_deref()
+            .is_some_and(|v| v != hash_info.value)
+    {
+        tracing::warn!("Artifact of update {} was replaced upstream", update_id);
+        attachment.database.mark_update_hash_changed(update_id).await?;
+
+        if reverify {
+            attachment.send_problem(
+                format!("reverify update {}", update_id),
+                IndexerError::HashMismatch(update_id),
+            );
+        }
+    }
 
     cached_update.etag = download_info.etag;
     cached_update.file_name = download_info.file_name;
-    cached_update.download_url = Some(download_info.url.to_string());
+    let (download_url, fallback_url) = stored_download_urls(&attachment, update_id, &download_info.url);
+    cached_update.download_url = Some(download_url);
+    cached_update.fallback_download_url = fallback_url;
+    cached_update.size = download_info.size.or(hash_info.size);
+    let artifact = attachment
+        .repo
+        .artifact_cache()
+        .map(|cache| cache.path_for(&hash_info.algorithm, &hash_info.value));
+
     cached_update.hash_algorithm = Some(hash_info.algorithm);
     cached_update.hash = Some(hash_info.value);
 
+    let started = Instant::now();
     attachment
         .database
         .change_update_info(&cached_update)
         .await?;
+    attachment.database.mark_update_verified(update_id).await?;
+    attachment.database.mark_update_plugins_dirty(update_id).await?;
+    attachment.send_timing(TimingCategory::DatabaseWrites, started);
+
+    // Only artifacts we had to download ourselves can be inspected
+    if let Some(artifact) = artifact
+        && tokio::fs::try_exists(&artifact).await?
+    {
+        let configs = tokio::task::spawn_blocking(move || {
+            crate::inspect::optional_dependency_configs(&artifact)
+        })
+        .await
+        .map_err(IndexerError::from)
+        .and_then(|v| v);
+
+        // The hash is already stored, a retry would not inspect the artifact again
+        match configs {
+            Ok(configs) => {
+                attachment
+                    .database
+                    .set_update_dependency_configs(update_id, &configs)
+                    .await?;
+            }
+            Err(err) => {
+                attachment.send_problem(format!("inspect update {}", update_id), err);
+            }
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,103 @@
+use std::cmp::Ordering;
+
+/// Compare plugin versions the lenient way JetBrains does.
+///
+/// Versions are split into numbers and words at separators and at every
+/// change between digits and letters. Numbers compare numerically, known
+/// qualifiers such as `beta` or `SNAPSHOT` sort before the release they
+/// precede, so `233.1.SNAPSHOT < 233.1 < 233.1.1`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = tokenize(a);
+    let b = tokenize(b);
+
+    for i in 0..a.len().max(b.len()) {
+        let ordering = compare_tokens(a.get(i), b.get(i));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+const UNKNOWN_WORD: u8 = 60;
+
+#[derive(Debug)]
+enum Token {
+    Number(String),
+    Word(String),
+}
+
+fn tokenize(version: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    let mut flush = |current: &mut String| {
+        if current.is_empty() {
+            return;
+        }
+
+        let token = std::mem::take(current);
+        tokens.push(match token.starts_with(|c: char| c.is_ascii_digit()) {
+            true => Token::Number(token.trim_start_matches('0').to_owned()),
+            false => Token::Word(token.to_lowercase()),
+        });
+    };
+
+    for c in version.trim().chars() {
+        if !c.is_alphanumeric() {
+            flush(&mut current);
+            continue;
+        }
+
+        if current
+            .chars()
+            .last()
+            .is_some_and(|last| last.is_ascii_digit() != c.is_ascii_digit())
+        {
+            flush(&mut current);
+        }
+
+        current.push(c);
+    }
+
+    flush(&mut current);
+    tokens
+}
+
+/// Rank of a token relative to the end of the version, which is a release.
+fn rank(token: Option<&Token>) -> u8 {
+    match token {
+        Some(Token::Word(word)) => match word.as_str() {
+            "snapshot" | "dev" | "nightly" => 10,
+            "alpha" | "a" => 20,
+            "beta" | "b" => 30,
+            "eap" | "m" | "milestone" | "pre" | "preview" => 40,
+            "rc" | "cr" => 50,
+            "release" | "final" | "ga" => 70,
+            "sp" => 80,
+            _ => UNKNOWN_WORD,
+        },
+        None => 70,
+        Some(Token::Number(_)) => 90,
+    }
+}
+
+fn compare_tokens(a: Option<&Token>, b: Option<&Token>) -> Ordering {
+    if let (Some(Token::Number(a)), Some(Token::Number(b))) = (a, b) {
+        // Leading zeros are stripped, so the longer number is larger
+        return a.len().cmp(&b.len()).then_with(|| a.cmp(b));
+    }
+
+    let (rank_a, rank_b) = (rank(a), rank(b));
+
+    match (a, b) {
+        // Unknown words only order among themselves by name
+        (Some(Token::Word(a)), Some(Token::Word(b)))
+            if rank_a == UNKNOWN_WORD && rank_b == UNKNOWN_WORD =>
+        {
+            a.cmp(b)
+        }
+        _ => rank_a.cmp(&rank_b),
+    }
+}
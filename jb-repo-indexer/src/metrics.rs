@@ -0,0 +1,176 @@
+use crate::api::HttpStatisticsSnapshot;
+use crate::error::IndexerError;
+use crate::statistics::Statistics;
+use bytes::Bytes;
+use http_body_util::Full;
+use reqwest::Url;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::net::TcpListener;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counters about the progress of a sync.
+#[derive(Debug, Default)]
+pub struct SyncCounters {
+    plugins_synced: AtomicU64,
+    updates_hashed: AtomicU64,
+}
+
+impl SyncCounters {
+    pub fn record_plugin_synced(&self) {
+        self.plugins_synced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_update_hashed(&self) {
+        self.updates_hashed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Render the metrics in the Prometheus text format.
+///
+/// Task outcomes are only known once a sync finished.
+pub fn render(
+    counters: &SyncCounters,
+    http: &HttpStatisticsSnapshot,
+    statistics: Option<&Statistics>,
+) -> String {
+    let mut out = String::new();
+
+    counter(
+        &mut out,
+        "plugins_synced_total",
+        "Plugins synced.",
+        counters.plugins_synced.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "updates_hashed_total",
+        "Updates whose artifact was hashed.",
+        counters.updates_hashed.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "http_requests_total",
+        "HTTP requests sent.",
+        http.requests,
+    );
+    counter(
+        &mut out,
+        "http_requests_failed_total",
+        "HTTP requests without a response.",
+        http.failed_requests,
+    );
+    counter(
+        &mut out,
+        "http_retries_total",
+        "HTTP requests retried.",
+        http.retries,
+    );
+    counter(
+        &mut out,
+        "http_downloaded_bytes_total",
+        "Bytes downloaded.",
+        http.bytes_downloaded,
+    );
+
+    header(
+        &mut out,
+        "http_responses_total",
+        "HTTP responses by status.",
+        "counter",
+    );
+    for (status, count) in &http.status_codes {
+        let _ = writeln!(
+            out,
+            "jb_repo_indexer_http_responses_total{{status=\"{}\"}} {}",
+            status, count
+        );
+    }
+
+    if let Some(statistics) = statistics {
+        gauge(
+            &mut out,
+            "tasks_succeeded",
+            "Tasks which succeeded in the last sync.",
+            statistics.successful_tasks as u64,
+        );
+        gauge(
+            &mut out,
+            "tasks_failed",
+            "Tasks which failed in the last sync.",
+            statistics.failures.len() as u64,
+        );
+        gauge(
+            &mut out,
+            "task_problems",
+            "Problems encountered in the last sync.",
+            statistics.problems.len() as u64,
+        );
+        gauge(
+            &mut out,
+            "last_sync_timestamp_seconds",
+            "When the last sync finished.",
+            crate::meta::unix_timestamp() as u64,
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP jb_repo_indexer_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE jb_repo_indexer_{} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "jb_repo_indexer_{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "jb_repo_indexer_{} {}", name, value);
+}
+
+/// Serve the rendered metrics to scrapers in the background.
+pub async fn serve<F>(address: SocketAddr, render: F) -> Result<(), IndexerError>
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!("Serving metrics on http://{}/metrics", address);
+
+    tokio::spawn(crate::server::accept_loop(listener, move |_| {
+        let body = render();
+
+        async move {
+            hyper::Response::builder()
+                .header(hyper::header::CONTENT_TYPE, CONTENT_TYPE)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        }
+    }));
+
+    Ok(())
+}
+
+/// Push the rendered metrics to a Prometheus pushgateway.
+pub async fn push(gateway: &Url, body: String) -> Result<(), IndexerError> {
+    let url = format!(
+        "{}/metrics/job/{}",
+        gateway.as_str().trim_end_matches('/'),
+        env!("CARGO_PKG_NAME")
+    );
+
+    reqwest::Client::new()
+        .put(url)
+        .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
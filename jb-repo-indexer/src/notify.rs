@@ -0,0 +1,157 @@
+use crate::args::{ApiArgs, NotifyArgs, WebhookFormat};
+use crate::db::DatabaseChanges;
+use crate::error::{IndexerError, error_chain};
+use crate::statistics::{FailureCategory, Statistics};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use url::Url;
+
+/// Failed tasks listed by name in chat messages.
+const LISTED_FAILURES: usize = 10;
+
+/// Discord rejects longer messages.
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+#[derive(Debug, Serialize)]
+struct SyncSummary<'a> {
+    successful_tasks: usize,
+    problems: usize,
+    failures: usize,
+    failure_counts: BTreeMap<FailureCategory, usize>,
+    failed_tasks: Vec<&'a str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changes: Option<&'a DatabaseChanges>,
+}
+
+/// Post a summary of a finished sync to the webhook, unless too few tasks
+/// failed.
+pub async fn notify(
+    args: &NotifyArgs,
+    api: &ApiArgs,
+    statistics: &Statistics,
+    changes: Option<&DatabaseChanges>,
+) -> Result<(), IndexerError> {
+    let Some(url) = &args.webhook_url else {
+        return Ok(());
+    };
+
+    if args
+        .webhook_min_failures
+        .is_some_and(|min| statistics.failures.len() < min)
+    {
+        return Ok(());
+    }
+
+    let summary = SyncSummary {
+        successful_tasks: statistics.successful_tasks,
+        problems: statistics.problems.len(),
+        failures: statistics.failures.len(),
+        failure_counts: statistics.failure_counts(),
+        failed_tasks: statistics
+            .failures
+            .iter()
+            .map(|v| v.task_name.as_str())
+            .collect(),
+        changes,
+    };
+
+    let payload = match args.webhook_format {
+        WebhookFormat::Json => serde_json::to_vec(&summary)?,
+        WebhookFormat::Slack => serde_json::to_vec(&json!({ "text": message(&summary) }))?,
+        WebhookFormat::Discord => {
+            serde_json::to_vec(&json!({ "content": discord_message(message(&summary)) }))?
+        }
+    };
+
+    post(url, api, payload).await
+}
+
+/// Post the error a sync failed with to the webhook, regardless of how many
+/// tasks failed before.
+pub async fn notify_error(
+    args: &NotifyArgs,
+    api: &ApiArgs,
+    error: &IndexerError,
+) -> Result<(), IndexerError> {
+    let Some(url) = &args.webhook_url else {
+        return Ok(());
+    };
+
+    let chain = error_chain(error);
+    let message = format!("Sync failed: {}", chain);
+    let payload = match args.webhook_format {
+        WebhookFormat::Json => serde_json::to_vec(&json!({ "error": chain }))?,
+        WebhookFormat::Slack => serde_json::to_vec(&json!({ "text": message }))?,
+        WebhookFormat::Discord => serde_json::to_vec(&json!({ "content": discord_message(message) }))?,
+    };
+
+    post(url, api, payload).await
+}
+
+async fn post(url: &Url, api: &ApiArgs, payload: Vec<u8>) -> Result<(), IndexerError> {
+    crate::api::http_client(api)?
+        .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(payload)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    tracing::info!("Notified webhook {}", url);
+    Ok(())
+}
+
+/// Shorten a message to what Discord accepts.
+fn discord_message(mut message: String) -> String {
+    if message.len() > DISCORD_MAX_LENGTH {
+        let mut end = DISCORD_MAX_LENGTH - 3;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        message.truncate(end);
+        message.push_str("...");
+    }
+
+    message
+}
+
+/// A human readable summary for chat messages.
+fn message(summary: &SyncSummary) -> String {
+    let mut out = format!(
+        "Sync finished: {} tasks succeeded, {} failed, {} problems",
+        summary.successful_tasks, summary.failures, summary.problems
+    );
+
+    for (category, count) in &summary.failure_counts {
+        let _ = write!(out, "\n- {}: {}", category.name(), count);
+    }
+
+    if !summary.failed_tasks.is_empty() {
+        out.push_str("\nFailed tasks:");
+        for task in summary.failed_tasks.iter().take(LISTED_FAILURES) {
+            let _ = write!(out, "\n- {}", task);
+        }
+
+        if summary.failed_tasks.len() > LISTED_FAILURES {
+            let _ = write!(out, "\n- and {} more", summary.failed_tasks.len() - LISTED_FAILURES);
+        }
+    }
+
+    if let Some(changes) = summary.changes {
+        let _ = write!(
+            out,
+            "\nDry run, would change: {} new plugins, {} removed plugins, {} new versions, {} removed versions, {} changed artifacts",
+            changes.added_plugins.len(),
+            changes.removed_plugins.len(),
+            changes.added_versions.len(),
+            changes.removed_versions.len(),
+            changes.changed_artifacts.len(),
+        );
+    }
+
+    out
+}
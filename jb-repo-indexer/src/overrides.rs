@@ -0,0 +1,93 @@
+use crate::args::LatestStrategy;
+use crate::error::IndexerError;
+use crate::meta::parse_sri_hash;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Operator provided overrides applied on top of the marketplace data.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    #[serde(default)]
+    pub plugins: BTreeMap<String, PluginOverride>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginOverride {
+    /// Strategy used to select the latest version of this plugin.
+    #[serde(default)]
+    pub latest: Option<LatestStrategy>,
+
+    /// Overrides of single versions, keyed by version.
+    #[serde(default)]
+    pub versions: BTreeMap<String, VersionOverride>,
+}
+
+/// Escape hatch for versions whose artifact the marketplace serves broken.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionOverride {
+    /// Leave the version out of the generated tree.
+    #[serde(default)]
+    pub exclude: bool,
+
+    /// Download the artifact from here instead, requires a pinned hash.
+    #[serde(default)]
+    pub download_url: Option<String>,
+
+    /// The hash of the artifact in SRI form, such as `sha256-...`.
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl VersionOverride {
+    /// The pinned hash as algorithm and raw value, like hashes are stored.
+    pub fn pinned_hash(&self) -> Result<Option<(String, Vec<u8>)>, IndexerError> {
+        let Some(hash) = &self.hash else {
+            return Ok(None);
+        };
+
+        parse_sri_hash(hash)
+            .map(Some)
+            .ok_or_else(|| IndexerError::InvalidOverrides(format!("unsupported hash {}", hash)))
+    }
+}
+
+impl Overrides {
+    /// Load the overrides file, if any.
+    pub async fn load(path: Option<&Path>) -> Result<Self, IndexerError> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        tracing::debug!("Loading overrides from {}", path.display());
+        let data = tokio::fs::read(path).await?;
+        let overrides: Self = serde_json::from_slice(&data)?;
+
+        // Fail before generating anything instead of once the version is reached
+        for (xml_id, plugin) in &overrides.plugins {
+            for (version, version_override) in &plugin.versions {
+                version_override.pinned_hash()?;
+
+                if version_override.download_url.is_some() && version_override.hash.is_none() {
+                    return Err(IndexerError::InvalidOverrides(format!(
+                        "{}@{} pins a download url without a hash",
+                        xml_id, version
+                    )));
+                }
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    pub fn plugin(&self, xml_id: &str) -> Option<&PluginOverride> {
+        self.plugins.get(xml_id)
+    }
+
+    pub fn version(&self, xml_id: &str, version: &str) -> Option<&VersionOverride> {
+        self.plugin(xml_id)?.versions.get(version)
+    }
+}
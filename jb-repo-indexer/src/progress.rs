@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use indicatif::ProgressStyle;
+use tracing::Span;
+use tracing_indicatif::span_ext::IndicatifSpanExt as _;
+
+/// A phase of a sync shown as its own progress bar.
+#[derive(Debug, Clone, Copy)]
+pub enum SyncPhase {
+    Plugins,
+    Versions,
+    Hashing,
+}
+
+/// Progress bars of the phases of a sync.
+///
+/// The totals grow as tasks are dispatched, unless they were expected up
+/// front. Every task counts as done once it finished, whether it succeeded
+/// or not.
+#[derive(Debug)]
+pub struct SyncProgress {
+    plugins: Span,
+    versions: Span,
+    hashing: Span,
+    expected: [AtomicU64; 3],
+}
+
+impl SyncProgress {
+    pub fn new() -> Self {
+        let style = ProgressStyle::with_template(
+            "{span_name:>8} [{wide_bar}] {pos}/{len} ({per_sec}, {eta} left)",
+        )
+        .unwrap()
+        .progress_chars("=> ");
+
+        let phase = |span: Span| {
+            span.pb_set_style(&style);
+            span.pb_set_length(0);
+            span.pb_start();
+            span
+        };
+
+        Self {
+            plugins: phase(tracing::info_span!(parent: None, "plugins")),
+            versions: phase(tracing::info_span!(parent: None, "versions")),
+            hashing: phase(tracing::info_span!(parent: None, "hashing")),
+            expected: Default::default(),
+        }
+    }
+
+    /// Add tasks which will be dispatched later to the total of a phase.
+    pub fn expect_tasks(&self, phase: SyncPhase, count: u64) {
+        self.expected[phase as usize].fetch_add(count, Ordering::Relaxed);
+        self.span(phase).pb_inc_length(count);
+    }
+
+    pub fn add_task(&self, phase: SyncPhase) {
+        let was_expected = self.expected[phase as usize]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1))
+            .is_ok();
+
+        if !was_expected {
+            self.span(phase).pb_inc_length(1);
+        }
+    }
+
+    pub fn finish_task(&self, phase: SyncPhase) {
+        self.span(phase).pb_inc(1);
+    }
+
+    fn span(&self, phase: SyncPhase) -> &Span {
+        match phase {
+            SyncPhase::Plugins => &self.plugins,
+            SyncPhase::Versions => &self.versions,
+            SyncPhase::Hashing => &self.hashing,
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use crate::args::S3Args;
+use crate::error::IndexerError;
+use hmac::{Hmac, Mac};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use reqwest::{Client, Method, Url};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Minimal client for S3 compatible object storage, signing requests with
+/// AWS Signature Version 4.
+///
+/// Buckets are always addressed path-style, which every compatible
+/// service supports.
+#[derive(Debug, Clone)]
+pub struct S3Bucket {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Bucket {
+    pub fn new(args: &S3Args, bucket: String) -> Result<Self, IndexerError> {
+        let endpoint = match &args.s3_endpoint {
+            Some(v) => v.clone(),
+            None => Url::parse(&format!("https://s3.{}.amazonaws.com", args.s3_region)).unwrap(),
+        };
+
+        let (Some(access_key_id), Some(secret_access_key)) =
+            (&args.s3_access_key_id, &args.s3_secret_access_key)
+        else {
+            return Err(IndexerError::IncompleteData(
+                "S3 credentials are missing".into(),
+            ));
+        };
+
+        Ok(Self {
+            client: Client::new(),
+            endpoint,
+            bucket,
+            region: args.s3_region.clone(),
+            access_key_id: access_key_id.clone(),
+            secret_access_key: secret_access_key.clone(),
+            session_token: args.s3_session_token.clone(),
+        })
+    }
+
+    /// All objects below a prefix, mapped to their etag.
+    #[tracing::instrument(skip(self))]
+    pub async fn list(&self, prefix: &str) -> Result<HashMap<String, String>, IndexerError> {
+        let mut objects = HashMap::new();
+        let mut continuation_token = None;
+
+        loop {
+            let mut query = vec![("list-type", "2".to_owned()), ("prefix", prefix.to_owned())];
+            if let Some(token) = continuation_token.take() {
+                query.push(("continuation-token", token));
+            }
+
+            let data = self
+                .send(Method::GET, "", &query, None)
+                .await?
+                .bytes()
+                .await?;
+
+            let page = parse_list_page(&data)?;
+            objects.extend(page.objects);
+
+            match page.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self, data))]
+    pub async fn put(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> Result<(), IndexerError> {
+        self.send(Method::PUT, key, &[], Some((data, content_type)))
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn delete(&self, key: &str) -> Result<(), IndexerError> {
+        self.send(Method::DELETE, key, &[], None).await?;
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, String)],
+        body: Option<(Vec<u8>, &str)>,
+    ) -> Result<reqwest::Response, IndexerError> {
+        let mut url = self.endpoint.clone();
+        url.set_path(&match key.is_empty() {
+            true => format!("/{}", uri_encode(&self.bucket, false)),
+            false => format!(
+                "/{}/{}",
+                uri_encode(&self.bucket, false),
+                uri_encode(key, false)
+            ),
+        });
+
+        let mut query = query.to_vec();
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k, true), uri_encode(v, true)))
+            .collect::<Vec<_>>()
+            .join("&");
+        url.set_query((!canonical_query.is_empty()).then_some(canonical_query.as_str()));
+
+        let payload_hash = match &body {
+            Some((data, _)) => crate::hex::encode(Sha256::digest(data)),
+            None => EMPTY_PAYLOAD_HASH.to_owned(),
+        };
+
+        let amz_date = format_amz_date(crate::meta::unix_timestamp());
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let authorization =
+            self.authorization(&method, url.path(), &canonical_query, &headers, &amz_date);
+        let mut request = self
+            .client
+            .request(method, url)
+            .header(reqwest::header::AUTHORIZATION, authorization);
+
+        // Host is set by the client itself
+        for (name, value) in headers.into_iter().skip(1) {
+            request = request.header(name, value);
+        }
+
+        if let Some((data, content_type)) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(data);
+        }
+
+        Ok(request.send().await?.error_for_status()?)
+    }
+
+    /// The `Authorization` header of a request, the headers have to include
+    /// the payload hash.
+    fn authorization(
+        &self,
+        method: &Method,
+        path: &str,
+        canonical_query: &str,
+        headers: &[(&str, String)],
+        amz_date: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+
+        let canonical_headers = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect::<String>();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+        let payload_hash = headers
+            .iter()
+            .find(|(k, _)| *k == "x-amz-content-sha256")
+            .map(|(_, v)| v.as_str())
+            .unwrap_or(EMPTY_PAYLOAD_HASH);
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.as_str(),
+            path,
+            canonical_query,
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            crate::hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part);
+        }
+        let signature = crate::hex::encode(hmac_sha256(&key, &string_to_sign));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, signature
+        )
+    }
+}
+
+struct ListPage {
+    objects: Vec<(String, String)>,
+    next_continuation_token: Option<String>,
+}
+
+fn parse_list_page(data: &[u8]) -> Result<ListPage, IndexerError> {
+    let mut reader = Reader::from_reader(data);
+    let mut buffer = Vec::new();
+
+    let mut objects = Vec::new();
+    let mut next_continuation_token = None;
+    let mut key = None;
+    let mut etag = None;
+    let mut element = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(start) => element = start.local_name().as_ref().to_vec(),
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+
+                match element.as_slice() {
+                    b"Key" => key = Some(text),
+                    b"ETag" => etag = Some(text.trim_matches('"').to_owned()),
+                    b"NextContinuationToken" => next_continuation_token = Some(text),
+                    _ => {}
+                }
+            }
+            Event::End(end) => {
+                if end.local_name().as_ref() == b"Contents"
+                    && let Some(key) = key.take()
+                {
+                    objects.push((key, etag.take().unwrap_or_default()));
+                }
+
+                element.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buffer.clear();
+    }
+
+    Ok(ListPage {
+        objects,
+        next_continuation_token,
+    })
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but unreserved characters, optionally keeping
+/// slashes as path separators.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// Format a unix timestamp as `YYYYMMDD'T'HHMMSS'Z'`.
+fn format_amz_date(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // Civil date from days since the epoch, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,89 @@
+use crate::error::IndexerError;
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair as _, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Name of the manifest announcing the key a tree is signed with.
+pub const KEYS_FILE: &str = "keys.json";
+
+/// An ed25519 key generated files are signed with.
+///
+/// The key file holds the base64 encoded 32 byte seed, such as generated by
+/// `head -c 32 /dev/urandom | base64`.
+pub struct SigningKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+impl SigningKey {
+    pub async fn load(path: &Path) -> Result<Self, IndexerError> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let seed = BASE64_STANDARD.decode(content.trim())?;
+
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&seed).map_err(|_| {
+            IndexerError::InvalidSigningKey(format!(
+                "{} does not contain a 32 byte seed",
+                path.display()
+            ))
+        })?;
+
+        Ok(Self { key_pair })
+    }
+
+    /// The base64 encoded public key.
+    pub fn public_key(&self) -> String {
+        BASE64_STANDARD.encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// The base64 encoded signature of some data.
+    pub fn sign(&self, data: &[u8]) -> String {
+        BASE64_STANDARD.encode(self.key_pair.sign(data).as_ref())
+    }
+
+    pub fn manifest(&self) -> KeyManifest {
+        KeyManifest {
+            algorithm: ED25519_ALGORITHM.to_owned(),
+            public_key: self.public_key(),
+        }
+    }
+}
+
+const ED25519_ALGORITHM: &str = "ed25519";
+
+/// Contents of [`KEYS_FILE`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyManifest {
+    pub algorithm: String,
+    pub public_key: String,
+}
+
+impl KeyManifest {
+    pub fn check_algorithm(&self) -> Result<(), IndexerError> {
+        match self.algorithm.as_str() {
+            ED25519_ALGORITHM => Ok(()),
+            other => Err(IndexerError::InvalidSigningKey(format!(
+                "unsupported signature algorithm {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Check a base64 encoded signature against a base64 encoded public key.
+pub fn verify(public_key: &str, data: &[u8], signature: &str) -> Result<bool, IndexerError> {
+    let public_key = BASE64_STANDARD.decode(public_key.trim())?;
+    let signature = BASE64_STANDARD.decode(signature.trim())?;
+
+    Ok(UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(data, &signature)
+        .is_ok())
+}
@@ -1,17 +1,304 @@
+use crate::error::IndexerError;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 #[derive(Debug)]
 pub struct Statistics {
     pub successful_tasks: usize,
+
+    /// Tasks which did not finish because of a shutdown.
+    pub cancelled_tasks: usize,
+
+    /// Whether the sync was shut down before all tasks finished.
+    pub interrupted: bool,
     pub problems: Vec<ProblemReport>,
     pub failures: Vec<ErrorReport>,
+
+    /// How long every timed operation took, per category.
+    pub timings: BTreeMap<TimingCategory, Vec<Duration>>,
+}
+
+impl Statistics {
+    /// How many tasks failed per category.
+    pub fn failure_counts(&self) -> BTreeMap<FailureCategory, usize> {
+        let mut counts = BTreeMap::new();
+        for failure in &self.failures {
+            *counts.entry(failure.category).or_default() += 1;
+        }
+
+        counts
+    }
+
+    /// The first of the given categories any task failed with.
+    pub fn first_failure_in(&self, categories: &[FailureCategory]) -> Option<FailureCategory> {
+        let counts = self.failure_counts();
+        categories.iter().copied().find(|v| counts.contains_key(v))
+    }
+
+    /// Distribution of the durations of every timed category.
+    pub fn timing_summaries(&self) -> BTreeMap<TimingCategory, TimingSummary> {
+        self.timings
+            .iter()
+            .map(|(category, durations)| (*category, TimingSummary::new(durations)))
+            .collect()
+    }
+
+    /// Log where the time of the sync went.
+    pub fn log_timings(&self) {
+        tracing::info!("Timings:");
+        for (category, summary) in self.timing_summaries() {
+            tracing::info!(
+                "- {}: {} in {:.2?} (p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?})",
+                category.name(),
+                summary.count,
+                summary.total,
+                summary.p50,
+                summary.p90,
+                summary.p99,
+                summary.max
+            );
+        }
+    }
+
+    /// Write the statistics as JSON, for consumption by CI pipelines.
+    pub async fn write_report(&self, path: &Path) -> Result<(), IndexerError> {
+        let report = StatisticsReport {
+            successful_tasks: self.successful_tasks,
+            cancelled_tasks: self.cancelled_tasks,
+            interrupted: self.interrupted,
+            problems: self
+                .problems
+                .iter()
+                .map(|v| TaskErrorReport::new(&v.task_name, v.error.as_ref()))
+                .collect(),
+            failures: self
+                .failures
+                .iter()
+                .map(|v| TaskErrorReport {
+                    category: Some(v.category),
+                    ..TaskErrorReport::new(&v.task_name, v.error.as_ref())
+                })
+                .collect(),
+            failure_counts: self.failure_counts(),
+            timings: self.timing_summaries(),
+        };
+
+        let data = serde_json::to_vec_pretty(&report)?;
+        tokio::fs::write(path, data).await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatisticsReport<'a> {
+    successful_tasks: usize,
+    cancelled_tasks: usize,
+    interrupted: bool,
+    problems: Vec<TaskErrorReport<'a>>,
+    failures: Vec<TaskErrorReport<'a>>,
+    failure_counts: BTreeMap<FailureCategory, usize>,
+    timings: BTreeMap<TimingCategory, TimingSummary>,
+}
+
+/// What kind of operation a duration was measured for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimingCategory {
+    /// Fetching the details of a plugin.
+    PluginDetails,
+
+    /// Listing the versions of a plugin.
+    VersionListing,
+
+    /// Hashing the artifact of an update, downloading it if needed.
+    Hashing,
+
+    /// Writing the results of a task to the database.
+    DatabaseWrites,
+}
+
+impl TimingCategory {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PluginDetails => "plugin-details",
+            Self::VersionListing => "version-listing",
+            Self::Hashing => "hashing",
+            Self::DatabaseWrites => "database-writes",
+        }
+    }
+}
+
+/// Distribution of the durations of a category, in milliseconds in the report.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TimingSummary {
+    pub count: usize,
+
+    #[serde(serialize_with = "millis")]
+    pub total: Duration,
+
+    #[serde(serialize_with = "millis")]
+    pub p50: Duration,
+
+    #[serde(serialize_with = "millis")]
+    pub p90: Duration,
+
+    #[serde(serialize_with = "millis")]
+    pub p99: Duration,
+
+    #[serde(serialize_with = "millis")]
+    pub max: Duration,
+}
+
+impl TimingSummary {
+    fn new(durations: &[Duration]) -> Self {
+        let mut sorted = durations.to_vec();
+        sorted.sort_unstable();
+
+        // Nearest rank, so every percentile is a measured duration
+        let percentile = |p: usize| match sorted.len() {
+            0 => Duration::ZERO,
+            len => sorted[(len * p).div_ceil(100).max(1) - 1],
+        };
+
+        Self {
+            count: sorted.len(),
+            total: sorted.iter().sum(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+fn millis<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+#[derive(Debug, Serialize)]
+struct TaskErrorReport<'a> {
+    task_name: &'a str,
+    error: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<FailureCategory>,
+
+    /// The sources of the error, outermost first.
+    caused_by: Vec<String>,
+}
+
+impl<'a> TaskErrorReport<'a> {
+    fn new(task_name: &'a str, error: &(dyn std::error::Error + Send + 'static)) -> Self {
+        let mut caused_by = Vec::new();
+
+        let mut src = error.source();
+        while let Some(err) = src {
+            caused_by.push(err.to_string());
+            src = err.source();
+        }
+
+        Self {
+            task_name,
+            error: error.to_string(),
+            category: None,
+            caused_by,
+        }
+    }
+}
+
+/// What kind of error a task failed with.
+///
+/// Categories are ordered by their exit code.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureCategory {
+    /// The repository could not be reached or answered with an error, exit code 10.
+    Network,
+
+    /// The repository kept rejecting requests as too many, exit code 11.
+    RateLimit,
+
+    /// The repository sent data which could not be understood, exit code 12.
+    Parse,
+
+    /// An artifact doesn't match its recorded hash anymore, exit code 13.
+    HashMismatch,
+
+    /// Reading or writing the database failed, exit code 14.
+    Database,
+
+    /// Anything else, exit code 15.
+    Other,
+}
+
+impl FailureCategory {
+    /// Classify an error by the first cause in its chain which tells the
+    /// category.
+    pub fn of(error: &(dyn std::error::Error + 'static)) -> Self {
+        let mut src = Some(error);
+        while let Some(err) = src {
+            if let Some(category) = err.downcast_ref::<IndexerError>().and_then(Self::of_indexer_error)
+            {
+                return category;
+            }
+
+            src = err.source();
+        }
+
+        Self::Other
+    }
+
+    fn of_indexer_error(error: &IndexerError) -> Option<Self> {
+        Some(match error {
+            IndexerError::HttpClientError(err)
+                if err.status() == Some(StatusCode::TOO_MANY_REQUESTS) =>
+            {
+                Self::RateLimit
+            }
+            IndexerError::HttpClientError(_) | IndexerError::ArtifactChanged(_) => Self::Network,
+            IndexerError::DeserializeError(_)
+            | IndexerError::JsonError(_)
+            | IndexerError::InvalidBase64(_)
+            | IndexerError::InvalidArchive(_)
+            | IndexerError::InvalidXml(_)
+            | IndexerError::IncompleteData(_) => Self::Parse,
+            IndexerError::HashMismatch(_) | IndexerError::HashDrift(_) => Self::HashMismatch,
+            IndexerError::DatabaseError(_) | IndexerError::ReadOnly(_) => Self::Database,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::RateLimit => "rate-limit",
+            Self::Parse => "parse",
+            Self::HashMismatch => "hash-mismatch",
+            Self::Database => "database",
+            Self::Other => "other",
+        }
+    }
+
+    /// The exit code of a sync failing because of this category.
+    pub fn exit_code(self) -> i32 {
+        10 + self as i32
+    }
 }
 
 #[derive(Debug)]
 pub struct StatisticsCollector {
     successful_tasks: usize,
+    cancelled_tasks: usize,
     problems: Vec<ProblemReport>,
     failures: Vec<ErrorReport>,
+    timings: BTreeMap<TimingCategory, Vec<Duration>>,
     sender: UnboundedSender<TaskReport>,
     receiver: UnboundedReceiver<TaskReport>,
 }
@@ -23,8 +310,10 @@ impl StatisticsCollector {
 
         Self {
             successful_tasks: 0,
+            cancelled_tasks: 0,
             problems: Vec::new(),
             failures: Vec::new(),
+            timings: BTreeMap::new(),
             sender,
             receiver,
         }
@@ -37,6 +326,11 @@ impl StatisticsCollector {
         }
     }
 
+    /// Count tasks which were given up on without reporting an outcome.
+    pub fn record_abandoned(&mut self, count: usize) {
+        self.cancelled_tasks += count;
+    }
+
     /// Run the collector.
     ///
     /// This never returns.
@@ -50,49 +344,90 @@ impl StatisticsCollector {
             }
 
             for report in buffer.drain(..received) {
-                match report.data {
-                    TaskDataPoint::Succeeded => self.successful_tasks += 1,
-                    TaskDataPoint::Failed(err) => {
-                        tracing::error!("Task failed: {}: {}", report.name, err);
-
-                        let mut src = err.source();
-                        while let Some(err) = src {
-                            tracing::error!("-> Caused by: {}", err);
-                            src = err.source();
-                        }
-
-                        self.failures.push(ErrorReport {
-                            task_name: report.name,
-                            error: err,
-                        })
-                    },
-                    TaskDataPoint::EncounteredProblem(err) => {
-                        tracing::warn!("Task encountered a problem: {}: {}", report.name, err);
-
-                        let mut src = err.source();
-                        while let Some(err) = src {
-                            tracing::error!("-> Caused by: {}", err);
-                            src = err.source();
-                        }
-
-                        self.problems.push(ProblemReport {
-                            task_name: report.name,
-                            error: err,
-                        })
-                    },
-                }
+                self.record(report);
+            }
+        }
+    }
+
+    fn record(&mut self, report: TaskReport) {
+        match report.data {
+            TaskDataPoint::Succeeded => self.successful_tasks += 1,
+            TaskDataPoint::Timing(category, duration) => {
+                self.timings.entry(category).or_default().push(duration);
+            }
+            TaskDataPoint::Failed(err)
+                if matches!(err.downcast_ref(), Some(IndexerError::ShuttingDown)) =>
+            {
+                tracing::debug!("Task cancelled: {}", report.name);
+                self.cancelled_tasks += 1;
             }
+            TaskDataPoint::Failed(err) => {
+                let mut error_chain = Vec::new();
+                let mut src = err.source();
+                while let Some(err) = src {
+                    error_chain.push(err.to_string());
+                    src = err.source();
+                }
+
+                tracing::error!(
+                    task = report.name.as_str(),
+                    plugin = report.subject.plugin.as_deref(),
+                    update_id = report.subject.update_id,
+                    error_chain = error_chain.join(" -> "),
+                    "Task failed: {}",
+                    err
+                );
+
+                self.failures.push(ErrorReport {
+                    task_name: report.name,
+                    category: FailureCategory::of(err.as_ref()),
+                    error: err,
+                })
+            },
+            TaskDataPoint::EncounteredProblem(err) => {
+                tracing::warn!("Task encountered a problem: {}: {}", report.name, err);
+
+                let mut src = err.source();
+                while let Some(err) = src {
+                    tracing::error!("-> Caused by: {}", err);
+                    src = err.source();
+                }
+
+                self.problems.push(ProblemReport {
+                    task_name: report.name,
+                    error: err,
+                })
+            },
         }
     }
 
+    /// Record the reports which were sent but not received yet.
+    fn drain(&mut self) {
+        while let Ok(report) = self.receiver.try_recv() {
+            self.record(report);
+        }
+    }
+
+    /// Drop failures which are about to be retried.
+    pub fn forget_failures(&mut self, mut filter: impl FnMut(&ErrorReport) -> bool) {
+        self.drain();
+        self.failures.retain(|v| !filter(v));
+    }
+
     pub fn reset(&mut self) -> Statistics {
+        self.drain();
+
         let stats = Statistics {
             successful_tasks: self.successful_tasks,
+            cancelled_tasks: self.cancelled_tasks,
+            interrupted: false,
             problems: std::mem::take(&mut self.problems),
             failures: std::mem::take(&mut self.failures),
+            timings: std::mem::take(&mut self.timings),
         };
 
         self.successful_tasks = 0;
+        self.cancelled_tasks = 0;
 
         stats
     }
@@ -107,35 +442,30 @@ pub struct ProblemReport {
 #[derive(Debug)]
 pub struct ErrorReport {
     pub task_name: String,
+    pub category: FailureCategory,
     pub error: Box<dyn std::error::Error + Send + 'static>,
 }
 
 #[derive(Debug)]
 pub struct TaskReport {
     name: String,
+    subject: TaskSubject,
     data: TaskDataPoint,
 }
 
+/// What a task works on, logged with its failure.
+#[derive(Debug, Clone, Default)]
+pub struct TaskSubject {
+    pub plugin: Option<String>,
+    pub update_id: Option<u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct StatisticsSender {
     sender: UnboundedSender<TaskReport>,
 }
 
 impl StatisticsSender {
-    pub fn send_succeeded(&self, name: String) {
-        let _ = self.sender.send(TaskReport {
-            name,
-            data: TaskDataPoint::Succeeded,
-        });
-    }
-
-    pub fn send_failed(&self, name: String, error: Box<dyn std::error::Error + Send + 'static>) {
-        let _ = self.sender.send(TaskReport {
-            name,
-            data: TaskDataPoint::Failed(error),
-        });
-    }
-
     pub fn send_problem(
         &self,
         name: impl Into<String>,
@@ -143,13 +473,32 @@ impl StatisticsSender {
     ) {
         let _ = self.sender.send(TaskReport {
             name: name.into(),
+            subject: TaskSubject::default(),
             data: TaskDataPoint::EncounteredProblem(error),
         });
     }
 
+    /// Record how long an operation of a category took.
+    pub fn send_timing(&self, category: TimingCategory, duration: Duration) {
+        let _ = self.sender.send(TaskReport {
+            name: category.name().to_owned(),
+            subject: TaskSubject::default(),
+            data: TaskDataPoint::Timing(category, duration),
+        });
+    }
+
+    /// Run a future, recording how long it took.
+    pub async fn timed<F: Future>(&self, category: TimingCategory, future: F) -> F::Output {
+        let started = Instant::now();
+        let output = future.await;
+        self.send_timing(category, started.elapsed());
+        output
+    }
+
     pub fn guard_future<F, E>(
         &self,
         name: impl Into<String>,
+        subject: TaskSubject,
         future: F,
     ) -> impl Future<Output = ()> + 'static
     where
@@ -163,10 +512,12 @@ impl StatisticsSender {
             let _ = match future.await {
                 Ok(()) => sender.send(TaskReport {
                     name,
+                    subject,
                     data: TaskDataPoint::Succeeded,
                 }),
                 Err(err) => sender.send(TaskReport {
                     name,
+                    subject,
                     data: TaskDataPoint::Failed(Box::new(err)),
                 }),
             };
@@ -177,6 +528,7 @@ impl StatisticsSender {
 #[derive(Debug)]
 enum TaskDataPoint {
     Succeeded,
+    Timing(TimingCategory, Duration),
     Failed(Box<dyn std::error::Error + Send + 'static>),
     EncounteredProblem(Box<dyn std::error::Error + Send + 'static>),
 }
@@ -25,17 +25,29 @@ in rec {
     else
       binaryMerge 0 (builtins.length list);
 
+  # Load the metadata of a plugin, following the chain of `next` pages
+  # large plugins split their versions into
   loadPlugin = metadata: let
     plugin = builtins.fromJSON (builtins.readFile metadata);
+    pluginDir = builtins.dirOf metadata;
+
+    loadPages = next: if next == null then { } else let
+      page = builtins.fromJSON (builtins.readFile /${pluginDir}/${next});
+    in page.versions // loadPages (page.next or null);
   in
-    plugin;
+    plugin // {
+      versions = plugin.versions // loadPages (plugin.next or null);
+    };
 
   # Load the data from the dataRoot directory
   loadData = dataRoot: let
     indexFile = /${dataRoot}/index.json;
     index = builtins.fromJSON (builtins.readFile indexFile);
   in
-    lib.attrsets.mapAttrs (_: hash: let
+    lib.attrsets.mapAttrs (_: entry: let
+      # Older indices map straight to the digest
+      hash = if builtins.isString entry then entry else entry.digest;
+
       # Split the hash into aa/bb/cc[...]
       hashFirst = builtins.substring 0 2 hash;
       hashSecond = builtins.substring 2 2 hash;
@@ -35,13 +35,15 @@ rec {
   maybeUnpackPlugin = doUnpack: file: fileName: if doUnpack then unpackPlugin file fileName else file;
 
   createSinglePluginPackage = data: selectedVersion:
-  let
+  if (data.removed or { }) ? ${selectedVersion} then
+    throw "Version ${selectedVersion} of plugin ${data.xml_id} has been removed upstream, please select another version via versions.<version> or channels.<channel>"
+  else let
     versionData = data.versions.${selectedVersion};
-    fileName = versionData.file_name or "${data.name}-${selectedVersion}.jar";
+    fileName = versionData.file_name or "${data.meta.name or data.xml_id}-${selectedVersion}.jar";
   in pkgs.callPackage ({
     name ? "jetbrains-plugin-${data.xml_id}",
     version ? selectedVersion,
-    sha256 ? versionData.sha256,
+    hash ? versionData.hash,
     downloadUrl ? versionData.download_url,
     unpack ? lib.strings.hasSuffix ".zip" fileName,
     fetchAsExecutable ? lib.strings.hasSuffix ".jar" fileName,
@@ -52,15 +54,24 @@ rec {
 
     # Download the plugin file
     src = maybeUnpackPlugin unpack (pkgs.fetchurl {
-      url = downloadUrl;
+      # The mirror URLs are tried in order if the download fails
+      urls = [ downloadUrl ] ++ (versionData.mirror_urls or [ ]);
       executable = fetchAsExecutable;
-      inherit sha256;
+      inherit hash;
     }) fileName;
 
     passthru = {
       rawData = data;
     };
 
+    meta = lib.attrsets.filterAttrs (_: v: v != null) {
+      description = data.meta.description or null;
+      homepage = data.meta.homepage or null;
+
+      # Lets paid plugins be rejected unless unfree packages are allowed
+      license = if (data.license.free or true) then null else lib.licenses.unfree;
+    };
+
     installPhase = ''
       runHook preInstall
       mkdir -p $out && cp -r . $out
@@ -71,7 +82,7 @@ rec {
   createAllPluginPackages = data: let
     versions = lib.attrsets.mapAttrs (version: _:
       createSinglePluginPackage data version
-    ) data.versions;
+    ) ((data.removed or { }) // data.versions);
 
     channels = lib.attrsets.mapAttrs (channel: version: versions.${version}) data.latest;
     latest = if channels ? stable
@@ -88,8 +99,27 @@ rec {
         meta = err;
         system = err;
       };
+
+    # The branch of a build number like `IU-233.11799.241`, `241.*` or `233`
+    buildBranch = build: lib.toInt (builtins.head (lib.strings.splitString "."
+      (lib.lists.last (lib.strings.splitString "-" (toString build)))));
+
+    # The highest version of a channel compatible with an IDE build, the
+    # metadata only lists the branches at which the answer changes
+    latestForBuild = channel: build: let
+      branch = buildBranch build;
+      branches = data.latest_for_build.${channel} or { };
+      below = builtins.filter (v: v <= branch) (map lib.toInt (builtins.attrNames branches));
+      version = branches.${toString (lib.lists.foldl' lib.max 0 below)};
+      versionData = data.versions.${version};
+      supported = (versionData.since or null == null || buildBranch versionData.since <= branch)
+        && (versionData.until or null == null || buildBranch versionData.until >= branch);
+    in if below != [ ] && supported
+      then versions.${version}
+      else throw "No version of ${data.xml_id} in channel ${channel} is compatible with build ${toString build}";
   in latest // {
     inherit channels;
     inherit versions;
+    inherit latestForBuild;
   };
 }
\ No newline at end of file